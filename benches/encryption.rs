@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use euler_cryptor::crypto::KeyPair;
+use rand::Rng;
+
+const INPUT_SIZES: [usize; 3] = [16 * 1024, 64 * 1024, 256 * 1024];
+
+fn random_bytes(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; size];
+    rand::thread_rng().fill(&mut bytes[..]);
+    bytes
+}
+
+/*
+ * "prepared" builds the key arithmetic once for the whole input, "per-chunk" is the baseline which builds it again
+ * for every chunk, as before the keys could be prepared
+ */
+fn encryption_benchmark(c: &mut Criterion) {
+    let KeyPair { public_key, private_key } = KeyPair::generate(2048).unwrap();
+    let prepared_public_key = public_key.prepare();
+    let prepared_private_key = private_key.prepare();
+    let mut group = c.benchmark_group("rsa-2048");
+    group.sample_size(10);
+    for input_size in INPUT_SIZES {
+        let input = random_bytes(input_size);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        group.throughput(Throughput::Bytes(input_size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt/prepared", input_size), &input, |b, input| {
+            b.iter(|| prepared_public_key.encrypt_bytes(input))
+        });
+        group.bench_with_input(BenchmarkId::new("encrypt/per-chunk", input_size), &input, |b, input| {
+            b.iter(|| {
+                input.chunks(public_key.encryption_chunk_size()).map(|chunk| public_key.encrypt_bytes(chunk)).collect::<Vec<_>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt/prepared", input_size), &encrypted, |b, encrypted| {
+            b.iter(|| prepared_private_key.decrypt_bytes(encrypted))
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt/per-chunk", input_size), &encrypted, |b, encrypted| {
+            b.iter(|| {
+                encrypted.chunks(private_key.decryption_chunk_size()).map(|chunk| private_key.decrypt_bytes(chunk)).collect::<Vec<_>>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encryption_benchmark);
+criterion_main!(benches);
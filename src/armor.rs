@@ -0,0 +1,212 @@
+use base64::prelude::*;
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
+use zeroize::Zeroizing;
+use crate::Error;
+use crate::pem;
+
+/*
+ * PEM-style text encoding of encrypted data, so that it can be pasted where binary data breaks.
+ * Data is encoded and decoded line by line, without buffering the whole message
+ */
+pub const ARMOR_LABEL: &str = "EULER ENCRYPTED MESSAGE";
+
+// Number of lines encoded at once
+const LINES_PER_WRITE: usize = 64;
+
+// Armored data starts with the PEM header, binary data with the container magic number
+pub fn is_armored(prefix: &[u8]) -> bool {
+    let begin_line = pem::begin_line(ARMOR_LABEL);
+    let compared_size = cmp::min(prefix.len(), begin_line.len());
+    compared_size > 0 && prefix[..compared_size] == begin_line.as_bytes()[..compared_size]
+}
+
+/*
+ * Writes the header line when created and the footer line by finish, or when the writer is dropped,
+ * in which case errors are ignored
+ */
+pub struct ArmorWriter<W: Write> {
+    inner: Option<W>,
+    // Data which does not fill a whole line yet
+    buffer: Zeroizing<Vec<u8>>
+}
+
+impl<W: Write> ArmorWriter<W> {
+
+    pub fn new(mut inner: W) -> Result<ArmorWriter<W>, Error> {
+        inner.write_all(pem::begin_line(ARMOR_LABEL).as_bytes())?;
+        Ok(ArmorWriter {
+            inner: Some(inner),
+            buffer: Zeroizing::new(Vec::with_capacity(pem::LINE_DATA_SIZE * LINES_PER_WRITE))
+        })
+    }
+
+    // Writes the last line and the footer and returns the inner writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_footer()?;
+        let mut inner = self.inner.take().expect("Inner writer is only taken by finish");
+        inner.flush()?;
+        Ok(inner)
+    }
+
+    fn write_lines(&mut self, size: usize) -> io::Result<()> {
+        let encoded_lines = pem::encode_lines(&self.buffer[..size]);
+        self.buffer.drain(..size);
+        match self.inner.as_mut() {
+            Some(inner) => inner.write_all(encoded_lines.as_bytes()),
+            None => Ok(())
+        }
+    }
+
+    fn write_footer(&mut self) -> Result<(), Error> {
+        self.write_lines(self.buffer.len())?;
+        if let Some(inner) = self.inner.as_mut() {
+            inner.write_all(pem::end_line(ARMOR_LABEL).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ArmorWriter<W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let batch_size = pem::LINE_DATA_SIZE * LINES_PER_WRITE;
+        let written_size = cmp::min(data.len(), batch_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..written_size]);
+        if self.buffer.len() == batch_size {
+            self.write_lines(batch_size)?;
+        }
+        Ok(written_size)
+    }
+
+    // Only whole lines are written, so flushing does not write the buffered data
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+impl<W: Write> Drop for ArmorWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_footer();
+        }
+    }
+}
+
+/*
+ * Reads armored data and returns it decoded. Lines can have any length and end with \n or \r\n,
+ * a missing footer is reported as an error
+ */
+pub struct ArmorReader<R: BufRead> {
+    inner: R,
+    // Characters which do not form a whole base64 quantum yet
+    encoded: String,
+    decoded: Zeroizing<Vec<u8>>,
+    position: usize,
+    has_finished: bool
+}
+
+impl<R: BufRead> ArmorReader<R> {
+
+    pub fn new(mut inner: R) -> Result<ArmorReader<R>, Error> {
+        let mut header = String::new();
+        inner.read_line(&mut header)?;
+        if header.trim_end() != pem::begin_line(ARMOR_LABEL).trim_end() {
+            return Err(Error::Pem(format!("Expected header of {}", ARMOR_LABEL)));
+        }
+        Ok(ArmorReader {
+            inner,
+            encoded: String::new(),
+            decoded: Zeroizing::new(Vec::new()),
+            position: 0,
+            has_finished: false
+        })
+    }
+
+    fn decode_next_line(&mut self) -> Result<(), Error> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(Error::Pem("Could not find footer".to_string()));
+        }
+        let line = line.trim_end();
+        let encoded_size = if line.starts_with("-----END ") {
+            if line != pem::end_line(ARMOR_LABEL).trim_end() {
+                return Err(Error::Pem(format!("Expected footer of {}", ARMOR_LABEL)));
+            }
+            self.has_finished = true;
+            self.encoded.len()
+        } else {
+            self.encoded.push_str(line);
+            self.encoded.len() / 4 * 4
+        };
+        let decoded = BASE64_STANDARD.decode(&self.encoded[..encoded_size]).map_err(|err| Error::Pem(err.to_string()))?;
+        self.encoded.drain(..encoded_size);
+        self.decoded = Zeroizing::new(decoded);
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ArmorReader<R> {
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            if self.has_finished {
+                return Ok(0);
+            }
+            self.decode_next_line()?;
+        }
+        let read_size = cmp::min(data.len(), self.decoded.len() - self.position);
+        data[..read_size].copy_from_slice(&self.decoded[self.position..self.position + read_size]);
+        self.position += read_size;
+        Ok(read_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::get_random_bytes;
+
+    fn armor(data: &[u8]) -> Vec<u8> {
+        let mut writer = ArmorWriter::new(Vec::new()).unwrap();
+        for part in data.chunks(100) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn should_armor_same_as_pem_block() {
+        for size in [0, 1, 48, 1000, 10000] {
+            let data = get_random_bytes(size);
+            assert_eq!(armor(&data), pem::serialize_block(&data, ARMOR_LABEL));
+        }
+    }
+
+    #[test]
+    fn should_read_armored_data() {
+        let data = get_random_bytes(10000);
+        let armored = armor(&data);
+        assert!(is_armored(&armored[..8]));
+        let mut decoded = Vec::new();
+        ArmorReader::new(armored.as_slice()).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        let with_carriage_returns = String::from_utf8(armored).unwrap().replace('\n', "\r\n");
+        let mut decoded = Vec::new();
+        ArmorReader::new(with_carriage_returns.as_bytes()).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn should_reject_armored_data_without_footer() {
+        let armored = armor(&get_random_bytes(1000));
+        let truncated = &armored[..armored.len() - 20];
+        let error = ArmorReader::new(truncated).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(error), Error::Pem(_)));
+        assert!(!is_armored(b"EULERCRY"));
+    }
+}
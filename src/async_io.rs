@@ -0,0 +1,263 @@
+#![allow(deprecated)]
+
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::{self, JoinHandle};
+use zeroize::Zeroizing;
+use crate::Error;
+use crate::crypto::{RsaPublicKey, RsaPrivateKey};
+
+// Number of RSA blocks encrypted or decrypted by a single blocking task, amortizes the cost of spawning the task
+const BLOCKS_PER_TASK: usize = 16;
+
+/*
+ * Asynchronous counterpart of io::EncryptingWriter producing the same unauthenticated encrypted data.
+ * Chunks are encrypted on the blocking thread pool of tokio, so the exponentiations never block the reactor,
+ * the writer has to be used from within a tokio runtime.
+ * Buffered data which does not fill a whole chunk is only encrypted by shutdown
+ */
+#[deprecated(note = "Unauthenticated like io::EncryptingWriter, use io::encrypt_for on a blocking task instead")]
+pub struct AsyncEncryptingWriter<W: AsyncWrite + Unpin> {
+    inner: W,
+    key: Arc<RsaPublicKey>,
+    chunk_size: usize,
+    // Plaintext which does not fill a whole chunk yet
+    buffer: Zeroizing<Vec<u8>>,
+    state: WriterState
+}
+
+enum WriterState {
+    Buffering,
+    Encrypting(JoinHandle<Result<Vec<u8>, Error>>),
+    Writing(Vec<u8>, usize)
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncryptingWriter<W> {
+
+    pub fn new(inner: W, key: Arc<RsaPublicKey>) -> AsyncEncryptingWriter<W> {
+        let chunk_size = key.encryption_chunk_size() * BLOCKS_PER_TASK;
+        AsyncEncryptingWriter::with_chunk_size(inner, key, chunk_size)
+    }
+
+    pub fn with_chunk_size(inner: W, key: Arc<RsaPublicKey>, chunk_size: usize) -> AsyncEncryptingWriter<W> {
+        let chunk_size = cmp::max(chunk_size, 1);
+        AsyncEncryptingWriter {
+            inner,
+            key,
+            chunk_size,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size)),
+            state: WriterState::Buffering
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Encrypts and writes the buffered data, partial chunks only when requested
+    fn poll_write_buffered(&mut self, cx: &mut Context<'_>, include_partial_chunk: bool) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                WriterState::Buffering => {
+                    if self.buffer.is_empty() || (!include_partial_chunk && self.buffer.len() < self.chunk_size) {
+                        return Poll::Ready(Ok(()));
+                    }
+                    let chunk = Zeroizing::new(self.buffer.to_vec());
+                    self.buffer.clear();
+                    let key = Arc::clone(&self.key);
+                    self.state = WriterState::Encrypting(task::spawn_blocking(move || key.encrypt_bytes(&chunk)));
+                },
+                WriterState::Encrypting(handle) => {
+                    let encrypted = ready!(Pin::new(handle).poll(cx))?.map_err(io::Error::from)?;
+                    self.state = WriterState::Writing(encrypted, 0);
+                },
+                WriterState::Writing(encrypted, position) => {
+                    while *position < encrypted.len() {
+                        let written_size = ready!(Pin::new(&mut self.inner).poll_write(cx, &encrypted[*position..]))?;
+                        if written_size == 0 {
+                            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                        }
+                        *position += written_size;
+                    }
+                    self.state = WriterState::Buffering;
+                }
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncEncryptingWriter<W> {
+
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_write_buffered(cx, false))?;
+        let written_size = cmp::min(data.len(), writer.chunk_size - writer.buffer.len());
+        writer.buffer.extend_from_slice(&data[..written_size]);
+        Poll::Ready(Ok(written_size))
+    }
+
+    // Chunks are only encrypted when full, so flushing does not write a partial chunk
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_write_buffered(cx, false))?;
+        Pin::new(&mut writer.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_write_buffered(cx, true))?;
+        Pin::new(&mut writer.inner).poll_shutdown(cx)
+    }
+}
+
+/*
+ * Asynchronous counterpart of io::DecryptingReader, chunks are decrypted on the blocking thread pool of tokio.
+ * Corrupted or truncated data is reported as an error of kind InvalidData, modified chunks which still decrypt to
+ * the expected prefix are not detected
+ */
+#[deprecated(note = "Unauthenticated like io::DecryptingReader, use io::decrypt_container on a blocking task instead")]
+pub struct AsyncDecryptingReader<R: AsyncRead + Unpin> {
+    inner: R,
+    key: Arc<RsaPrivateKey>,
+    chunk_size: usize,
+    state: ReaderState
+}
+
+enum ReaderState {
+    Reading(Vec<u8>, usize),
+    Decrypting(JoinHandle<Result<Vec<u8>, Error>>),
+    // Decrypted data which has not been read yet, starting at the position
+    Decrypted(Zeroizing<Vec<u8>>, usize),
+    Finished
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecryptingReader<R> {
+
+    pub fn new(inner: R, key: Arc<RsaPrivateKey>) -> AsyncDecryptingReader<R> {
+        let chunk_size = key.decryption_chunk_size() * BLOCKS_PER_TASK;
+        AsyncDecryptingReader::with_chunk_size(inner, key, chunk_size)
+    }
+
+    // Chunk size has to be a multiple of the decryption chunk size of the key
+    pub fn with_chunk_size(inner: R, key: Arc<RsaPrivateKey>, chunk_size: usize) -> AsyncDecryptingReader<R> {
+        let chunk_size = cmp::max(chunk_size, 1);
+        AsyncDecryptingReader {
+            inner,
+            key,
+            chunk_size,
+            state: ReaderState::Reading(vec![0u8; chunk_size], 0)
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecryptingReader<R> {
+
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let reader = self.get_mut();
+        loop {
+            match &mut reader.state {
+                ReaderState::Reading(encrypted, read_size) => {
+                    if *read_size < reader.chunk_size {
+                        let mut read_buffer = ReadBuf::new(&mut encrypted[*read_size..]);
+                        ready!(Pin::new(&mut reader.inner).poll_read(cx, &mut read_buffer))?;
+                        let new_size = read_buffer.filled().len();
+                        *read_size += new_size;
+                        let has_more_data = new_size > 0;
+                        if has_more_data {
+                            continue;
+                        }
+                        if *read_size == 0 {
+                            reader.state = ReaderState::Finished;
+                            continue;
+                        }
+                    }
+                    encrypted.truncate(*read_size);
+                    let (encrypted, key) = (std::mem::take(encrypted), Arc::clone(&reader.key));
+                    reader.state = ReaderState::Decrypting(task::spawn_blocking(move || key.decrypt_bytes(&encrypted)));
+                },
+                ReaderState::Decrypting(handle) => {
+                    let decrypted = ready!(Pin::new(handle).poll(cx))?.map_err(io::Error::from)?;
+                    reader.state = ReaderState::Decrypted(Zeroizing::new(decrypted), 0);
+                },
+                ReaderState::Decrypted(decrypted, position) => {
+                    if *position == decrypted.len() {
+                        reader.state = ReaderState::Reading(vec![0u8; reader.chunk_size], 0);
+                        continue;
+                    }
+                    let read_size = cmp::min(data.remaining(), decrypted.len() - *position);
+                    data.put_slice(&decrypted[*position..*position + read_size]);
+                    *position += read_size;
+                    return Poll::Ready(Ok(()));
+                },
+                ReaderState::Finished => return Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::crypto::KeyPair;
+    use crate::crypto::tests::{predefined_keys, get_random_bytes};
+
+    fn shared_keys() -> (Arc<RsaPublicKey>, Arc<RsaPrivateKey>) {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        (Arc::new(public_key), Arc::new(private_key))
+    }
+
+    #[tokio::test]
+    async fn should_encrypt_and_decrypt_through_duplex_stream() {
+        let (public_key, private_key) = shared_keys();
+        let input = get_random_bytes(10000);
+        let (client, server) = tokio::io::duplex(64);
+        let expected_input = input.clone();
+        let writing = tokio::spawn(async move {
+            let mut writer = AsyncEncryptingWriter::new(client, public_key);
+            for part in expected_input.chunks(13) {
+                writer.write_all(part).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        });
+        let mut decrypted = Vec::new();
+        AsyncDecryptingReader::new(server, private_key).read_to_end(&mut decrypted).await.unwrap();
+        writing.await.unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[tokio::test]
+    async fn should_produce_same_data_as_blocking_encryption() {
+        let (public_key, _) = shared_keys();
+        let input = get_random_bytes(1000);
+        let mut writer = AsyncEncryptingWriter::with_chunk_size(Vec::new(), Arc::clone(&public_key), 100);
+        writer.write_all(&input).await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.into_inner(), public_key.encrypt_bytes(&input).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_report_truncated_stream() {
+        let (public_key, private_key) = shared_keys();
+        let encrypted = public_key.encrypt_bytes(&get_random_bytes(100)).unwrap();
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(&encrypted[..encrypted.len() - 3]).await.unwrap();
+        drop(client);
+        let mut decrypted = Vec::new();
+        let error = AsyncDecryptingReader::new(server, private_key).read_to_end(&mut decrypted).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
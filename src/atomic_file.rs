@@ -0,0 +1,214 @@
+use rand::Rng;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::Error;
+
+/*
+ * File written under a temporary name in the directory of its path and moved to the path by commit, once its data
+ * has been synced to the disk, so that the path never holds partially written data. Nothing is left behind when the
+ * file is dropped without being committed, for example after an error.
+ * Symbolic links are followed, so that the file they point to is replaced and not the link. Devices, pipes and
+ * anything else which is not a regular file can not be replaced, they are written directly
+ */
+pub struct AtomicFile {
+    file: Option<File>,
+    // None when the data is written to the path directly
+    temporary_path: Option<PathBuf>,
+    path: PathBuf,
+    overwrite: bool
+}
+
+impl AtomicFile {
+
+    /*
+     * Permissions are the default ones for new files, an existing file at the path is replaced by commit
+     * and its permissions are kept
+     */
+    pub fn create(path: &Path) -> Result<AtomicFile, Error> {
+        AtomicFile::create_with_mode(path, 0o666, true)
+    }
+
+    // Only the owner can read and write the file, for private keys and anything revealing them
+    pub fn create_private(path: &Path) -> Result<AtomicFile, Error> {
+        AtomicFile::create_with_mode(path, 0o600, false)
+    }
+
+    // Commit fails instead of replacing an existing file, which is checked right away as well
+    pub fn without_overwriting(mut self) -> Result<AtomicFile, Error> {
+        if self.path.exists() {
+            return Err(already_exists(&self.path));
+        }
+        self.overwrite = false;
+        Ok(self)
+    }
+
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn create_with_mode(path: &Path, mode: u32, keep_permissions: bool) -> Result<AtomicFile, Error> {
+        // Fails when nothing exists at the path yet, a new file is then created at the path as it is
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let existing_metadata = fs::metadata(&path).ok();
+        if existing_metadata.as_ref().is_some_and(|metadata| !metadata.is_file()) {
+            let file = OpenOptions::new().write(true).truncate(true).open(&path)?;
+            return Ok(AtomicFile { file: Some(file), temporary_path: None, path, overwrite: true });
+        }
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new(".")
+        };
+        let file_name = path.file_name()
+            .ok_or(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display()))))?;
+        let temporary_path = directory.join(format!(".{}.{:016x}.tmp", file_name.to_string_lossy(), rand::thread_rng().gen::<u64>()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+        let file = options.open(&temporary_path)?;
+        let atomic_file = AtomicFile { file: Some(file), temporary_path: Some(temporary_path), path, overwrite: true };
+        if let Some(metadata) = existing_metadata.filter(|_| keep_permissions) {
+            atomic_file.file.as_ref().expect("File is only taken by commit").set_permissions(metadata.permissions())?;
+        }
+        Ok(atomic_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Syncs the data and moves the file to its path
+    pub fn commit(mut self) -> Result<(), Error> {
+        let file = self.file.take().expect("File is only taken by commit");
+        let Some(temporary_path) = self.temporary_path.take() else {
+            return Ok(());
+        };
+        file.sync_all()?;
+        drop(file);
+        let moved = if self.overwrite {
+            fs::rename(&temporary_path, &self.path)
+        } else {
+            // Unlike rename, linking fails when the path exists
+            fs::hard_link(&temporary_path, &self.path).and_then(|_| fs::remove_file(&temporary_path))
+        };
+        if let Err(error) = moved {
+            let _ = fs::remove_file(&temporary_path);
+            return Err(match error.kind() {
+                io::ErrorKind::AlreadyExists => already_exists(&self.path),
+                _ => Error::Io(error)
+            });
+        }
+        sync_directory_of(&self.path)
+    }
+}
+
+fn already_exists(path: &Path) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())))
+}
+
+// The rename itself is only durable once the directory is synced
+#[cfg(unix)]
+fn sync_directory_of(path: &Path) -> Result<(), Error> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new(".")
+    };
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_directory_of(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+impl Write for AtomicFile {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("File is only taken by commit").write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("File is only taken by commit").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if let (Some(_), Some(temporary_path)) = (self.file.take(), &self.temporary_path) {
+            let _ = fs::remove_file(temporary_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TemporaryDirectory;
+
+    #[test]
+    fn should_replace_file_only_when_committed() {
+        let directory = TemporaryDirectory::create();
+        let path = directory.path().join("output.bin");
+        fs::write(&path, vec![7u8; 1000]).unwrap();
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"short").unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 1000);
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"short");
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"dropped").unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"short");
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn should_not_overwrite_existing_file() {
+        let directory = TemporaryDirectory::create();
+        let path = directory.path().join("key_sec.pem");
+        let file = AtomicFile::create_private(&path).unwrap().without_overwriting().unwrap();
+        let other_file = AtomicFile::create_private(&path).unwrap().without_overwriting().unwrap();
+        file.commit().unwrap();
+        let error = other_file.commit().unwrap_err();
+        assert!(matches!(&error, Error::Io(error) if error.kind() == io::ErrorKind::AlreadyExists));
+        assert!(AtomicFile::create(&path).unwrap().without_overwriting().is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_replace_target_of_symbolic_link() {
+        let directory = TemporaryDirectory::create();
+        let target_path = directory.path().join("target.bin");
+        let link_path = directory.path().join("link.bin");
+        fs::write(&target_path, b"old").unwrap();
+        std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
+        let mut file = AtomicFile::create(&link_path).unwrap();
+        file.write_all(b"new").unwrap();
+        file.commit().unwrap();
+        assert!(fs::symlink_metadata(&link_path).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&target_path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_keep_permissions_of_replaced_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = TemporaryDirectory::create();
+        let path = directory.path().join("output.bin");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        let file = AtomicFile::create_private(&path).unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
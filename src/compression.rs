@@ -0,0 +1,168 @@
+use flate2::read::GzEncoder;
+use flate2::write::GzDecoder;
+use std::io::{self, BufReader, Read, Write};
+use zstd::stream::{raw, zio};
+use crate::Error;
+use crate::container::Compression;
+
+/*
+ * Compression of the payload before it is encrypted, encrypted data does not compress anymore.
+ * Compressing secret data together with data an attacker controls can reveal the secret through the length of
+ * the output (CRIME, BREACH), so compression is only used when requested
+ */
+pub(crate) enum CompressingReader<R: Read> {
+    None(R),
+    Gzip(GzEncoder<R>),
+    Zstd(zstd::stream::read::Encoder<'static, BufReader<R>>)
+}
+
+impl<R: Read> CompressingReader<R> {
+
+    pub(crate) fn new(inner: R, compression: Compression) -> Result<CompressingReader<R>, Error> {
+        Ok(match compression {
+            Compression::None => CompressingReader::None(inner),
+            Compression::Gzip => CompressingReader::Gzip(GzEncoder::new(inner, flate2::Compression::default())),
+            Compression::Zstd => CompressingReader::Zstd(zstd::stream::read::Encoder::new(inner, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+        })
+    }
+}
+
+impl<R: Read> Read for CompressingReader<R> {
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        match self {
+            CompressingReader::None(inner) => inner.read(data),
+            CompressingReader::Gzip(encoder) => encoder.read(data),
+            CompressingReader::Zstd(encoder) => encoder.read(data)
+        }
+    }
+}
+
+/*
+ * Decompresses everything written to it, the end of the compressed data is only checked by finish, which fails when
+ * the last gzip member or zstd frame is incomplete.
+ * A few bytes of compressed data can decompress to gigabytes, so the decompressed data can be limited to a maximal
+ * length, usually the original length stored in the header. Without a limit it is written out as it is decompressed,
+ * the memory used stays bounded but the output can be arbitrarily long
+ */
+pub(crate) enum DecompressingWriter<W: Write> {
+    None(LimitedWriter<W>),
+    Gzip(GzDecoder<LimitedWriter<W>>),
+    Zstd(zio::Writer<LimitedWriter<W>, raw::Decoder<'static>>)
+}
+
+impl<W: Write> DecompressingWriter<W> {
+
+    pub(crate) fn new(inner: W, compression: Compression, maximal_length: Option<u64>) -> Result<DecompressingWriter<W>, Error> {
+        let inner = LimitedWriter { inner, remaining: maximal_length };
+        Ok(match compression {
+            Compression::None => DecompressingWriter::None(inner),
+            Compression::Gzip => DecompressingWriter::Gzip(GzDecoder::new(inner)),
+            Compression::Zstd => DecompressingWriter::Zstd(zio::Writer::new(inner, raw::Decoder::new()?))
+        })
+    }
+
+    pub(crate) fn finish(self) -> Result<W, Error> {
+        let mut inner = match self {
+            DecompressingWriter::None(inner) => inner,
+            DecompressingWriter::Gzip(decoder) => decoder.finish()?,
+            DecompressingWriter::Zstd(mut decoder) => {
+                // Unlike flush, finish checks that the frame is complete
+                decoder.finish()?;
+                decoder.into_inner().0
+            }
+        };
+        inner.flush()?;
+        Ok(inner.inner)
+    }
+}
+
+impl<W: Write> Write for DecompressingWriter<W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            DecompressingWriter::None(inner) => inner.write(data),
+            DecompressingWriter::Gzip(decoder) => decoder.write(data),
+            DecompressingWriter::Zstd(decoder) => decoder.write(data)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DecompressingWriter::None(inner) => inner.flush(),
+            DecompressingWriter::Gzip(decoder) => decoder.flush(),
+            DecompressingWriter::Zstd(decoder) => decoder.flush()
+        }
+    }
+}
+
+// Fails when more than the remaining number of bytes is written
+pub(crate) struct LimitedWriter<W: Write> {
+    inner: W,
+    remaining: Option<u64>
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(remaining) = self.remaining {
+            if data.len() as u64 > remaining {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Decompressed data is longer than the original data"));
+            }
+        }
+        let written_size = self.inner.write(data)?;
+        self.remaining = self.remaining.map(|remaining| remaining - written_size as u64);
+        Ok(written_size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::get_random_bytes;
+
+    #[test]
+    fn should_compress_and_decompress() {
+        let mut input = get_random_bytes(1000);
+        input.extend_from_slice(&[b'a'; 100000]);
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut compressed = Vec::new();
+            CompressingReader::new(input.as_slice(), compression).unwrap().read_to_end(&mut compressed).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < input.len() / 10);
+            }
+            let mut decompressing_writer = DecompressingWriter::new(Vec::new(), compression, Some(input.len() as u64)).unwrap();
+            for part in compressed.chunks(1000) {
+                decompressing_writer.write_all(part).unwrap();
+            }
+            assert_eq!(decompressing_writer.finish().unwrap(), input);
+        }
+    }
+    #[test]
+    fn should_reject_truncated_compressed_data() {
+        let input = get_random_bytes(10000);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut compressed = Vec::new();
+            CompressingReader::new(input.as_slice(), compression).unwrap().read_to_end(&mut compressed).unwrap();
+            let mut decompressing_writer = DecompressingWriter::new(Vec::new(), compression, None).unwrap();
+            decompressing_writer.write_all(&compressed[..compressed.len() - 4]).unwrap();
+            assert!(decompressing_writer.finish().is_err());
+        }
+    }
+
+    #[test]
+    fn should_limit_decompressed_length() {
+        let input = [b'a'; 100000];
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut compressed = Vec::new();
+            CompressingReader::new(input.as_slice(), compression).unwrap().read_to_end(&mut compressed).unwrap();
+            let mut decompressing_writer = DecompressingWriter::new(Vec::new(), compression, Some(input.len() as u64 - 1)).unwrap();
+            let result = decompressing_writer.write_all(&compressed).and_then(|_| decompressing_writer.flush());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
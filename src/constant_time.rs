@@ -0,0 +1,591 @@
+use num_bigint::{BigInt, BigUint, Sign};
+use std::cmp;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::secret::SecretBigInt;
+
+/*
+ * Montgomery arithmetic on fixed-width numbers stored as little-endian u64 limbs.
+ *
+ * Unlike modulo_arithmetic, which works on variable-time BigInt, every operation here executes the same sequence of
+ * instructions and memory accesses regardless of the values: there are no branches on secret data, the final
+ * subtraction of REDC is a masked select and the exponentiation uses a fixed window with a table lookup that reads
+ * every entry. Numbers are reduced modulo n with Montgomery multiplications as well, never with a BigInt division.
+ * It is used for all the operations involving the private exponent and the secret primes, so the modulus and all the
+ * intermediate limbs are overwritten with zeros when they are no longer needed
+ */
+const LIMB_BITS: usize = 64;
+const WINDOW_SIZE: usize = 4;
+
+pub(crate) struct ConstantTimeModulus {
+    n: Vec<u64>,
+    // -n ^ (-1) mod 2 ^ 64
+    n_prime: u64,
+    // R ^ 2 mod n where R = 2 ^ (64 * limbs)
+    r_squared: Vec<u64>,
+    // 1 in Montgomery form: R mod n
+    one: Vec<u64>
+}
+
+impl Drop for ConstantTimeModulus {
+    fn drop(&mut self) {
+        self.n.zeroize();
+        self.r_squared.zeroize();
+        self.one.zeroize();
+    }
+}
+
+impl ConstantTimeModulus {
+
+    // n should be odd
+    pub(crate) fn for_modulo(n: &BigInt) -> ConstantTimeModulus {
+        ConstantTimeModulus::from_limbs(to_limbs(n, 0))
+    }
+
+    // Same as for_modulo, for one of the secret primes
+    pub(crate) fn for_secret_modulo(n: &SecretBigInt) -> ConstantTimeModulus {
+        ConstantTimeModulus::from_limbs(n.limbs().to_vec())
+    }
+
+    fn from_limbs(n: Vec<u64>) -> ConstantTimeModulus {
+        let limbs = n.len();
+        let n_prime = inverse_modulo_word(n[0]).wrapping_neg();
+        let mut one = vec![0u64; limbs];
+        one[0] = 1;
+        // Doubling 1 modulo n 2 * 64 * limbs times gives R ^ 2 mod n without a variable-time division
+        let mut r_squared = one.clone();
+        for _ in 0..2 * LIMB_BITS * limbs {
+            let doubled = add_modulo(&r_squared, &r_squared, &n);
+            r_squared.zeroize();
+            r_squared = doubled;
+        }
+        let mut modulus = ConstantTimeModulus {
+            n,
+            n_prime,
+            r_squared,
+            one: Vec::new()
+        };
+        modulus.one = modulus.to_montgomery_form(&one);
+        modulus
+    }
+
+    pub(crate) fn exponent(&self, number: &BigInt, power: &BigInt) -> BigInt {
+        let number_limbs = Zeroizing::new(self.reduce(number));
+        let power_limbs = Zeroizing::new(to_limbs(power, self.n.len()));
+        let result = Zeroizing::new(self.exponent_limbs(&number_limbs, &power_limbs));
+        self.to_bigint(&result)
+    }
+
+    pub(crate) fn multiply(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        let a = Zeroizing::new(self.reduce(a));
+        let b = Zeroizing::new(self.reduce(b));
+        let result = Zeroizing::new(self.multiply_limbs(&a, &b));
+        self.to_bigint(&result)
+    }
+
+    /*
+     * Same operations for secret numbers, which never leave their limbs and are never converted to BigInt.
+     * The time of the exponentiation only depends on the length of the modulo, powers are padded to it
+     */
+    pub(crate) fn secret_exponent(&self, number: &SecretBigInt, power: &SecretBigInt) -> SecretBigInt {
+        let number_limbs = Zeroizing::new(self.reduce_limbs(number.limbs()));
+        let power_limbs = padded(power.limbs(), self.n.len());
+        SecretBigInt::from_limbs(self.exponent_limbs(&number_limbs, &power_limbs))
+    }
+
+    // Only hides the number, the time depends on the length of the power
+    pub(crate) fn secret_exponent_with_public_power(&self, number: &SecretBigInt, power: &BigInt) -> SecretBigInt {
+        let number_limbs = Zeroizing::new(self.reduce_limbs(number.limbs()));
+        SecretBigInt::from_limbs(self.exponent_limbs(&number_limbs, &to_limbs(power, 1)))
+    }
+
+    pub(crate) fn secret_modulo(&self, number: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(self.reduce_limbs(number.limbs()))
+    }
+
+    pub(crate) fn secret_multiply(&self, a: &SecretBigInt, b: &SecretBigInt) -> SecretBigInt {
+        let a = Zeroizing::new(self.reduce_limbs(a.limbs()));
+        let b = Zeroizing::new(self.reduce_limbs(b.limbs()));
+        SecretBigInt::from_limbs(self.multiply_limbs(&a, &b))
+    }
+
+    // a - b mod n
+    pub(crate) fn secret_subtract(&self, a: &SecretBigInt, b: &SecretBigInt) -> SecretBigInt {
+        let a = Zeroizing::new(self.reduce_limbs(a.limbs()));
+        let b = Zeroizing::new(self.reduce_limbs(b.limbs()));
+        SecretBigInt::from_limbs(subtract_modulo(&a, &b, &self.n))
+    }
+
+    // None when the number is not coprime with n
+    pub(crate) fn secret_inverse(&self, number: &SecretBigInt) -> Option<SecretBigInt> {
+        let (gcd, inverse) = self.gcd_and_inverse(number.limbs());
+        let inverse = SecretBigInt::from_limbs(inverse);
+        let mut one = vec![0u64; self.n.len()];
+        one[0] = 1;
+        bool::from(gcd.as_slice().ct_eq(&one)).then_some(inverse)
+    }
+
+    pub(crate) fn secret_gcd(&self, number: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(self.gcd_and_inverse(number.limbs()).0.to_vec())
+    }
+
+    // a * b mod n, with the product computed in Montgomery form: (a * b * R ^ (-1)) * R ^ 2 * R ^ (-1)
+    fn multiply_limbs(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let product = Zeroizing::new(self.montgomery_multiply(a, b));
+        self.montgomery_multiply(&product, &self.r_squared)
+    }
+
+    /*
+     * Binary extended Euclidean algorithm with a fixed number of steps and masked swaps and subtractions.
+     * x = u * number and y = v * number (mod n) hold after every step, each step halves x and the sum of the lengths of
+     * x and y decreases by at least one bit, so after 2 * bits(n) steps x is 0, y is gcd(number, n) and v is the inverse
+     * of number when the gcd is 1
+     */
+    fn gcd_and_inverse(&self, number: &[u64]) -> (Zeroizing<Vec<u64>>, Vec<u64>) {
+        let limbs = self.n.len();
+        let mut x = Zeroizing::new(self.reduce_limbs(number));
+        let mut y = Zeroizing::new(self.n.clone());
+        let mut u = Zeroizing::new(vec![0u64; limbs]);
+        u[0] = 1;
+        let mut v = Zeroizing::new(vec![0u64; limbs]);
+        for _ in 0..2 * LIMB_BITS * limbs {
+            let x_is_odd = Choice::from((x[0] & 1) as u8);
+            let (mut difference, borrow) = subtract(&x, &y);
+            difference.zeroize();
+            let x_is_smaller = x_is_odd & Choice::from(borrow as u8);
+            conditional_swap(&mut x, &mut y, x_is_smaller);
+            conditional_swap(&mut u, &mut v, x_is_smaller);
+            let difference = Zeroizing::new(subtract(&x, &y).0);
+            x = Zeroizing::new(select(&x, &difference, x_is_odd));
+            let u_minus_v = Zeroizing::new(subtract_modulo(&u, &v, &self.n));
+            u = Zeroizing::new(select(&u, &u_minus_v, x_is_odd));
+            shift_right_one(&mut x, 0);
+            u = Zeroizing::new(half_modulo(&u, &self.n));
+        }
+        (y, v.to_vec())
+    }
+
+    fn reduce(&self, number: &BigInt) -> Vec<u64> {
+        let number_limbs = Zeroizing::new(to_limbs(number, 0));
+        self.reduce_limbs(&number_limbs)
+    }
+
+    /*
+     * number mod n for a number of any length: number = x_0 + x_1 * R + x_2 * R ^ 2 + ... with blocks x_i < R of as many
+     * limbs as n, and the Montgomery multiplication of x_i by R ^ (i + 1) mod n gives x_i * R ^ i mod n
+     */
+    fn reduce_limbs(&self, number_limbs: &[u64]) -> Vec<u64> {
+        let limbs = self.n.len();
+        let mut result = vec![0u64; limbs];
+        let mut power_of_r = Zeroizing::new(self.one.clone());
+        for block in number_limbs.chunks(limbs) {
+            let mut block = Zeroizing::new(block.to_vec());
+            block.resize(limbs, 0);
+            let term = Zeroizing::new(self.montgomery_multiply(&block, &power_of_r));
+            let sum = add_modulo(&result, &term, &self.n);
+            result.zeroize();
+            result = sum;
+            power_of_r = Zeroizing::new(self.montgomery_multiply(&power_of_r, &self.r_squared));
+        }
+        result
+    }
+
+    // Fixed window exponentiation, the number of squarings and multiplications only depends on the length of power
+    fn exponent_limbs(&self, number: &[u64], power: &[u64]) -> Vec<u64> {
+        let number_in_montgomery_form = Zeroizing::new(self.to_montgomery_form(number));
+        let mut table = Zeroizing::new(vec![self.one.clone()]);
+        for i in 1..(1 << WINDOW_SIZE) {
+            let next_power = self.montgomery_multiply(&table[i - 1], &number_in_montgomery_form);
+            table.push(next_power);
+        }
+        let mut result = Zeroizing::new(self.one.clone());
+        let windows = power.len() * LIMB_BITS / WINDOW_SIZE;
+        for window_index in (0..windows).rev() {
+            for _ in 0..WINDOW_SIZE {
+                result = Zeroizing::new(self.montgomery_multiply(&result, &result));
+            }
+            let bit_index = window_index * WINDOW_SIZE;
+            let window_value = (power[bit_index / LIMB_BITS] >> (bit_index % LIMB_BITS)) & ((1 << WINDOW_SIZE) - 1);
+            let table_entry = Zeroizing::new(select_from_table(&table, window_value));
+            result = Zeroizing::new(self.montgomery_multiply(&result, &table_entry));
+        }
+        self.from_montgomery_form(&result)
+    }
+
+    fn to_montgomery_form(&self, a: &[u64]) -> Vec<u64> {
+        self.montgomery_multiply(a, &self.r_squared)
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    fn from_montgomery_form(&self, a: &[u64]) -> Vec<u64> {
+        let mut one = vec![0u64; self.n.len()];
+        one[0] = 1;
+        self.montgomery_multiply(a, &one)
+    }
+
+    // Coarsely Integrated Operand Scanning (CIOS) Montgomery multiplication: a * b * R ^ (-1) mod n for a, b < n
+    fn montgomery_multiply(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let limbs = self.n.len();
+        let mut t = vec![0u64; limbs + 2];
+        for &b_limb in b.iter() {
+            let mut carry = 0u64;
+            for j in 0..limbs {
+                (t[j], carry) = multiply_add(a[j], b_limb, t[j], carry);
+            }
+            let (sum, overflow) = t[limbs].overflowing_add(carry);
+            t[limbs] = sum;
+            t[limbs + 1] = overflow as u64;
+
+            let m = t[0].wrapping_mul(self.n_prime);
+            let (_, mut carry) = multiply_add(m, self.n[0], t[0], 0);
+            for j in 1..limbs {
+                (t[j - 1], carry) = multiply_add(m, self.n[j], t[j], carry);
+            }
+            let (sum, overflow) = t[limbs].overflowing_add(carry);
+            t[limbs - 1] = sum;
+            t[limbs] = t[limbs + 1] + overflow as u64;
+        }
+        // t < 2n, subtract n unless it results in a borrow
+        let (mut difference, borrow) = subtract(&t[..limbs], &self.n);
+        let is_smaller_than_n = Choice::from((borrow & !(t[limbs] & 1) & 1) as u8);
+        let result = select(&difference, &t[..limbs], is_smaller_than_n);
+        t.zeroize();
+        difference.zeroize();
+        result
+    }
+
+    fn to_bigint(&self, limbs: &[u64]) -> BigInt {
+        let digits: Vec<u32> = limbs.iter().flat_map(|&limb| [limb as u32, (limb >> 32) as u32]).collect();
+        BigInt::from_biguint(Sign::Plus, BigUint::new(digits))
+    }
+}
+
+// Little-endian limbs of a non-negative number, padded with zeros to at least the given number of limbs
+fn to_limbs(number: &BigInt, limbs: usize) -> Vec<u64> {
+    let mut result = number.to_u64_digits().1;
+    if result.len() < limbs {
+        result.resize(limbs, 0);
+    }
+    result
+}
+
+// a * b + c + carry as (low word, high word), never overflows
+fn multiply_add(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let result = (a as u128) * (b as u128) + (c as u128) + (carry as u128);
+    (result as u64, (result >> LIMB_BITS) as u64)
+}
+
+// a - b and the final borrow
+fn subtract(a: &[u64], b: &[u64]) -> (Vec<u64>, u64) {
+    let mut borrow = 0u64;
+    let difference = a.iter().zip(b.iter()).map(|(&a_limb, &b_limb)| {
+        let (partial, first_borrow) = a_limb.overflowing_sub(b_limb);
+        let (limb, second_borrow) = partial.overflowing_sub(borrow);
+        borrow = (first_borrow | second_borrow) as u64;
+        limb
+    }).collect();
+    (difference, borrow)
+}
+
+// a + b and the final carry
+fn add_with_carry(a: &[u64], b: &[u64]) -> (Vec<u64>, u64) {
+    let mut carry = 0u64;
+    let sum = a.iter().zip(b.iter()).map(|(&a_limb, &b_limb)| {
+        let (partial, first_carry) = a_limb.overflowing_add(b_limb);
+        let (limb, second_carry) = partial.overflowing_add(carry);
+        carry = (first_carry | second_carry) as u64;
+        limb
+    }).collect();
+    (sum, carry)
+}
+
+// a + b mod n for a, b < n
+fn add_modulo(a: &[u64], b: &[u64], n: &[u64]) -> Vec<u64> {
+    let (sum, carry) = add_with_carry(a, b);
+    let sum = Zeroizing::new(sum);
+    let (difference, borrow) = subtract(&sum, n);
+    let difference = Zeroizing::new(difference);
+    let is_smaller_than_n = Choice::from((borrow & !carry & 1) as u8);
+    select(&difference, &sum, is_smaller_than_n)
+}
+
+// a - b mod n for a, b < n, n is added back when the subtraction borrows
+fn subtract_modulo(a: &[u64], b: &[u64], n: &[u64]) -> Vec<u64> {
+    let (difference, borrow) = subtract(a, b);
+    let difference = Zeroizing::new(difference);
+    let addend = Zeroizing::new(select(&vec![0u64; n.len()], n, Choice::from(borrow as u8)));
+    add_with_carry(&difference, &addend).0
+}
+
+// a / 2 mod n for a < n and odd n: a / 2 for even a, (a + n) / 2 for odd a
+fn half_modulo(a: &[u64], n: &[u64]) -> Vec<u64> {
+    let addend = Zeroizing::new(select(&vec![0u64; n.len()], n, Choice::from((a[0] & 1) as u8)));
+    let (mut sum, carry) = add_with_carry(a, &addend);
+    shift_right_one(&mut sum, carry);
+    sum
+}
+
+// Shifts the limbs right by one bit, the highest bit is set to top_bit
+fn shift_right_one(limbs: &mut [u64], top_bit: u64) {
+    let mut carry = top_bit;
+    for limb in limbs.iter_mut().rev() {
+        let lowest_bit = *limb & 1;
+        *limb = (*limb >> 1) | (carry << (LIMB_BITS - 1));
+        carry = lowest_bit;
+    }
+}
+
+// Shifts the limbs left by one bit, the lowest bit is set to bottom_bit
+fn shift_left_one(limbs: &mut [u64], bottom_bit: u64) {
+    let mut carry = bottom_bit;
+    for limb in limbs.iter_mut() {
+        let highest_bit = *limb >> (LIMB_BITS - 1);
+        *limb = (*limb << 1) | carry;
+        carry = highest_bit;
+    }
+}
+
+// Swaps a and b if choice is 1
+fn conditional_swap(a: &mut [u64], b: &mut [u64], choice: Choice) {
+    for (a_limb, b_limb) in a.iter_mut().zip(b.iter_mut()) {
+        u64::conditional_swap(a_limb, b_limb, choice);
+    }
+}
+
+// Swaps a and b of the same length if a < b
+pub(crate) fn larger_first(a: &mut [u64], b: &mut [u64]) {
+    let (mut difference, borrow) = subtract(a, b);
+    difference.zeroize();
+    conditional_swap(a, b, Choice::from(borrow as u8));
+}
+
+// Copy of the limbs padded with zeros to at least the given number of limbs, allocated once
+fn padded(limbs: &[u64], length: usize) -> Zeroizing<Vec<u64>> {
+    let length = cmp::max(limbs.len(), length);
+    let mut result = Zeroizing::new(Vec::with_capacity(length));
+    result.extend_from_slice(limbs);
+    result.resize(length, 0);
+    result
+}
+
+/*
+ * Arithmetic on non-negative numbers of any size, used for the secret numbers which are not reduced modulo an odd n.
+ * The time only depends on the number of limbs of the arguments
+ */
+
+// a + b, one limb longer than the longer of the two
+pub(crate) fn add(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let length = cmp::max(a.len(), b.len()) + 1;
+    let (sum, _) = add_with_carry(&padded(a, length), &padded(b, length));
+    sum
+}
+
+// a - b for a >= b, as long as a
+pub(crate) fn subtract_smaller(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let (difference, _) = subtract(a, &padded(b, a.len()));
+    difference
+}
+
+// Schoolbook multiplication, as long as a and b together
+pub(crate) fn multiply(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut product = vec![0u64; a.len() + b.len()];
+    for (i, &a_limb) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &b_limb) in b.iter().enumerate() {
+            (product[i + j], carry) = multiply_add(a_limb, b_limb, product[i + j], carry);
+        }
+        product[i + b.len()] = carry;
+    }
+    product
+}
+
+/*
+ * a mod m for any m > 0, including the even p - 1 and q - 1 which Montgomery arithmetic can not use.
+ * Long division bit by bit: the remainder is doubled, the next bit of a is added and m is subtracted unless it borrows
+ */
+pub(crate) fn remainder(a: &[u64], m: &[u64]) -> Vec<u64> {
+    // The doubled remainder is smaller than 2 * m, so it fits into one more limb
+    let modulo = padded(m, m.len() + 1);
+    let mut result = Zeroizing::new(vec![0u64; m.len() + 1]);
+    for bit_index in (0..a.len() * LIMB_BITS).rev() {
+        shift_left_one(&mut result, (a[bit_index / LIMB_BITS] >> (bit_index % LIMB_BITS)) & 1);
+        let (difference, borrow) = subtract(&result, &modulo);
+        let difference = Zeroizing::new(difference);
+        result = Zeroizing::new(select(&difference, &result, Choice::from(borrow as u8)));
+    }
+    result[..m.len()].to_vec()
+}
+
+/*
+ * a / d for an odd d which divides a, from the lowest limb up: the next limb of the quotient is the one which makes the
+ * lowest remaining limb of a zero, found with the inverse of the lowest limb of d modulo 2 ^ 64
+ */
+pub(crate) fn divide_exact(a: &[u64], d: &[u64]) -> Vec<u64> {
+    let d_inverse = inverse_modulo_word(d[0]);
+    let d = padded(d, a.len());
+    let mut remaining = Zeroizing::new(a.to_vec());
+    let mut quotient = vec![0u64; a.len()];
+    for i in 0..a.len() {
+        quotient[i] = remaining[i].wrapping_mul(d_inverse);
+        let (mut carry, mut borrow) = (0u64, 0u64);
+        for j in i..a.len() {
+            let product;
+            (product, carry) = multiply_add(quotient[i], d[j - i], 0, carry);
+            let (partial, first_borrow) = remaining[j].overflowing_sub(product);
+            let (limb, second_borrow) = partial.overflowing_sub(borrow);
+            remaining[j] = limb;
+            borrow = (first_borrow | second_borrow) as u64;
+        }
+    }
+    quotient
+}
+
+// Returns a if choice is 0 and b if choice is 1
+fn select(a: &[u64], b: &[u64], choice: Choice) -> Vec<u64> {
+    a.iter().zip(b.iter()).map(|(a_limb, b_limb)| u64::conditional_select(a_limb, b_limb, choice)).collect()
+}
+
+// Reads every entry of the table so that the memory access pattern does not depend on the index
+fn select_from_table(table: &[Vec<u64>], index: u64) -> Vec<u64> {
+    let mut result = vec![0u64; table[0].len()];
+    for (entry_index, entry) in table.iter().enumerate() {
+        let is_selected = (entry_index as u64).ct_eq(&index);
+        for (result_limb, entry_limb) in result.iter_mut().zip(entry.iter()) {
+            result_limb.conditional_assign(entry_limb, is_selected);
+        }
+    }
+    result
+}
+
+// a ^ (-1) mod 2 ^ 64 for odd a with Newton's iteration, every step doubles the number of correct bits
+fn inverse_modulo_word(a: u64) -> u64 {
+    let mut inverse = 1u64;
+    for _ in 0..6 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(a.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modulo_arithmetic;
+    use num_bigint::{RandBigInt, ToBigInt};
+    use num_traits::FromPrimitive;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn should_find_inverse_modulo_word() {
+        for a in [1u64, 3, 0xffff_ffff_ffff_ffff, 404790586766519] {
+            assert_eq!(a.wrapping_mul(inverse_modulo_word(a)), 1);
+        }
+    }
+
+    #[test]
+    fn should_compute_r_squared() {
+        let n = BigInt::from_u64(404790586766519).unwrap();
+        let modulus = ConstantTimeModulus::for_modulo(&n);
+        assert_eq!(modulus.to_bigint(&modulus.r_squared), (BigInt::from(1) << 128) % &n);
+    }
+
+    #[test]
+    fn should_reduce_numbers_of_any_length_without_division() {
+        let mut rng = thread_rng();
+        for modulo_bits in [8, 64, 65, 1024] {
+            let modulo = rng.gen_biguint(modulo_bits).to_bigint().unwrap() | (BigInt::from(1) << (modulo_bits - 1)) | BigInt::from(1);
+            let modulus = ConstantTimeModulus::for_modulo(&modulo);
+            for number_bits in [0, 1, modulo_bits - 1, modulo_bits, 2 * modulo_bits, 5 * modulo_bits + 3] {
+                let number = rng.gen_biguint(number_bits).to_bigint().unwrap();
+                assert_eq!(modulus.to_bigint(&modulus.reduce(&number)), &number % &modulo, "{} mod {}", number, modulo);
+            }
+        }
+    }
+
+    #[test]
+    fn should_multiply_modulo() {
+        let modulo = BigInt::from_u64(404790586766519).unwrap();
+        let modulus = ConstantTimeModulus::for_modulo(&modulo);
+        let a = BigInt::from_u64(32817698412213213).unwrap();
+        let b = BigInt::from_u64(8752368742787).unwrap();
+        assert_eq!(modulus.multiply(&a, &b), (&a * &b) % &modulo);
+        let (secret_a, secret_b) = (SecretBigInt::new(a.clone()), SecretBigInt::new(b.clone()));
+        assert_eq!(modulus.secret_multiply(&secret_a, &secret_b).expose(), (&a * &b) % &modulo);
+        assert_eq!(modulus.secret_modulo(&secret_a).expose(), &a % &modulo);
+        assert_eq!(modulus.secret_subtract(&secret_b, &secret_a).expose(), ((&b - &a) % &modulo + &modulo) % &modulo);
+    }
+
+    #[test]
+    fn should_find_inverse_and_gcd() {
+        let modulus = ConstantTimeModulus::for_modulo(&BigInt::from(3120 * 17 + 1));
+        let inverse = modulus.secret_inverse(&SecretBigInt::from(17)).unwrap().expose();
+        assert_eq!((inverse * 17) % (3120 * 17 + 1), BigInt::from(1));
+        let modulus = ConstantTimeModulus::for_modulo(&BigInt::from(11));
+        assert_eq!(modulus.secret_inverse(&SecretBigInt::from(25)).unwrap().expose(), BigInt::from(4));
+        let modulus = ConstantTimeModulus::for_modulo(&BigInt::from(9));
+        assert_eq!(modulus.secret_inverse(&SecretBigInt::from(6)), None);
+        assert_eq!(modulus.secret_gcd(&SecretBigInt::from(6)).expose(), BigInt::from(3));
+    }
+
+    #[test]
+    fn should_invert_random_numbers() {
+        let mut rng = thread_rng();
+        for modulo_bits in [8, 64, 65, 1024] {
+            let modulo = rng.gen_biguint(modulo_bits).to_bigint().unwrap() | (BigInt::from(1) << (modulo_bits - 1)) | BigInt::from(1);
+            let modulus = ConstantTimeModulus::for_modulo(&modulo);
+            let number = rng.gen_biguint(modulo_bits + 16).to_bigint().unwrap();
+            let gcd = modulus.secret_gcd(&SecretBigInt::new(number.clone())).expose();
+            assert_eq!(gcd, crate::euclidean::find_gcd_and_bezout_coefficients(&number, &modulo).gcd);
+            match modulus.secret_inverse(&SecretBigInt::new(number.clone())) {
+                Some(inverse) => assert_eq!((inverse.expose() * &number) % &modulo, BigInt::from(1)),
+                None => assert_ne!(gcd, BigInt::from(1))
+            }
+        }
+    }
+
+    #[test]
+    fn should_exponentiate_correctly() {
+        let modulus = ConstantTimeModulus::for_modulo(&BigInt::from_u64(17).unwrap());
+        assert_eq!(modulus.exponent(&BigInt::from(2), &BigInt::from(4)), BigInt::from(16));
+        assert_eq!(modulus.exponent(&BigInt::from(2), &BigInt::from(30)), BigInt::from(13));
+        assert_eq!(modulus.exponent(&BigInt::from(2), &BigInt::from(0)), BigInt::from(1));
+    }
+
+    #[test]
+    fn should_match_variable_time_exponent_for_random_inputs() {
+        let mut rng = thread_rng();
+        for modulo_bits in [8, 64, 65, 127, 512, 1024, 2048] {
+            for _ in 0..3 {
+                let modulo = rng.gen_biguint(modulo_bits).to_bigint().unwrap() | (BigInt::from(1) << (modulo_bits - 1)) | BigInt::from(1);
+                let number = rng.gen_biguint(modulo_bits + 16).to_bigint().unwrap();
+                let power_bits = rng.gen_range(0..=modulo_bits + 64);
+                let power = rng.gen_biguint(power_bits).to_bigint().unwrap();
+                let modulus = ConstantTimeModulus::for_modulo(&modulo);
+                assert_eq!(modulus.exponent(&number, &power), modulo_arithmetic::exponent(&number, &power, &modulo),
+                    "{} ^ {} mod {}", number, power, modulo);
+            }
+        }
+    }
+
+    #[test]
+    fn should_exponentiate_with_public_power() {
+        let modulo = BigInt::from_u64(404790586766519).unwrap();
+        let modulus = ConstantTimeModulus::for_modulo(&modulo);
+        let number = BigInt::from_u64(32817698412213213).unwrap();
+        for power in [0u64, 1, 65537] {
+            let power = BigInt::from(power);
+            assert_eq!(modulus.secret_exponent_with_public_power(&SecretBigInt::new(number.clone()), &power).expose(),
+                modulo_arithmetic::exponent(&number, &power, &modulo));
+        }
+    }
+
+    #[test]
+    fn should_handle_numbers_close_to_modulo() {
+        // All limbs of the modulo are set, so intermediate results overflow into the extra limb
+        let modulo = (BigInt::from(1) << 256) - 189;
+        let modulus = ConstantTimeModulus::for_modulo(&modulo);
+        let number: BigInt = &modulo - 1;
+        assert_eq!(modulus.exponent(&number, &BigInt::from(2)), BigInt::from(1));
+        assert_eq!(modulus.exponent(&number, &BigInt::from(3)), number);
+    }
+}
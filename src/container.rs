@@ -0,0 +1,293 @@
+use std::fmt;
+use std::io::{Read, Write};
+use crate::Error;
+use crate::crypto::KeyFingerprint;
+
+/*
+ * Header written in front of the encrypted data, so that a file tells which keys and which scheme it needs.
+ *
+ * magic (8) | version (1) | cipher suite (1) | flags (1) | compression (1) | original length (8, big-endian) |
+ * recipient count (2) | recipients: key fingerprint (32) | wrapped key length (2) | wrapped key
+ *
+ * The original length is only meaningful when the HAS_ORIGINAL_LENGTH flag is set, it is unknown when encrypting
+ * a stream such as the standard input. The IS_SIGNED flag marks data sealed by io::seal_container, whose decrypted
+ * payload carries the signature of the sender next to the plaintext. The payload is compressed before it is
+ * encrypted unless the compression is None, the original length is the length before compression.
+ * Wrapped keys are empty for the cipher suites encrypting the data with the recipient key directly.
+ *
+ * Version 2 was the same without the compression.
+ * Version 1 had a single recipient without a wrapped key:
+ * magic (8) | version (1) | cipher suite (1) | flags (1) | key fingerprint (32) | original length (8, big-endian)
+ */
+pub const MAGIC: [u8; 8] = *b"EULERCRY";
+pub const FORMAT_VERSION: u8 = 3;
+
+const HAS_ORIGINAL_LENGTH: u8 = 1;
+const IS_SIGNED: u8 = 2;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CipherSuite {
+    // Every chunk prefixed with a marker byte and encrypted with the RSA key directly
+    RsaChunks = 1,
+    // Data encrypted with ChaCha20 under a random content key, the content key encrypted for every recipient
+    RsaWrappedChaCha20 = 2,
+    // Data split into segments authenticated with ChaCha20-Poly1305 under a random content key
+    RsaWrappedChaCha20Poly1305 = 3
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Gzip = 1,
+    Zstd = 2
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Recipient {
+    pub key_fingerprint: KeyFingerprint,
+    pub wrapped_key: Vec<u8>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Header {
+    pub version: u8,
+    pub cipher_suite: CipherSuite,
+    pub original_length: Option<u64>,
+    pub recipients: Vec<Recipient>,
+    pub is_signed: bool,
+    pub compression: Compression
+}
+
+impl CipherSuite {
+
+    fn from_byte(byte: u8) -> Result<CipherSuite, Error> {
+        match byte {
+            1 => Ok(CipherSuite::RsaChunks),
+            2 => Ok(CipherSuite::RsaWrappedChaCha20),
+            3 => Ok(CipherSuite::RsaWrappedChaCha20Poly1305),
+            _ => Err(Error::InvalidContainer(format!("Unknown cipher suite {}", byte)))
+        }
+    }
+
+    // Older cipher suites do not detect modified data
+    pub fn is_authenticated(&self) -> bool {
+        *self == CipherSuite::RsaWrappedChaCha20Poly1305
+    }
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CipherSuite::RsaChunks => write!(f, "RSA chunks"),
+            CipherSuite::RsaWrappedChaCha20 => write!(f, "ChaCha20 with RSA wrapped key"),
+            CipherSuite::RsaWrappedChaCha20Poly1305 => write!(f, "ChaCha20-Poly1305 segments with RSA wrapped key")
+        }
+    }
+}
+
+impl Compression {
+
+    fn from_byte(byte: u8) -> Result<Compression, Error> {
+        match byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::InvalidContainer(format!("Unknown compression {}", byte)))
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd")
+        }
+    }
+}
+
+impl Recipient {
+
+    pub fn new(key_fingerprint: KeyFingerprint, wrapped_key: Vec<u8>) -> Recipient {
+        Recipient { key_fingerprint, wrapped_key }
+    }
+}
+
+impl Header {
+
+    pub fn new(cipher_suite: CipherSuite, recipients: Vec<Recipient>, original_length: Option<u64>) -> Header {
+        Header { version: FORMAT_VERSION, cipher_suite, original_length, recipients, is_signed: false, compression: Compression::None }
+    }
+
+    pub fn signed(mut self) -> Header {
+        self.is_signed = true;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Header {
+        self.compression = compression;
+        self
+    }
+
+    pub fn recipient(&self, key_fingerprint: &KeyFingerprint) -> Option<&Recipient> {
+        self.recipients.iter().find(|recipient| &recipient.key_fingerprint == key_fingerprint)
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    // Bytes of the header in the layout of its version, so that headers read from older data are reproduced exactly
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if self.version < 3 && self.compression != Compression::None {
+            return Err(Error::InvalidContainer(format!("Format version {} does not support compression", self.version)));
+        }
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.push(self.version);
+        header.push(self.cipher_suite as u8);
+        let mut flags = if self.original_length.is_some() { HAS_ORIGINAL_LENGTH } else { 0 };
+        if self.is_signed {
+            flags |= IS_SIGNED;
+        }
+        header.push(flags);
+        match self.version {
+            1 => {
+                let [recipient] = self.recipients.as_slice() else {
+                    return Err(Error::InvalidContainer(format!("Format version 1 needs a single recipient, found {}", self.recipients.len())));
+                };
+                header.extend_from_slice(&recipient.key_fingerprint.0);
+                header.extend_from_slice(&self.original_length.unwrap_or(0).to_be_bytes());
+                return Ok(header);
+            },
+            2 => (),
+            3 => header.push(self.compression as u8),
+            version => return Err(Error::InvalidContainer(format!("Unsupported format version {}", version)))
+        }
+        let recipient_count = u16::try_from(self.recipients.len())
+            .map_err(|_| Error::InvalidContainer(format!("Too many recipients {}", self.recipients.len())))?;
+        header.extend_from_slice(&self.original_length.unwrap_or(0).to_be_bytes());
+        header.extend_from_slice(&recipient_count.to_be_bytes());
+        for recipient in &self.recipients {
+            let wrapped_key_length = u16::try_from(recipient.wrapped_key.len())
+                .map_err(|_| Error::InvalidContainer(format!("Wrapped key too long {}", recipient.wrapped_key.len())))?;
+            header.extend_from_slice(&recipient.key_fingerprint.0);
+            header.extend_from_slice(&wrapped_key_length.to_be_bytes());
+            header.extend_from_slice(&recipient.wrapped_key);
+        }
+        Ok(header)
+    }
+
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> Result<Header, Error> {
+        let mut magic = [0u8; MAGIC.len()];
+        reader.read_exact(&mut magic).map_err(|_| Error::InvalidContainer("Missing header".to_string()))?;
+        if magic != MAGIC {
+            return Err(Error::InvalidContainer("Missing magic number, data is not an encrypted container".to_string()));
+        }
+        Header::read_after_magic_from(reader)
+    }
+
+    // For readers which have already consumed the magic number
+    pub(crate) fn read_after_magic_from<R: Read + ?Sized>(reader: &mut R) -> Result<Header, Error> {
+        let [version, cipher_suite, flags] = read_array(reader)?;
+        let cipher_suite = CipherSuite::from_byte(cipher_suite)?;
+        let (compression, original_length, recipients) = match version {
+            1 => {
+                let key_fingerprint = KeyFingerprint(read_array(reader)?);
+                let original_length = u64::from_be_bytes(read_array(reader)?);
+                (Compression::None, original_length, vec![Recipient::new(key_fingerprint, Vec::new())])
+            },
+            2 | 3 => {
+                let compression = if version == 3 { Compression::from_byte(read_array::<R, 1>(reader)?[0])? } else { Compression::None };
+                let original_length = u64::from_be_bytes(read_array(reader)?);
+                let recipient_count = u16::from_be_bytes(read_array(reader)?);
+                let mut recipients = Vec::with_capacity(recipient_count as usize);
+                for _ in 0..recipient_count {
+                    let key_fingerprint = KeyFingerprint(read_array(reader)?);
+                    let mut wrapped_key = vec![0u8; u16::from_be_bytes(read_array(reader)?) as usize];
+                    reader.read_exact(&mut wrapped_key).map_err(|_| Error::InvalidContainer("Truncated header".to_string()))?;
+                    recipients.push(Recipient::new(key_fingerprint, wrapped_key));
+                }
+                (compression, original_length, recipients)
+            },
+            _ => return Err(Error::InvalidContainer(format!("Unsupported format version {}", version)))
+        };
+        Ok(Header {
+            version,
+            cipher_suite,
+            original_length: (flags & HAS_ORIGINAL_LENGTH != 0).then_some(original_length),
+            recipients,
+            is_signed: flags & IS_SIGNED != 0,
+            compression
+        })
+    }
+}
+
+fn read_array<R: Read + ?Sized, const N: usize>(reader: &mut R) -> Result<[u8; N], Error> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(|_| Error::InvalidContainer("Truncated header".to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::predefined_keys;
+
+    #[test]
+    fn should_write_and_read_header() {
+        let fingerprint = predefined_keys().public_key.fingerprint();
+        for original_length in [Some(1234), None] {
+            let recipients = vec![Recipient::new(fingerprint, vec![1, 2, 3]), Recipient::new(KeyFingerprint([7u8; 32]), vec![4])];
+            let header = Header::new(CipherSuite::RsaWrappedChaCha20, recipients, original_length).signed()
+                .with_compression(Compression::Zstd);
+            let mut bytes = Vec::new();
+            header.write_to(&mut bytes).unwrap();
+            assert_eq!(Header::read_from(&mut bytes.as_slice()).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn should_read_version_1_header() {
+        let fingerprint = predefined_keys().public_key.fingerprint();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, CipherSuite::RsaChunks as u8, HAS_ORIGINAL_LENGTH]);
+        bytes.extend_from_slice(&fingerprint.0);
+        bytes.extend_from_slice(&1234u64.to_be_bytes());
+        let header = Header::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.original_length, Some(1234));
+        assert_eq!(header.recipient(&fingerprint), Some(&Recipient::new(fingerprint, Vec::new())));
+        assert_eq!(header.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn should_read_version_2_header_without_compression() {
+        let recipients = vec![Recipient::new(predefined_keys().public_key.fingerprint(), vec![1, 2, 3])];
+        let mut header = Header::new(CipherSuite::RsaWrappedChaCha20Poly1305, recipients, None);
+        header.version = 2;
+        let bytes = header.to_bytes().unwrap();
+        assert_eq!(bytes.len(), Header::new(CipherSuite::RsaChunks, header.recipients.clone(), None).to_bytes().unwrap().len() - 1);
+        assert_eq!(Header::read_from(&mut bytes.as_slice()).unwrap(), header);
+        assert!(header.with_compression(Compression::Gzip).to_bytes().is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_header() {
+        let recipients = vec![Recipient::new(predefined_keys().public_key.fingerprint(), Vec::new())];
+        let bytes = Header::new(CipherSuite::RsaChunks, recipients, None).to_bytes().unwrap();
+        assert!(matches!(Header::read_from(&mut &bytes[..20]), Err(Error::InvalidContainer(_))));
+        let mut future_version = bytes.clone();
+        future_version[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(Header::read_from(&mut future_version.as_slice()), Err(Error::InvalidContainer(_))));
+        let mut unknown_cipher_suite = bytes.clone();
+        unknown_cipher_suite[MAGIC.len() + 1] = 0;
+        assert!(matches!(Header::read_from(&mut unknown_cipher_suite.as_slice()), Err(Error::InvalidContainer(_))));
+        let mut missing_magic = bytes.clone();
+        missing_magic[0] = b'X';
+        assert!(matches!(Header::read_from(&mut missing_magic.as_slice()), Err(Error::InvalidContainer(_))));
+    }
+}
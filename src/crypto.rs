@@ -1,108 +1,551 @@
-use anyhow::{anyhow, Result};
-use num_bigint::{BigInt, Sign};
+use num_bigint::{BigInt, RandBigInt, Sign};
 use num_traits::One;
 use num_traits::{FromPrimitive, Zero};
-use std::{cmp, result};
+use std::{cmp, fmt};
 use rand::Rng;
+use sha2::{Digest, Sha256};
 use yasna::{self, ASN1Error};
 
+use crate::Error;
 use crate::pem;
-use crate::euclidean;
 use crate::primes;
-use crate::modulo_arithmetic;
-use crate::pkcs8::PrivateKeyInfo;
+use crate::modulo_arithmetic::ModulusContext;
+use crate::constant_time::ConstantTimeModulus;
+use crate::prime_certificate::{self, PrimeCertificate};
+use crate::pkcs8::{self, PrivateKeyInfo};
 use crate::spki::SubjectPublicKeyInfo;
+use crate::secret::SecretBigInt;
+use zeroize::Zeroizing;
 
+mod legacy;
+#[allow(deprecated)]
+pub use legacy::{Key, KeyType, PreparedKey, generate_keys, generate_keys_with_certificates, encryption_chunk_size,
+    decryption_chunk_size, encrypt_bytes, decrypt_bytes};
+
+const PUBLIC_KEY_LABEL: &str = "PUBLIC KEY";
+const PRIVATE_KEY_LABEL: &str = "PRIVATE KEY";
+
+// Can only encrypt
 #[derive(Debug, PartialEq, Clone)]
-pub struct Key {
-    pub exponent: BigInt,
-    pub modulo: BigInt,
-    pub key_type: KeyType
+pub struct RsaPublicKey {
+    modulo: BigInt,
+    exponent: BigInt
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum KeyType {
-    Public,
-    Private
+// Can only decrypt. The private exponent is wiped when the key is dropped, so the key cannot be cloned
+#[derive(Debug, PartialEq)]
+pub struct RsaPrivateKey {
+    modulo: BigInt,
+    exponent: SecretBigInt,
+    // None for legacy private keys whose modulo could not be factored
+    components: Option<PrivateComponents>
+}
+
+// Public exponent and the factors of the modulo, stored in the private key as in PKCS#1 RSAPrivateKey
+#[derive(Debug, PartialEq)]
+pub struct PrivateComponents {
+    pub public_exponent: BigInt,
+    pub p: SecretBigInt,
+    pub q: SecretBigInt,
+    // q ^ (-1) mod p for the Chinese remainder theorem
+    pub q_inverse: SecretBigInt
 }
 
-impl Key {
+impl PrivateComponents {
+
+    // Fails when p is not odd or q has no inverse modulo p, which is never the case for distinct odd primes
+    pub fn new(public_exponent: BigInt, p: SecretBigInt, q: SecretBigInt) -> Result<PrivateComponents, Error> {
+        if !p.is_odd() || p == SecretBigInt::from(1) {
+            return Err(Error::InvalidKey("Factor p of the modulo is not an odd number larger than 1".to_string()));
+        }
+        let q_inverse = ConstantTimeModulus::for_secret_modulo(&p).secret_inverse(&q)
+            .ok_or(Error::InvalidKey("Factor q of the modulo has no inverse modulo p".to_string()))?;
+        Ok(PrivateComponents { public_exponent, p, q, q_inverse })
+    }
+
+    // p - 1 and q - 1
+    fn minus_one(&self) -> (SecretBigInt, SecretBigInt) {
+        let one = SecretBigInt::from(1);
+        (self.p.subtract(&one), self.q.subtract(&one))
+    }
+
+    // phi(n) = (p - 1) * (q - 1)
+    fn totient_function(&self) -> SecretBigInt {
+        let (p_minus_one, q_minus_one) = self.minus_one();
+        p_minus_one.multiply(&q_minus_one)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct KeyPair {
+    pub public_key: RsaPublicKey,
+    pub private_key: RsaPrivateKey
+}
+
+impl RsaPublicKey {
+
+    pub fn new(modulo: BigInt, exponent: BigInt) -> RsaPublicKey {
+        RsaPublicKey { modulo, exponent }
+    }
+
+    pub fn modulo(&self) -> &BigInt {
+        &self.modulo
+    }
+
+    pub fn exponent(&self) -> &BigInt {
+        &self.exponent
+    }
+
+    pub fn fingerprint(&self) -> KeyFingerprint {
+        KeyFingerprint::of_modulo(&self.modulo)
+    }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let key_data = match self.key_type {
-            KeyType::Private => {
-                PrivateKeyInfo::wrap(&self).serialize()
-            },
-            KeyType::Public => {
-                SubjectPublicKeyInfo::wrap(&self).serialize()
-            }
-        };
-        pem::serialize(&key_data, &self.key_type)
+        pem::serialize_block(&SubjectPublicKeyInfo::wrap(self).serialize(), PUBLIC_KEY_LABEL)
     }
 
-    pub fn deserialize(input: &[u8]) -> Result<Key, anyhow::Error> {
-        let (key_data, key_type) = pem::deserialize(input)?;
-        match key_type {
-            KeyType::Private => {
-                let private_key_info = PrivateKeyInfo::deserialize(&key_data)
-                    .map_err(|err| anyhow!("Failed to deserialize {}", err))?;
-                Key::from_bytes(&private_key_info.private_key, key_type).map_err(|err| anyhow!("Failed to deserialize private key {}", err))
-            },
-            KeyType::Public => {
-                let public_key_info = SubjectPublicKeyInfo::deserialize(&key_data)
-                    .map_err(|err| anyhow!("Failed to deserialize {}", err))?;
-                Ok(public_key_info.public_key)
-            }
+    pub fn deserialize(input: &[u8]) -> Result<RsaPublicKey, Error> {
+        let (key_data, label) = pem::deserialize_block(input)?;
+        if label != PUBLIC_KEY_LABEL {
+            return Err(Error::InvalidKey(format!("Expected {}, found {}", PUBLIC_KEY_LABEL, label)));
         }
+        RsaPublicKey::from_key_info(&key_data)
     }
 
+    pub(crate) fn from_key_info(key_data: &[u8]) -> Result<RsaPublicKey, Error> {
+        let public_key_info = SubjectPublicKeyInfo::deserialize(key_data)?;
+        ensure_rsa_algorithm(&public_key_info.public_key_algorithm.algorithm)?;
+        Ok(public_key_info.public_key)
+    }
+
+    // SEQUENCE { modulo, exponent }
     pub(crate) fn as_bytes(&self) -> Vec<u8> {
         yasna::construct_der(|writer| {
             writer.write_sequence(|writer| {
-                let (modulo_sign, modulo_bytes) = self.modulo.to_bytes_be();
-                let (exponent_sign, exponent_bytes) = self.exponent.to_bytes_be();
-                writer.next().write_bigint_bytes(&modulo_bytes, modulo_sign == Sign::Plus);
-                writer.next().write_bigint_bytes(&exponent_bytes, exponent_sign == Sign::Plus);
+                write_bigint(writer.next(), &self.modulo);
+                write_bigint(writer.next(), &self.exponent);
             })
         })
     }
 
-    pub(crate) fn from_bytes(bytes: &[u8], key_type: KeyType) -> Result<Key, ASN1Error> {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<RsaPublicKey, ASN1Error> {
         yasna::parse_der(bytes, |reader| {
             reader.read_sequence(|reader| {
-                let (modulo_bytes, modulo_positive) = reader.next().read_bigint_bytes()?;
-                let (exponent_bytes, exponent_positive) = reader.next().read_bigint_bytes()?;
-                let modulo = BigInt::from_bytes_be(if modulo_positive { Sign::Plus } else { Sign::Minus } , &modulo_bytes);
-                let exponent = BigInt::from_bytes_be(if exponent_positive { Sign::Plus } else { Sign::Minus } , &exponent_bytes);
-                Ok(Key {
-                    exponent,
-                    modulo,
-                    key_type
-                })
+                let modulo = read_bigint(reader.next())?;
+                let exponent = read_bigint(reader.next())?;
+                Ok(RsaPublicKey { modulo, exponent })
             })
         })
     }
+
+    pub fn prepare(&self) -> PreparedPublicKey<'_> {
+        PreparedPublicKey {
+            key: self,
+            operation: RsaOperation::public(&self.modulo, &self.exponent)
+        }
+    }
+
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.prepare().encrypt_bytes(data)
+    }
+
+    pub fn encryption_chunk_size(&self) -> usize {
+        // leave one byte for ENCRYPTED_PREFIX and one byte to make sure that modulo is not overflown
+        cmp::max(modulo_size_bytes(&self.modulo) - 2, 1)
+    }
+
+    /*
+     * RSA-KEM, see https://datatracker.ietf.org/doc/html/rfc5990#appendix-A: the secret is a random number below
+     * the modulo, encrypted without any padding. Returns the secret and the encapsulated secret, keys should be derived
+     * from the secret with a hash instead of using it directly
+     */
+    pub fn encapsulate(&self) -> Result<(Zeroizing<Vec<u8>>, Vec<u8>), Error> {
+        self.prepare().operation.encapsulate()
+    }
+
+    // Checks a signature made by sign_digest of the private key of the pair
+    pub fn verify_digest(&self, digest: &[u8; 32], signature: &[u8]) -> Result<(), Error> {
+        self.prepare().operation.verify_digest(digest, signature)
+    }
+
+    pub fn signature_size(&self) -> usize {
+        modulo_size_bytes(&self.modulo)
+    }
 }
 
-fn find_private_key(totient_function: &BigInt, public_key: &BigInt) -> BigInt {
-    let gcd_and_coefficients = euclidean::find_gcd_and_bezout_coefficients(public_key, totient_function);
-    let mut private_key = gcd_and_coefficients.y;
-    if private_key < BigInt::zero() {
-        private_key = private_key + totient_function;
+impl RsaPrivateKey {
+
+    pub fn new(modulo: BigInt, exponent: SecretBigInt, components: Option<PrivateComponents>) -> RsaPrivateKey {
+        RsaPrivateKey { modulo, exponent, components }
+    }
+
+    pub fn modulo(&self) -> &BigInt {
+        &self.modulo
+    }
+
+    pub fn exponent(&self) -> &SecretBigInt {
+        &self.exponent
+    }
+
+    pub fn components(&self) -> Option<&PrivateComponents> {
+        self.components.as_ref()
+    }
+
+    // Same as the fingerprint of the public key of the pair
+    pub fn fingerprint(&self) -> KeyFingerprint {
+        KeyFingerprint::of_modulo(&self.modulo)
+    }
+
+    // Only known when the private key stores the public exponent
+    pub fn public_key(&self) -> Option<RsaPublicKey> {
+        self.components.as_ref().map(|components| RsaPublicKey::new(self.modulo.clone(), components.public_exponent.clone()))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let private_key_info = PrivateKeyInfo::wrap(Zeroizing::new(self.as_bytes()));
+        pem::serialize_block(&Zeroizing::new(private_key_info.serialize()), PRIVATE_KEY_LABEL)
+    }
+
+    pub fn deserialize(input: &[u8]) -> Result<RsaPrivateKey, Error> {
+        let (key_data, label) = pem::deserialize_block(input)?;
+        let key_data = Zeroizing::new(key_data);
+        if label != PRIVATE_KEY_LABEL {
+            return Err(Error::InvalidKey(format!("Expected {}, found {}", PRIVATE_KEY_LABEL, label)));
+        }
+        RsaPrivateKey::from_key_info(&key_data)
+    }
+
+    pub(crate) fn from_key_info(key_data: &[u8]) -> Result<RsaPrivateKey, Error> {
+        let private_key_info = PrivateKeyInfo::deserialize(key_data)?;
+        ensure_rsa_algorithm(&private_key_info.private_key_algorithm.algorithm)?;
+        RsaPrivateKey::from_bytes(&private_key_info.private_key)
     }
-    private_key
+
+    pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        private_key_as_bytes(&self.modulo, &self.exponent, self.components.as_ref())
+    }
+
+    /*
+     * Legacy private keys are SEQUENCE { modulo, exponent },
+     * private keys with known components are PKCS#1 RSAPrivateKey, see https://datatracker.ietf.org/doc/html/rfc8017#appendix-A.1.2
+     */
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<RsaPrivateKey, Error> {
+        let numbers = yasna::parse_der(bytes, |reader| {
+            reader.read_sequence(|reader| {
+                let mut numbers = vec![read_secret_bigint(reader.next())?, read_secret_bigint(reader.next())?];
+                if let Some(public_exponent) = reader.read_optional(read_secret_bigint)? {
+                    // Version, modulo and public exponent are followed by
+                    // private exponent, p, q, d mod (p - 1), d mod (q - 1) and q ^ (-1) mod p
+                    numbers.push(public_exponent);
+                    for _ in 0..6 {
+                        numbers.push(read_secret_bigint(reader.next())?);
+                    }
+                }
+                Ok(numbers)
+            })
+        })?;
+        let numbers = match <[SecretBigInt; 2]>::try_from(numbers) {
+            Ok([modulo, exponent]) => {
+                let modulo = modulo.expose();
+                let components = recover_private_components(&modulo, &exponent);
+                return Ok(RsaPrivateKey::new(modulo, exponent, components));
+            },
+            Err(numbers) => numbers
+        };
+        let Ok([_, modulo, public_exponent, exponent, p, q, p_exponent, q_exponent, q_inverse]) = <[SecretBigInt; 9]>::try_from(numbers) else {
+            unreachable!("Either 2 or 9 numbers are read")
+        };
+        let modulo = modulo.expose();
+        let components = PrivateComponents::new(public_exponent.expose(), p, q)?;
+        validate_private_components(&modulo, &exponent, &components)?;
+        let (p_minus_one, q_minus_one) = components.minus_one();
+        if p_exponent != exponent.remainder(&p_minus_one) || q_exponent != exponent.remainder(&q_minus_one)
+            || q_inverse != components.q_inverse {
+            return Err(Error::InvalidKey("CRT exponents or coefficient do not match the private key".to_string()));
+        }
+        Ok(RsaPrivateKey::new(modulo, exponent, Some(components)))
+    }
+
+    pub fn prepare(&self) -> PreparedPrivateKey<'_> {
+        PreparedPrivateKey {
+            key: self,
+            operation: RsaOperation::private(&self.modulo, &self.exponent, self.components.as_ref())
+        }
+    }
+
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.prepare().decrypt_bytes(data)
+    }
+
+    // Secret encapsulated by encapsulate of the public key of the pair
+    pub fn decapsulate(&self, encapsulated: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+        self.prepare().operation.decapsulate(encapsulated)
+    }
+
+    pub fn decryption_chunk_size(&self) -> usize {
+        modulo_size_bytes(&self.modulo)
+    }
+
+    // RSASSA-PKCS1-v1_5 signature of a SHA-256 digest, needs at least a 512 bit key
+    pub fn sign_digest(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.prepare().operation.sign_digest(digest)
+    }
+}
+
+impl KeyPair {
+
+    pub fn generate(key_size: u16) -> Result<KeyPair, Error> {
+        check_key_size(key_size)?;
+        let first_primes = primes::primes(1000);
+        let prime_bits = (key_size / 2) as usize;
+        let p = SecretBigInt::new(find_random_prime(prime_bits, &first_primes));
+        let mut q = SecretBigInt::new(find_random_prime(prime_bits, &first_primes));
+        while q == p {
+            q = SecretBigInt::new(find_random_prime(prime_bits, &first_primes));
+        }
+        KeyPair::from_primes(p, q)
+    }
+
+    // Same as generate, but the primes are provable and come with their Pocklington certificates
+    pub fn generate_with_certificates(key_size: u16) -> Result<(KeyPair, PrimeCertificate, PrimeCertificate), Error> {
+        check_key_size(key_size)?;
+        let prime_bits = (key_size / 2) as usize;
+        let p_certificate = find_random_provable_prime(prime_bits)?;
+        let mut q_certificate = find_random_provable_prime(prime_bits)?;
+        while q_certificate.prime() == p_certificate.prime() {
+            q_certificate = find_random_provable_prime(prime_bits)?;
+        }
+        let p = SecretBigInt::new(p_certificate.prime().clone());
+        let q = SecretBigInt::new(q_certificate.prime().clone());
+        Ok((KeyPair::from_primes(p, q)?, p_certificate, q_certificate))
+    }
+
+    /*
+     * Key pair of the public key whose modulo is the product of the distinct primes p and q, for example once
+     * the modulo has been factored
+     */
+    pub fn from_factors(public_key: &RsaPublicKey, p: BigInt, q: BigInt) -> Result<KeyPair, Error> {
+        if &p * &q != public_key.modulo || p == q || !primes::is_probable_prime(&p) || !primes::is_probable_prime(&q) {
+            return Err(Error::InvalidKey("Modulo is not the product of the two distinct primes".to_string()));
+        }
+        let components = PrivateComponents::new(public_key.exponent.clone(), SecretBigInt::new(p), SecretBigInt::new(q))?;
+        let private_exponent = find_private_key(&components.totient_function(), &public_key.exponent)
+            .ok_or(Error::InvalidKey("Public exponent is not coprime with the totient of the modulo".to_string()))?;
+        Ok(KeyPair {
+            public_key: public_key.clone(),
+            private_key: RsaPrivateKey::new(public_key.modulo.clone(), private_exponent, Some(components))
+        })
+    }
+
+    fn from_primes(p: SecretBigInt, q: SecretBigInt) -> Result<KeyPair, Error> {
+        let public_exponent: BigInt = BigInt::from_u32(PUBLIC_EXPONENT)
+            .ok_or(Error::InvalidKey(format!("Cannot convert {} to BigInt", PUBLIC_EXPONENT)))?;
+
+        let n: BigInt = p.multiply(&q).expose();
+        let components = PrivateComponents::new(public_exponent.clone(), p, q)?;
+        let private_exponent = find_private_key(&components.totient_function(), &public_exponent)
+            .ok_or(Error::InvalidKey("Public exponent is not coprime with the totient of the modulo".to_string()))?;
+
+        Ok(KeyPair {
+            public_key: RsaPublicKey::new(n.clone(), public_exponent),
+            private_key: RsaPrivateKey::new(n, private_exponent, Some(components))
+        })
+    }
+}
+
+/*
+ * SHA-256 of the big-endian bytes of the modulo. Only the modulo is hashed, so that it can be computed from both keys
+ * of the pair, including private keys without the public exponent
+ */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct KeyFingerprint(pub [u8; 32]);
+
+impl KeyFingerprint {
+
+    fn of_modulo(modulo: &BigInt) -> KeyFingerprint {
+        KeyFingerprint(Sha256::digest(modulo.to_bytes_be().1).into())
+    }
+}
+
+impl fmt::Display for KeyFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SHA256:")?;
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+fn ensure_rsa_algorithm(algorithm: &[u64]) -> Result<(), Error> {
+    if algorithm != pkcs8::RSA_IDENTIFIER {
+        let identifier = algorithm.iter().map(|component| component.to_string()).collect::<Vec<_>>().join(".");
+        return Err(Error::UnsupportedAlgorithm(identifier));
+    }
+    Ok(())
+}
+
+fn modulo_size_bytes(modulo: &BigInt) -> usize {
+    modulo.to_bytes_be().1.len()
+}
+
+// SEQUENCE { modulo, exponent } without components, PKCS#1 RSAPrivateKey with them
+fn private_key_as_bytes(modulo: &BigInt, exponent: &SecretBigInt, components: Option<&PrivateComponents>) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            match components {
+                Some(components) => {
+                    let (p_minus_one, q_minus_one) = components.minus_one();
+                    writer.next().write_u32(0);
+                    write_bigint(writer.next(), modulo);
+                    write_bigint(writer.next(), &components.public_exponent);
+                    for number in [exponent, &components.p, &components.q, &exponent.remainder(&p_minus_one),
+                            &exponent.remainder(&q_minus_one), &components.q_inverse] {
+                        write_secret_bigint(writer.next(), number);
+                    }
+                },
+                None => {
+                    write_bigint(writer.next(), modulo);
+                    write_secret_bigint(writer.next(), exponent);
+                }
+            }
+        })
+    })
+}
+
+// Big-endian bytes of the number, padded with leading zeros to the size
+fn padded_bytes(number: &SecretBigInt, size: usize) -> Zeroizing<Vec<u8>> {
+    let limb_bytes = number.to_bytes_be();
+    let bytes = &limb_bytes[limb_bytes.iter().take_while(|&&byte| byte == 0).count()..];
+    // Allocated once, so that growing the buffer does not leave copies of the data behind
+    let mut padded_bytes = Zeroizing::new(Vec::with_capacity(cmp::max(size, bytes.len())));
+    padded_bytes.resize(size.saturating_sub(bytes.len()), 0);
+    padded_bytes.extend_from_slice(bytes);
+    padded_bytes
+}
+
+fn write_bigint(writer: yasna::DERWriter, number: &BigInt) {
+    let (sign, bytes) = number.to_bytes_be();
+    let bytes = Zeroizing::new(bytes);
+    writer.write_bigint_bytes(&bytes, sign != Sign::Minus);
+}
+
+fn read_bigint(reader: yasna::BERReader) -> Result<BigInt, ASN1Error> {
+    let (bytes, is_positive) = reader.read_bigint_bytes()?;
+    let bytes = Zeroizing::new(bytes);
+    Ok(BigInt::from_bytes_be(if is_positive { Sign::Plus } else { Sign::Minus }, &bytes))
+}
+
+// Numbers of private keys are never negative
+fn read_secret_bigint(reader: yasna::BERReader) -> Result<SecretBigInt, ASN1Error> {
+    let (bytes, is_positive) = reader.read_bigint_bytes()?;
+    let bytes = Zeroizing::new(bytes);
+    if !is_positive {
+        return Err(ASN1Error::new(yasna::ASN1ErrorKind::Invalid));
+    }
+    Ok(SecretBigInt::from_bytes_be(&bytes))
+}
+
+fn write_secret_bigint(writer: yasna::DERWriter, number: &SecretBigInt) {
+    writer.write_bigint_bytes(&number.to_bytes_be(), true);
+}
+
+/*
+ * Components read from a key file are checked against each other, an inconsistent key would otherwise only show up
+ * as a fault of every private key operation
+ */
+fn validate_private_components(modulo: &BigInt, exponent: &SecretBigInt, components: &PrivateComponents) -> Result<(), Error> {
+    let one = SecretBigInt::from(1);
+    if components.p == one || components.q == one || components.p.multiply(&components.q) != SecretBigInt::new(modulo.clone()) {
+        return Err(Error::InvalidKey("Modulo is not the product of p and q".to_string()));
+    }
+    // e * d = 1 (mod lambda(n)) where lambda(n) = lcm(p - 1, q - 1), checked modulo p - 1 and q - 1 without a gcd
+    let (p_minus_one, q_minus_one) = components.minus_one();
+    let product = SecretBigInt::new(components.public_exponent.clone()).multiply(exponent);
+    if product.remainder(&p_minus_one) != one || product.remainder(&q_minus_one) != one {
+        return Err(Error::InvalidKey("Private exponent is not the inverse of the public exponent".to_string()));
+    }
+    Ok(())
+}
+
+/*
+ * Legacy private keys only store the modulo and the private exponent. Assuming the default public exponent,
+ * e * d - 1 is a multiple of lambda(n) and the modulo can be factored with the same idea as in the Miller-Rabin test:
+ * a non-trivial square root x of 1 (mod n) gives a factor gcd(x - 1, n)
+ */
+fn recover_private_components(modulo: &BigInt, private_exponent: &SecretBigInt) -> Option<PrivateComponents> {
+    const ATTEMPTS: usize = 100;
+    let public_exponent = BigInt::from(PUBLIC_EXPONENT);
+    let one = SecretBigInt::from(1);
+    let mut rng = rand::thread_rng();
+    if private_exponent.limbs().is_empty() || modulo < &BigInt::from(4) || modulo % 2 == BigInt::zero() {
+        return None;
+    }
+    // k = e * d - 1 = 2 ^ s * t for an odd t, y = g ^ t is squared s times. s is not a secret, it only tells how many
+    // times 2 divides lambda(n)
+    let k = SecretBigInt::from(PUBLIC_EXPONENT as u64).multiply(private_exponent).subtract(&one);
+    let trailing_zeros = k.trailing_zeros()?;
+    let odd_part = k.shift_right(trailing_zeros);
+    let modulus = ConstantTimeModulus::for_modulo(modulo);
+    let modulo_minus_one: BigInt = modulo - 1;
+    let minus_one = SecretBigInt::new(modulo_minus_one.clone());
+    for _ in 0..ATTEMPTS {
+        let g = SecretBigInt::new(rng.gen_bigint_range(&BigInt::from(2), &modulo_minus_one));
+        let mut y = modulus.secret_exponent(&g, &odd_part);
+        for _ in 0..trailing_zeros {
+            if y == one || y == minus_one {
+                break;
+            }
+            let x = modulus.secret_multiply(&y, &y);
+            if x == one {
+                let p = modulus.secret_gcd(&y.subtract(&one));
+                let q = SecretBigInt::new(modulo.clone()).divide_exact(&p);
+                // The factor found first is random, recovering the same key twice should give the same components
+                let (p, q) = SecretBigInt::larger_first(p, q);
+                return PrivateComponents::new(public_exponent, p, q).ok();
+            }
+            y = x;
+        }
+    }
+    log::warn!("Could not recover the factors of the modulo of a legacy private key");
+    None
+}
+
+/*
+ * d = e ^ (-1) mod phi(n) without the extended Euclidean algorithm on the secret phi(n) (Arazi's formula):
+ * with t = phi(n) ^ (-1) mod e, 1 + phi(n) * (e - t) is a multiple of e and divided by e it is d.
+ * Only phi(n) mod e is inverted, modulo the public and odd e. None when e is even or not coprime with phi(n)
+ */
+fn find_private_key(totient_function: &SecretBigInt, public_exponent: &BigInt) -> Option<SecretBigInt> {
+    if public_exponent <= &BigInt::one() || public_exponent % 2 == BigInt::zero() {
+        return None;
+    }
+    let t = ConstantTimeModulus::for_modulo(public_exponent).secret_inverse(totient_function)?;
+    let e = SecretBigInt::new(public_exponent.clone());
+    let multiple = totient_function.multiply(&e.subtract(&t)).add(&SecretBigInt::from(1));
+    Some(multiple.divide_exact(&e))
 }
 
 const PUBLIC_EXPONENT: u32 = 65537;
 
-fn generate_random_bytes(size: usize) -> Vec<u8> {
-    let mut result = vec![0u8; size];
+// Two primes of prime_certificate::MINIMUM_PRIME_BITS, keys this small are only good to demonstrate factoring
+pub const MINIMUM_KEY_SIZE: u16 = 2 * prime_certificate::MINIMUM_PRIME_BITS as u16;
+
+fn check_key_size(key_size: u16) -> Result<(), Error> {
+    if key_size < MINIMUM_KEY_SIZE {
+        return Err(Error::InvalidKey(format!("Key size {} is too small, at least {} bits are needed", key_size, MINIMUM_KEY_SIZE)));
+    }
+    Ok(())
+}
+
+fn generate_random_bytes(size: usize) -> Zeroizing<Vec<u8>> {
+    let mut result = Zeroizing::new(vec![0u8; size]);
     let mut rng = rand::thread_rng();
     rng.fill(&mut result[..]);
     result
 }
 
-fn is_prime(n: &BigInt, first_primes: &Vec<usize>) -> bool {
+fn is_prime(n: &BigInt, first_primes: &[usize]) -> bool {
     if first_primes.iter().any(|prime| n % prime == BigInt::zero()) {
         false
     } else {
@@ -110,225 +553,668 @@ fn is_prime(n: &BigInt, first_primes: &Vec<usize>) -> bool {
     }
 }
 
-fn find_random_prime(prime_bits: usize, first_primes: &Vec<usize>) -> BigInt {
+fn find_random_prime(prime_bits: usize, first_primes: &[usize]) -> BigInt {
     let prime_bytes = prime_bits / 8;
     let mut random_number = BigInt::from_bytes_be(Sign::Plus, &generate_random_bytes(prime_bytes));
     //Making sure that the number is large enough
     let bit_mask = BigInt::one() << (prime_bits - 1);
-    random_number = random_number | bit_mask;
+    random_number |= bit_mask;
+    // Candidates are updated in place, so that no copies of the prime are left behind
     let mut prime_candidate = random_number;
     if &prime_candidate % 2 == BigInt::zero() {
-        prime_candidate = &prime_candidate + BigInt::one()
+        prime_candidate += 1;
     }
-    while !is_prime(&prime_candidate, &first_primes) {
-        prime_candidate = &prime_candidate + 2;
+    // p - 1 should be coprime with the public exponent, otherwise there is no private exponent
+    while (&prime_candidate - 1u8) % PUBLIC_EXPONENT == BigInt::zero() || !is_prime(&prime_candidate, first_primes) {
+        prime_candidate += 2;
     }
     prime_candidate
 }
 
-pub fn generate_keys(key_size: u16) -> Result<(Key, Key), anyhow::Error> {
-    let first_primes = primes::primes(1000);
-    let prime_bits = (key_size / 2) as usize;
-    let p = find_random_prime(prime_bits, &first_primes);
-    let mut q = find_random_prime(prime_bits, &first_primes);
-    while q == p {
-        q = find_random_prime(prime_bits, &first_primes);
+fn find_random_provable_prime(prime_bits: usize) -> Result<PrimeCertificate, Error> {
+    let mut certificate = prime_certificate::random_provable_prime(prime_bits)?;
+    // p - 1 = 2 * r * q is not guaranteed to be coprime with the public exponent
+    while (certificate.prime() - 1u8) % PUBLIC_EXPONENT == BigInt::zero() {
+        certificate = prime_certificate::random_provable_prime(prime_bits)?;
     }
-    let public_exponent: BigInt = BigInt::from_u32(PUBLIC_EXPONENT).ok_or(anyhow!("Cannot convert {} to BigInt", PUBLIC_EXPONENT))?;
+    Ok(certificate)
+}
 
-    let n: BigInt = &p * &q;
-    let totient_function = (&p - 1) * (&q - 1);
-    let private_exponent = find_private_key(&totient_function, &public_exponent);
-    assert_eq!((&public_exponent * &private_exponent) % totient_function, BigInt::one());
+const ENCRYPTED_CHUNK_PREFIX: u8 = 128;
+
+// DER encoded DigestInfo of SHA-256 up to the digest itself, see https://datatracker.ietf.org/doc/html/rfc8017#section-9.2
+const SHA256_DIGEST_INFO_PREFIX: [u8; 19] = [0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04,
+    0x02, 0x01, 0x05, 0x00, 0x04, 0x20];
+// At least 8 bytes of 0xff padding between 0x00 0x01 and 0x00 DigestInfo
+const MINIMAL_SIGNATURE_PADDING: usize = 11;
 
-    let public_key = Key {
-        exponent: public_exponent,
-        modulo: n.clone(),
-        key_type: KeyType::Public
-    };
-    let private_key = Key {
-        exponent: private_exponent,
-        modulo: n,
-        key_type: KeyType::Private
-    };
-    Ok((public_key, private_key))
+/*
+ * Public key together with the precomputed modulus context, built once and reused for all the encrypted chunks
+ */
+pub struct PreparedPublicKey<'a> {
+    key: &'a RsaPublicKey,
+    operation: RsaOperation<'a>
 }
 
-fn encrypt_number(number_to_encrypt: &BigInt, key: &Key) -> BigInt {
-    modulo_arithmetic::exponent(number_to_encrypt, &key.exponent, &key.modulo)
+/*
+ * Private key together with the precomputed CRT context, built once and reused for all the decrypted chunks
+ */
+pub struct PreparedPrivateKey<'a> {
+    key: &'a RsaPrivateKey,
+    operation: RsaOperation<'a>
 }
 
-const ENCRYPTED_CHUNK_PREFIX: u8 = 128;
+// Exponentiation modulo the key modulo shared by the public, private and legacy keys
+struct RsaOperation<'a> {
+    modulo: &'a BigInt,
+    arithmetic: KeyArithmetic<'a>,
+    blinding: Blinding,
+    modulo_size_bytes: usize
+}
+
+/*
+ * Countermeasure against timing attacks on the private key operations:
+ * base blinding computes (c * r ^ e) ^ d * r ^ (-1) instead of c ^ d with a fresh random r,
+ * exponent blinding additionally uses d + k * phi(n) with a fresh random k instead of d.
+ * Requires the public exponent and the factors of the modulo, so it is not available for legacy private keys
+ */
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Blinding {
+    None,
+    Base,
+    #[default]
+    BaseAndExponent
+}
+
+const EXPONENT_BLINDING_BITS: u64 = 64;
 
-fn encrypt_chunk(data: &[u8], key: &Key, modulo_size_bytes: usize) -> Vec<u8> {
-    let number_to_encrypt = BigInt::from_bytes_be(Sign::Plus, &data);
-    let encrypted = encrypt_number(&number_to_encrypt, key).to_bytes_be();
-    let mut result_bytes = encrypted.1;
-    if result_bytes.len() < modulo_size_bytes {
-        let mut padded_result_bytes = vec![0u8; modulo_size_bytes - result_bytes.len()];
-        padded_result_bytes.extend(result_bytes);
-        result_bytes = padded_result_bytes;
+// Public key operations can use the faster variable-time arithmetic, private exponent must never leak through timing
+enum KeyArithmetic<'a> {
+    VariableTime(ModulusContext, &'a BigInt),
+    // Legacy private keys without the factors of the modulo
+    ConstantTime(ConstantTimeModulus, SecretBigInt),
+    ChineseRemainder(Box<ChineseRemainderContext>)
+}
+
+/*
+ * Private key operation split into exponentiations modulo p and q which are combined with Garner's formula,
+ * roughly 3-4 times faster than a single exponentiation modulo n. Everything derived from the secret components is
+ * computed once, so that the operations themselves only use the limbs of the secrets. The context keeps its own copies
+ * of the components it needs, so it can also be built from components recovered for a legacy key
+ */
+struct ChineseRemainderContext {
+    n: ConstantTimeModulus,
+    p: ConstantTimeModulus,
+    q: ConstantTimeModulus,
+    public_exponent: BigInt,
+    q_factor: SecretBigInt,
+    q_inverse: SecretBigInt,
+    // p - 1 and q - 1 for the exponent blinding
+    p_minus_one: SecretBigInt,
+    q_minus_one: SecretBigInt,
+    // d mod (p - 1) and d mod (q - 1)
+    dp: SecretBigInt,
+    dq: SecretBigInt
+}
+
+/*
+ * A computational fault in one of the halves of a CRT private key operation (Bellcore attack) gives a result which is
+ * correct modulo one prime and wrong modulo the other, gcd(result ^ e - c, n) then reveals a factor of n.
+ * Every CRT result is checked with the public exponent and is never released when the check fails
+ */
+#[derive(Debug, PartialEq, Clone)]
+pub struct FaultDetected;
+
+impl fmt::Display for FaultDetected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Private key operation produced an inconsistent result, the result has been discarded")
     }
-    result_bytes
 }
 
-pub fn encryption_chunk_size(key: &Key) -> usize {
-    let modulo_size_bytes = key.modulo.to_bytes_be().1.len();
-    // leave one byte for ENCRYPTED_PREFIX and one byte to make sure that modulo is not overflown
-    cmp::max(modulo_size_bytes - 2, 1)
+impl std::error::Error for FaultDetected {}
+
+
+impl<'a> PreparedPublicKey<'a> {
+
+    pub fn key(&self) -> &RsaPublicKey {
+        self.key
+    }
+
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.operation.encrypt_bytes(data)
+    }
 }
 
-pub fn decryption_chunk_size(key:&Key) -> usize {
-    key.modulo.to_bytes_be().1.len()
+impl<'a> PreparedPrivateKey<'a> {
+
+    pub fn with_blinding(mut self, blinding: Blinding) -> PreparedPrivateKey<'a> {
+        self.operation.blinding = blinding;
+        self
+    }
+
+    pub fn key(&self) -> &RsaPrivateKey {
+        self.key
+    }
+
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.operation.decrypt_bytes(data)
+    }
 }
 
-pub fn encrypt_bytes(data: &Vec<u8>, key: &Key) -> Vec<u8> {
-    let modulo_size_bytes = key.modulo.to_bytes_be().1.len();
-    // leave one byte for ENCRYPTED_PREFIX and one byte to make sure that modulo is not overflown
-    let block_size_bytes = cmp::max(modulo_size_bytes - 2, 1);
+impl<'a> RsaOperation<'a> {
+
+    fn public(modulo: &'a BigInt, exponent: &'a BigInt) -> RsaOperation<'a> {
+        RsaOperation::new(modulo, KeyArithmetic::VariableTime(ModulusContext::for_modulo(modulo), exponent))
+    }
+
+    fn private(modulo: &'a BigInt, exponent: &SecretBigInt, components: Option<&PrivateComponents>) -> RsaOperation<'a> {
+        let arithmetic = match components {
+            Some(components) => {
+                KeyArithmetic::ChineseRemainder(Box::new(ChineseRemainderContext::new(modulo, exponent, components)))
+            },
+            None => {
+                log::warn!("Private key does not contain the public exponent and the factors of the modulo, blinding is disabled");
+                KeyArithmetic::ConstantTime(ConstantTimeModulus::for_modulo(modulo), exponent.duplicate())
+            }
+        };
+        RsaOperation::new(modulo, arithmetic)
+    }
+
+    fn new(modulo: &'a BigInt, arithmetic: KeyArithmetic<'a>) -> RsaOperation<'a> {
+        RsaOperation {
+            modulo,
+            arithmetic,
+            blinding: Blinding::default(),
+            modulo_size_bytes: modulo_size_bytes(modulo)
+        }
+    }
+
+    fn encrypt_number(&self, number_to_encrypt: &SecretBigInt) -> Result<SecretBigInt, FaultDetected> {
+        match &self.arithmetic {
+            // Public key operations do not involve any secret of the key
+            KeyArithmetic::VariableTime(context, exponent) => Ok(SecretBigInt::new(context.exponent(&number_to_encrypt.expose(), exponent))),
+            KeyArithmetic::ConstantTime(modulus, exponent) => Ok(modulus.secret_exponent(number_to_encrypt, exponent)),
+            KeyArithmetic::ChineseRemainder(context) => self.blinded_exponent(context, number_to_encrypt)
+        }
+    }
+
+    fn blinded_exponent(&self, context: &ChineseRemainderContext, number: &SecretBigInt) -> Result<SecretBigInt, FaultDetected> {
+        let public_exponent = &context.public_exponent;
+        let mut rng = rand::thread_rng();
+        let (blinded_number, r_inverse) = if self.blinding == Blinding::None {
+            (context.n.secret_modulo(number), SecretBigInt::from(1))
+        } else {
+            let (r, r_inverse) = loop {
+                let r = SecretBigInt::new(rng.gen_bigint_range(&BigInt::from(2), self.modulo));
+                if let Some(r_inverse) = context.n.secret_inverse(&r) {
+                    break (r, r_inverse);
+                }
+            };
+            let r_power = context.n.secret_exponent_with_public_power(&r, public_exponent);
+            (context.n.secret_multiply(number, &r_power), r_inverse)
+        };
+        let (k_p, k_q) = if self.blinding == Blinding::BaseAndExponent {
+            (SecretBigInt::new(BigInt::from(rng.gen_biguint(EXPONENT_BLINDING_BITS))), SecretBigInt::new(BigInt::from(rng.gen_biguint(EXPONENT_BLINDING_BITS))))
+        } else {
+            (SecretBigInt::from(0), SecretBigInt::from(0))
+        };
+        let blinded_result = context.exponent(&blinded_number, &k_p, &k_q);
+        // (c * r ^ e) ^ d = c ^ d * r (mod n)
+        if context.n.secret_exponent_with_public_power(&blinded_result, public_exponent) != blinded_number {
+            return Err(FaultDetected);
+        }
+        Ok(context.n.secret_multiply(&blinded_result, &r_inverse))
+    }
+
+    fn encrypt_chunk(&self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, FaultDetected> {
+        let number_to_encrypt = SecretBigInt::from_bytes_be(data);
+        let encrypted = self.encrypt_number(&number_to_encrypt)?;
+        Ok(padded_bytes(&encrypted, self.modulo_size_bytes))
+    }
+
+    // Random number below the modulo as bytes of the size of the modulo, together with the number encrypted
+    fn encapsulate(&self) -> Result<(Zeroizing<Vec<u8>>, Vec<u8>), Error> {
+        let number = SecretBigInt::new(rand::thread_rng().gen_bigint_range(&BigInt::zero(), self.modulo));
+        let secret = padded_bytes(&number, self.modulo_size_bytes);
+        let encapsulated = self.encrypt_chunk(&secret)?.to_vec();
+        Ok((secret, encapsulated))
+    }
+
+    fn decapsulate(&self, encapsulated: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+        if encapsulated.len() != self.modulo_size_bytes || BigInt::from_bytes_be(Sign::Plus, encapsulated) >= *self.modulo {
+            return Err(Error::Decryption(format!("Encapsulated secret does not match a key of {} bytes", self.modulo_size_bytes)));
+        }
+        Ok(self.encrypt_chunk(encapsulated)?)
+    }
+
+    fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        // leave one byte for ENCRYPTED_PREFIX and one byte to make sure that modulo is not overflown
+        let block_size_bytes = cmp::max(self.modulo_size_bytes - 2, 1);
+
+        let mut encrypted: Vec<u8> = Vec::new();
+        for chunk in data.chunks(block_size_bytes) {
+            let mut data_to_encrypt = Zeroizing::new(Vec::with_capacity(chunk.len() + 1));
+            data_to_encrypt.push(ENCRYPTED_CHUNK_PREFIX);
+            data_to_encrypt.extend_from_slice(chunk);
+            let encrypted_chunk = self.encrypt_chunk(&data_to_encrypt)?;
+            encrypted.extend_from_slice(&encrypted_chunk);
+        }
+        Ok(encrypted)
+    }
+
+    fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if !data.len().is_multiple_of(self.modulo_size_bytes) {
+            return Err(Error::Decryption(format!("Encrypted data length {} is not a multiple of the block size {}",
+                data.len(), self.modulo_size_bytes)));
+        }
+        // Decrypted data is never longer than the encrypted data, so the buffer is never reallocated
+        let mut decrypted: Vec<u8> = Vec::with_capacity(data.len());
+        for (block, chunk) in data.chunks(self.modulo_size_bytes).enumerate() {
+            if BigInt::from_bytes_be(Sign::Plus, chunk) >= *self.modulo {
+                return Err(Error::Decryption(format!("Block {} is not smaller than the modulo", block)));
+            }
+            let decrypted_data = self.encrypt_chunk(chunk)?;
+            // Wrong key or corrupted data, the chunk does not start with the prefix after the zero padding
+            match decrypted_data.iter().position(|&byte| byte != 0) {
+                Some(i) if decrypted_data[i] == ENCRYPTED_CHUNK_PREFIX => decrypted.extend(&decrypted_data[i + 1..]),
+                _ => return Err(Error::Decryption(format!("Block {} does not contain the expected prefix", block)))
+            }
+        }
+        Ok(decrypted)
+    }
+
+    // EMSA-PKCS1-v1_5: 0x00 0x01 0xff ... 0xff 0x00 DigestInfo
+    fn encode_digest(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let digest_info_size = SHA256_DIGEST_INFO_PREFIX.len() + digest.len();
+        if self.modulo_size_bytes < digest_info_size + MINIMAL_SIGNATURE_PADDING {
+            return Err(Error::InvalidKey(format!("Key of {} bytes is too small to sign", self.modulo_size_bytes)));
+        }
+        let mut encoded = vec![0x00, 0x01];
+        encoded.resize(self.modulo_size_bytes - digest_info_size - 1, 0xff);
+        encoded.push(0x00);
+        encoded.extend_from_slice(&SHA256_DIGEST_INFO_PREFIX);
+        encoded.extend_from_slice(digest);
+        Ok(encoded)
+    }
 
-    let mut encrypted: Vec<u8> = Vec::new();
-    for chunk in data.chunks(block_size_bytes) {
-        let mut data_to_encrypt: Vec<u8> = vec![ENCRYPTED_CHUNK_PREFIX];
-        data_to_encrypt.extend(chunk);
-        let encrypted_chunk = encrypt_chunk(&data_to_encrypt, &key, modulo_size_bytes);
-        encrypted.extend(encrypted_chunk);
+    fn sign_digest(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let encoded = self.encode_digest(digest)?;
+        Ok(self.encrypt_chunk(&encoded)?.to_vec())
+    }
+
+    fn verify_digest(&self, digest: &[u8; 32], signature: &[u8]) -> Result<(), Error> {
+        if signature.len() != self.modulo_size_bytes || BigInt::from_bytes_be(Sign::Plus, signature) >= *self.modulo {
+            return Err(Error::Signature(format!("Signature does not match a key of {} bytes", self.modulo_size_bytes)));
+        }
+        if *self.encrypt_chunk(signature)? != self.encode_digest(digest)? {
+            return Err(Error::Signature("Signature does not match the data".to_string()));
+        }
+        Ok(())
     }
-    encrypted
 }
 
-pub fn decrypt_bytes(data: &Vec<u8>, key: &Key) -> Vec<u8> {
-    let modulo_size_bytes = key.modulo.to_bytes_be().1.len();
-    let mut decrypted: Vec<u8> = Vec::new();
-    for chunk in data.chunks(modulo_size_bytes) {
-        let decrypted_data = encrypt_chunk(&chunk, &key, modulo_size_bytes);
-        let mut i = 0;
-        let mut has_found_prefix = decrypted_data[i] == ENCRYPTED_CHUNK_PREFIX;
-        while !has_found_prefix && decrypted_data[i] == 0 {
-            i = i + 1;
-            has_found_prefix = decrypted_data[i] == ENCRYPTED_CHUNK_PREFIX;
+impl ChineseRemainderContext {
+
+    fn new(modulo: &BigInt, exponent: &SecretBigInt, components: &PrivateComponents) -> ChineseRemainderContext {
+        let (p_minus_one, q_minus_one) = components.minus_one();
+        ChineseRemainderContext {
+            n: ConstantTimeModulus::for_modulo(modulo),
+            p: ConstantTimeModulus::for_secret_modulo(&components.p),
+            q: ConstantTimeModulus::for_secret_modulo(&components.q),
+            public_exponent: components.public_exponent.clone(),
+            q_factor: components.q.duplicate(),
+            q_inverse: components.q_inverse.duplicate(),
+            dp: exponent.remainder(&p_minus_one),
+            dq: exponent.remainder(&q_minus_one),
+            p_minus_one,
+            q_minus_one
+        }
+    }
+
+    // number ^ d mod n, the exponents modulo p and q are blinded with k_p * (p - 1) and k_q * (q - 1)
+    fn exponent(&self, number: &SecretBigInt, k_p: &SecretBigInt, k_q: &SecretBigInt) -> SecretBigInt {
+        let p_exponent = self.dp.add(&k_p.multiply(&self.p_minus_one));
+        let q_exponent = self.dq.add(&k_q.multiply(&self.q_minus_one));
+        #[allow(unused_mut)]
+        let mut m_p = self.p.secret_exponent(number, &p_exponent);
+        let m_q = self.q.secret_exponent(number, &q_exponent);
+        #[cfg(feature = "fault-injection")]
+        if fault_injection::take_injected_fault() {
+            m_p = SecretBigInt::new(m_p.expose() ^ BigInt::one());
         }
-        //assert!(has_found_prefix);
-        decrypted.extend(&decrypted_data[i + 1..]);
+        // Garner's formula: h = q ^ (-1) * (m_p - m_q) mod p, m = m_q + h * q, reduced without dividing by p
+        let difference = self.p.secret_subtract(&m_p, &m_q);
+        let h = self.p.secret_multiply(&self.q_inverse, &difference);
+        m_q.add(&h.multiply(&self.q_factor))
+    }
+}
+/*
+ * Test hook which simulates a computational fault: the next CRT operation on the current thread
+ * gets a flipped bit in the result of the exponentiation modulo p
+ */
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection {
+    use std::cell::Cell;
+
+    thread_local! {
+        static INJECT_FAULT: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub fn inject_fault_into_next_operation() {
+        INJECT_FAULT.with(|inject_fault| inject_fault.set(true));
+    }
+
+    pub(crate) fn take_injected_fault() -> bool {
+        INJECT_FAULT.with(|inject_fault| inject_fault.replace(false))
     }
-    decrypted
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
-    fn predefined_keys() -> (Key, Key) {
-        let public_key = Key {
-            exponent: BigInt::from_u32(65537).unwrap(),
-            modulo: BigInt::from_u64(404790586766519).unwrap(),
-            key_type: KeyType::Public
-        };
-        let private_key = Key {
-            exponent: BigInt::from_u64(375946200922409).unwrap(),
-            modulo: BigInt::from_u64(404790586766519).unwrap(),
-            key_type: KeyType::Private
-        };
-        (public_key, private_key)
+    const MODULO: u64 = 404790586766519;
+    const PRIVATE_EXPONENT: u64 = 375946200922409;
+
+    pub(crate) fn predefined_keys() -> KeyPair {
+        let modulo = BigInt::from_u64(MODULO).unwrap();
+        let private_exponent = SecretBigInt::from(PRIVATE_EXPONENT);
+        let components = recover_private_components(&modulo, &private_exponent);
+        KeyPair {
+            public_key: RsaPublicKey::new(modulo.clone(), BigInt::from_u32(65537).unwrap()),
+            private_key: RsaPrivateKey::new(modulo, private_exponent, components)
+        }
     }
 
-    fn generated_keys() -> (Key, Key) {
-        generate_keys(2048).unwrap()
+    fn generated_keys() -> KeyPair {
+        KeyPair::generate(2048).unwrap()
     }
 
-    fn get_random_bytes(size: usize) -> Vec<u8> {
+    fn without_components(private_key: &RsaPrivateKey) -> RsaPrivateKey {
+        RsaPrivateKey::new(private_key.modulo().clone(), private_key.exponent().duplicate(), None)
+    }
+
+    pub(crate) fn get_random_bytes(size: usize) -> Vec<u8> {
         let mut rng = rand::thread_rng();
         (0..size).map(|_| rng.gen_range(0..=255)).collect()
     }
 
     #[test]
-    fn should_serialize_key() {
-        let key = Key {
-            exponent: BigInt::from_u8(2).unwrap(),
-            modulo: BigInt::from_u8(13).unwrap(),
-            key_type: KeyType::Public
-        };
+    fn should_serialize_public_key() {
+        let key = RsaPublicKey::new(BigInt::from_u8(13).unwrap(), BigInt::from_u8(2).unwrap());
         assert_eq!(key.as_bytes(), vec![48u8, 6u8, 2u8, 1u8, 13u8, 2u8, 1u8, 2u8])
     }
 
     #[test]
-    fn should_deserialize_key() {
+    fn should_deserialize_public_key() {
         let key_bytes = vec![48u8, 6u8, 2u8, 1u8, 13u8, 2u8, 1u8, 2u8];
-        assert_eq!(Key::from_bytes(&key_bytes, KeyType::Public).unwrap(), Key {
-            exponent: BigInt::from_u8(2).unwrap(),
-            modulo: BigInt::from_u16(13).unwrap(),
-            key_type: KeyType::Public
-        })
+        assert_eq!(RsaPublicKey::from_bytes(&key_bytes).unwrap(), RsaPublicKey::new(BigInt::from_u16(13).unwrap(), BigInt::from_u8(2).unwrap()))
+    }
+
+    #[test]
+    fn should_serialize_and_deserialize_public_key() {
+        let KeyPair { public_key, .. } = predefined_keys();
+        assert_eq!(RsaPublicKey::deserialize(&public_key.serialize()).unwrap(), public_key);
+    }
+
+    #[test]
+    fn should_recover_components_of_legacy_private_key() {
+        let KeyPair { private_key, .. } = predefined_keys();
+        let components = private_key.components().unwrap();
+        assert_eq!(components.public_exponent, BigInt::from(65537));
+        assert_eq!(components.p.expose() * components.q.expose(), *private_key.modulo());
+        assert!(components.p.expose() > BigInt::one() && components.q.expose() > BigInt::one());
+    }
+
+    #[test]
+    fn should_deserialize_legacy_private_key() {
+        let KeyPair { private_key, .. } = predefined_keys();
+        let legacy_key = without_components(&private_key);
+        let deserialized = RsaPrivateKey::from_bytes(&legacy_key.as_bytes()).unwrap();
+        assert_eq!(deserialized.exponent(), private_key.exponent());
+        assert!(deserialized.components().is_some());
     }
 
     #[test]
-    fn should_return_same_key_when_running_as_bytes_from_bytes_in_succession() {
-        let key = Key {
-            exponent: BigInt::from_u8(2).unwrap(),
-            modulo: BigInt::from_u8(13).unwrap(),
-            key_type: KeyType::Public
+    fn should_serialize_and_deserialize_private_key_with_components() {
+        let KeyPair { private_key, .. } = KeyPair::generate(512).unwrap();
+        assert_eq!(RsaPrivateKey::deserialize(&private_key.serialize()).unwrap(), private_key);
+    }
+
+    #[test]
+    fn should_reject_private_key_with_inconsistent_components() {
+        let KeyPair { private_key, .. } = KeyPair::generate(512).unwrap();
+        let components = private_key.components().unwrap();
+        let with_components = |exponent: &BigInt, p: &SecretBigInt, q: &SecretBigInt, q_inverse: BigInt| {
+            let components = PrivateComponents {
+                public_exponent: components.public_exponent.clone(), p: p.duplicate(), q: q.duplicate(), q_inverse: SecretBigInt::new(q_inverse)
+            };
+            private_key_as_bytes(private_key.modulo(), &SecretBigInt::new(exponent.clone()), Some(&components))
         };
-        assert_eq!(Key::from_bytes(&key.as_bytes(), key.key_type.clone()).unwrap(), key)
+        let exponent = &private_key.exponent().expose();
+        let q_inverse = components.q_inverse.expose();
+        assert!(RsaPrivateKey::from_bytes(&with_components(exponent, &components.p, &components.q, q_inverse.clone())).is_ok());
+        for bytes in [
+            with_components(exponent, &components.p, &components.q, BigInt::zero()),
+            with_components(&(exponent + 2), &components.p, &components.q, q_inverse.clone()),
+            with_components(exponent, &components.p, &SecretBigInt::new(components.q.expose() + 2), q_inverse.clone()),
+            with_components(exponent, &SecretBigInt::from(1), &SecretBigInt::new(private_key.modulo().clone()), BigInt::zero())
+        ] {
+            assert!(matches!(RsaPrivateKey::from_bytes(&bytes), Err(Error::InvalidKey(_))));
+        }
+    }
+
+    #[test]
+    fn should_not_deserialize_key_of_another_type() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        assert!(RsaPrivateKey::deserialize(&public_key.serialize()).is_err());
+        assert!(RsaPublicKey::deserialize(&private_key.serialize()).is_err());
+    }
+
+    #[test]
+    fn should_report_kind_of_deserialization_error() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        assert!(matches!(RsaPrivateKey::deserialize(&public_key.serialize()), Err(Error::InvalidKey(_))));
+        assert!(matches!(RsaPublicKey::deserialize(b"-----BEGIN PUBLIC KEY-----\n"), Err(Error::Pem(_))));
+        let truncated_key = pem::serialize_block(&[48u8, 6u8, 2u8, 1u8], PUBLIC_KEY_LABEL);
+        assert!(matches!(RsaPublicKey::deserialize(&truncated_key), Err(Error::Asn1(_))));
+        let elliptic_curve_key_info = PrivateKeyInfo {
+            version: 0,
+            private_key_algorithm: pkcs8::AlgorithmIdentifier {
+                algorithm: vec![1, 2, 840, 10045, 2, 1]
+            },
+            private_key: Zeroizing::new(private_key.as_bytes())
+        };
+        let elliptic_curve_key = pem::serialize_block(&elliptic_curve_key_info.serialize(), PRIVATE_KEY_LABEL);
+        match RsaPrivateKey::deserialize(&elliptic_curve_key) {
+            Err(Error::UnsupportedAlgorithm(algorithm)) => assert_eq!(algorithm, "1.2.840.10045.2.1"),
+            result => panic!("Unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn should_derive_public_key_from_private_key() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        assert_eq!(private_key.public_key(), Some(public_key));
+        assert_eq!(without_components(&private_key).public_key(), None);
+    }
+
+    #[test]
+    fn blinded_and_unblinded_decryption_should_match() {
+        for KeyPair { public_key, private_key } in [predefined_keys(), KeyPair::generate(1024).unwrap()] {
+            let input: Vec<u8> = get_random_bytes(200);
+            let encrypted = public_key.encrypt_bytes(&input).unwrap();
+            for blinding in [Blinding::None, Blinding::Base, Blinding::BaseAndExponent] {
+                assert_eq!(private_key.prepare().with_blinding(blinding).decrypt_bytes(&encrypted).unwrap(), input);
+            }
+        }
+    }
+
+    #[test]
+    fn should_decrypt_with_and_without_chinese_remainder_theorem() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(1024).unwrap();
+        let legacy_private_key = without_components(&private_key);
+        let input: Vec<u8> = get_random_bytes(200);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        assert_eq!(private_key.decrypt_bytes(&encrypted).unwrap(), input);
+        assert_eq!(legacy_private_key.decrypt_bytes(&encrypted).unwrap(), input);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn should_detect_fault_in_chinese_remainder_theorem_half() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(1024).unwrap();
+        let input: Vec<u8> = get_random_bytes(10);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        for blinding in [Blinding::None, Blinding::BaseAndExponent] {
+            fault_injection::inject_fault_into_next_operation();
+            let error = private_key.prepare().with_blinding(blinding).decrypt_bytes(&encrypted).unwrap_err();
+            assert!(matches!(error, Error::Fault(FaultDetected)));
+        }
+        assert_eq!(private_key.decrypt_bytes(&encrypted).unwrap(), input);
+    }
+
+    #[test]
+    fn should_not_print_private_exponent() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        assert!(format!("{:?}", public_key).contains("65537"));
+        let printed_private_key = format!("{:?}", private_key);
+        assert!(!printed_private_key.contains(&PRIVATE_EXPONENT.to_string()));
+        assert!(printed_private_key.contains("[REDACTED]"));
     }
 
     #[test]
     fn should_find_private_key() {
-        assert_eq!(find_private_key(&BigInt::from_u32(3120).unwrap(), &BigInt::from_u32(17).unwrap()), BigInt::from_u32(2753).unwrap())
+        assert_eq!(find_private_key(&SecretBigInt::from(3120), &BigInt::from(17)), Some(SecretBigInt::from(2753)));
+        assert_eq!(find_private_key(&SecretBigInt::from(3120), &BigInt::from(13)), None);
+        assert_eq!(find_private_key(&SecretBigInt::from(3120), &BigInt::from(16)), None)
+    }
+
+    #[test]
+    fn should_reject_too_small_key_size() {
+        for key_size in [0, 2, MINIMUM_KEY_SIZE - 1] {
+            assert!(matches!(KeyPair::generate(key_size), Err(Error::InvalidKey(_))));
+            assert!(matches!(KeyPair::generate_with_certificates(key_size), Err(Error::InvalidKey(_))));
+        }
+    }
+
+    #[test]
+    fn should_generate_keys_from_certified_primes() {
+        let (KeyPair { public_key, private_key }, p_certificate, q_certificate) = KeyPair::generate_with_certificates(512).unwrap();
+        assert_eq!(p_certificate.verify().unwrap() * q_certificate.verify().unwrap(), *public_key.modulo());
+        let input: Vec<u8> = get_random_bytes(100);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        assert_eq!(private_key.decrypt_bytes(&encrypted).unwrap(), input);
     }
 
     #[test]
     fn should_encrypt_and_decrypt_a_number_using_predefined_keys() {
-        let (public_key, private_key) = predefined_keys();
-        let original_number = BigInt::from_u64(65).unwrap();
-        let encrypted = encrypt_number(&original_number, &public_key);
-        let decrypted = encrypt_number(&encrypted, &private_key);
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let original_number = SecretBigInt::from(65);
+        let encrypted = public_key.prepare().operation.encrypt_number(&original_number).unwrap();
+        let decrypted = private_key.prepare().operation.encrypt_number(&encrypted).unwrap();
         assert_eq!(original_number, decrypted);
     }
 
     #[test]
     fn should_encrypt_and_decrypt_a_vec_of_bytes_using_predefined_keys() {
-        let (public_key, private_key) = predefined_keys();
+        let KeyPair { public_key, private_key } = predefined_keys();
         let input: Vec<u8> = get_random_bytes(1000);
-        let encrypted = encrypt_bytes(&input, &public_key);
-        let decrypted = decrypt_bytes(&encrypted, &private_key);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        let decrypted = private_key.decrypt_bytes(&encrypted).unwrap();
         assert_eq!(input, decrypted);
     }
 
+    #[test]
+    fn should_encrypt_and_decrypt_chunks_with_prepared_keys() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let (prepared_public_key, prepared_private_key) = (public_key.prepare(), private_key.prepare());
+        for input in [get_random_bytes(10), get_random_bytes(1000)] {
+            let encrypted = prepared_public_key.encrypt_bytes(&input).unwrap();
+            assert_eq!(prepared_private_key.decrypt_bytes(&encrypted).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn should_fail_to_decrypt_corrupted_data() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let encrypted = public_key.encrypt_bytes(&get_random_bytes(100)).unwrap();
+        let truncated = &encrypted[..encrypted.len() - 1];
+        assert!(matches!(private_key.decrypt_bytes(truncated), Err(Error::Decryption(_))));
+        assert!(matches!(private_key.decrypt_bytes(&[0u8; 7]), Err(Error::Decryption(_))));
+        assert!(matches!(private_key.decrypt_bytes(&[255u8; 7]), Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn should_fail_to_decrypt_with_wrong_key() {
+        let KeyPair { public_key, .. } = KeyPair::generate(512).unwrap();
+        let KeyPair { private_key, .. } = KeyPair::generate(512).unwrap();
+        let encrypted = public_key.encrypt_bytes(&get_random_bytes(1000)).unwrap();
+        assert!(matches!(private_key.decrypt_bytes(&encrypted), Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn should_sign_and_verify_digest() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let digest: [u8; 32] = Sha256::digest(b"The quick brown fox jumps over the lazy dog").into();
+        let signature = private_key.sign_digest(&digest).unwrap();
+        assert_eq!(signature.len(), public_key.signature_size());
+        public_key.verify_digest(&digest, &signature).unwrap();
+        let mut other_digest = digest;
+        other_digest[0] ^= 1;
+        assert!(matches!(public_key.verify_digest(&other_digest, &signature), Err(Error::Signature(_))));
+        let KeyPair { public_key: other_public_key, .. } = KeyPair::generate(512).unwrap();
+        assert!(matches!(other_public_key.verify_digest(&digest, &signature), Err(Error::Signature(_))));
+        assert!(matches!(predefined_keys().private_key.sign_digest(&digest), Err(Error::InvalidKey(_))));
+    }
+
+    #[test]
+    fn should_decapsulate_encapsulated_secret() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let (secret, encapsulated) = public_key.encapsulate().unwrap();
+        assert_eq!(secret.len(), 64);
+        assert_eq!(encapsulated.len(), 64);
+        assert_eq!(private_key.decapsulate(&encapsulated).unwrap(), secret);
+        assert_ne!(public_key.encapsulate().unwrap().0, secret);
+        assert!(matches!(private_key.decapsulate(&encapsulated[1..]), Err(Error::Decryption(_))));
+        assert!(matches!(private_key.decapsulate(&[0xff; 64]), Err(Error::Decryption(_))));
+    }
+
     #[test]
     fn should_encrypt_and_decrypt_a_string_using_predefined_keys() {
-        let (public_key, private_key) = predefined_keys();
+        let KeyPair { public_key, private_key } = predefined_keys();
         let text = "The quick brown fox jumps over the lazy dog";
-        let encrypted = encrypt_bytes(&text.as_bytes().to_vec(), &public_key);
-        let decrypted = decrypt_bytes(&encrypted, &private_key);
+        let encrypted = public_key.encrypt_bytes(text.as_bytes()).unwrap();
+        let decrypted = private_key.decrypt_bytes(&encrypted).unwrap();
         let decrypted_text = String::from_utf8_lossy(&decrypted);
         assert_eq!(text, decrypted_text);
     }
 
     #[test]
     fn should_encrypt_and_decrypt_a_number_using_generated_keys() {
-        let (public_key, private_key) = generated_keys();
-        let original_number = BigInt::from_u64(4093350987293047).unwrap();
-        let encrypted = encrypt_number(&original_number, &public_key);
-        let decrypted = encrypt_number(&encrypted, &private_key);
+        let KeyPair { public_key, private_key } = generated_keys();
+        let original_number = SecretBigInt::from(4093350987293047);
+        let encrypted = public_key.prepare().operation.encrypt_number(&original_number).unwrap();
+        let decrypted = private_key.prepare().operation.encrypt_number(&encrypted).unwrap();
         assert_eq!(original_number, decrypted);
     }
 
     #[test]
     fn should_encrypt_and_decrypt_a_vec_of_bytes_using_generated_keys() {
-        let (public_key, private_key) = generated_keys();
+        let KeyPair { public_key, private_key } = generated_keys();
         let input: Vec<u8> = get_random_bytes(1000);
-        let encrypted = encrypt_bytes(&input, &public_key);
-        let decrypted = decrypt_bytes(&encrypted, &private_key);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        let decrypted = private_key.decrypt_bytes(&encrypted).unwrap();
         assert_eq!(input, decrypted);
     }
 
     #[test]
     fn should_encrypt_and_decrypt_a_string_using_generated_keys() {
-        let (public_key, private_key) = generated_keys();
+        let KeyPair { public_key, private_key } = generated_keys();
         let text = "The quick brown fox jumps over the lazy dog";
-        let encrypted = encrypt_bytes(&text.as_bytes().to_vec(), &public_key);
-        let decrypted = decrypt_bytes(&encrypted, &private_key);
+        let encrypted = public_key.encrypt_bytes(text.as_bytes()).unwrap();
+        let decrypted = private_key.decrypt_bytes(&encrypted).unwrap();
         let decrypted_text = String::from_utf8_lossy(&decrypted);
         assert_eq!(text, decrypted_text);
     }
-}
\ No newline at end of file
+}
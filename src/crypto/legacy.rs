@@ -0,0 +1,305 @@
+#![allow(deprecated)]
+
+use num_bigint::BigInt;
+use std::{cmp, fmt, mem};
+use zeroize::Zeroizing;
+
+use crate::Error;
+use crate::pem;
+use crate::prime_certificate::PrimeCertificate;
+use crate::pkcs8::PrivateKeyInfo;
+use crate::secret::SecretBigInt;
+use super::{RsaPublicKey, RsaPrivateKey, KeyPair, Blinding, RsaOperation, PRIVATE_KEY_LABEL};
+
+/*
+ * Single key type for both public and private keys, kept for compatibility.
+ * Nothing prevents decrypting with a public key or encrypting with a private key, RsaPublicKey and RsaPrivateKey
+ * only provide the operations which make sense for each of them.
+ * Only the fields of the original Key are kept, the factors of the modulo of a private key are recovered from the
+ * private exponent whenever they are needed
+ */
+#[deprecated(note = "Use RsaPublicKey and RsaPrivateKey instead")]
+#[derive(PartialEq, Clone)]
+pub struct Key {
+    pub exponent: BigInt,
+    pub modulo: BigInt,
+    pub key_type: KeyType
+}
+
+#[deprecated(note = "Use RsaPublicKey and RsaPrivateKey instead")]
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyType {
+    Public,
+    Private
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Key");
+        match self.key_type {
+            KeyType::Private => debug.field("exponent", &"[REDACTED]"),
+            KeyType::Public => debug.field("exponent", &self.exponent)
+        };
+        debug.field("modulo", &self.modulo)
+            .field("key_type", &self.key_type)
+            .finish()
+    }
+}
+
+impl Key {
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self.key_type {
+            KeyType::Private => {
+                let private_key_bytes = Zeroizing::new(self.as_bytes());
+                let key_data = Zeroizing::new(PrivateKeyInfo::wrap(private_key_bytes).serialize());
+                pem::serialize_block(&key_data, PRIVATE_KEY_LABEL)
+            },
+            KeyType::Public => {
+                RsaPublicKey::new(self.modulo.clone(), self.exponent.clone()).serialize()
+            }
+        }
+    }
+
+    pub fn deserialize(input: &[u8]) -> Result<Key, Error> {
+        let (key_data, label) = pem::deserialize_block(input)?;
+        let key_data = Zeroizing::new(key_data);
+        if label.contains("PRIVATE") {
+            Ok(Key::from(RsaPrivateKey::from_key_info(&key_data)?))
+        } else {
+            Ok(Key::from(RsaPublicKey::from_key_info(&key_data)?))
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        match self.key_type {
+            KeyType::Private => {
+                let exponent = SecretBigInt::new(self.exponent.clone());
+                let components = super::recover_private_components(&self.modulo, &exponent);
+                super::private_key_as_bytes(&self.modulo, &exponent, components.as_ref())
+            },
+            KeyType::Public => RsaPublicKey::new(self.modulo.clone(), self.exponent.clone()).as_bytes()
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_bytes(bytes: &[u8], key_type: KeyType) -> Result<Key, Error> {
+        match key_type {
+            KeyType::Private => RsaPrivateKey::from_bytes(bytes).map(Key::from),
+            KeyType::Public => Ok(RsaPublicKey::from_bytes(bytes).map(Key::from)?)
+        }
+    }
+
+    pub fn prepare(&self) -> PreparedKey<'_> {
+        PreparedKey::new(self)
+    }
+}
+
+impl From<RsaPublicKey> for Key {
+    fn from(public_key: RsaPublicKey) -> Key {
+        Key {
+            exponent: public_key.exponent,
+            modulo: public_key.modulo,
+            key_type: KeyType::Public
+        }
+    }
+}
+
+impl From<RsaPrivateKey> for Key {
+    fn from(private_key: RsaPrivateKey) -> Key {
+        let RsaPrivateKey { modulo, exponent, .. } = private_key;
+        Key {
+            exponent: exponent.expose(),
+            modulo,
+            key_type: KeyType::Private
+        }
+    }
+}
+
+impl TryFrom<Key> for RsaPublicKey {
+    type Error = Error;
+
+    fn try_from(mut key: Key) -> Result<RsaPublicKey, Error> {
+        if key.key_type != KeyType::Public {
+            return Err(Error::InvalidKey("Expected a public key, found a private key".to_string()));
+        }
+        Ok(RsaPublicKey::new(mem::take(&mut key.modulo), mem::take(&mut key.exponent)))
+    }
+}
+
+impl TryFrom<Key> for RsaPrivateKey {
+    type Error = Error;
+
+    fn try_from(mut key: Key) -> Result<RsaPrivateKey, Error> {
+        if key.key_type != KeyType::Private {
+            return Err(Error::InvalidKey("Expected a private key, found a public key".to_string()));
+        }
+        let exponent = SecretBigInt::new(mem::take(&mut key.exponent));
+        let components = super::recover_private_components(&key.modulo, &exponent);
+        Ok(RsaPrivateKey::new(mem::take(&mut key.modulo), exponent, components))
+    }
+}
+
+/*
+ * Key together with the precomputed modulus context, built once and reused for all the encrypted or decrypted chunks
+ */
+#[deprecated(note = "Use PreparedPublicKey and PreparedPrivateKey instead")]
+pub struct PreparedKey<'a> {
+    key: &'a Key,
+    operation: RsaOperation<'a>
+}
+
+impl<'a> PreparedKey<'a> {
+
+    pub fn new(key: &'a Key) -> PreparedKey<'a> {
+        let operation = match key.key_type {
+            KeyType::Public => RsaOperation::public(&key.modulo, &key.exponent),
+            KeyType::Private => {
+                let exponent = SecretBigInt::new(key.exponent.clone());
+                let components = super::recover_private_components(&key.modulo, &exponent);
+                RsaOperation::private(&key.modulo, &exponent, components.as_ref())
+            }
+        };
+        PreparedKey { key, operation }
+    }
+
+    // Only affects private keys
+    pub fn with_blinding(mut self, blinding: Blinding) -> PreparedKey<'a> {
+        self.operation.blinding = blinding;
+        self
+    }
+
+    pub fn key(&self) -> &Key {
+        self.key
+    }
+
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.operation.encrypt_bytes(data)
+    }
+
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.operation.decrypt_bytes(data)
+    }
+}
+
+#[deprecated(note = "Use KeyPair::generate instead")]
+pub fn generate_keys(key_size: u16) -> Result<(Key, Key), Error> {
+    let key_pair = KeyPair::generate(key_size)?;
+    Ok((Key::from(key_pair.public_key), Key::from(key_pair.private_key)))
+}
+
+#[deprecated(note = "Use KeyPair::generate_with_certificates instead")]
+pub fn generate_keys_with_certificates(key_size: u16) -> Result<(Key, Key, PrimeCertificate, PrimeCertificate), Error> {
+    let (key_pair, p_certificate, q_certificate) = KeyPair::generate_with_certificates(key_size)?;
+    Ok((Key::from(key_pair.public_key), Key::from(key_pair.private_key), p_certificate, q_certificate))
+}
+
+#[deprecated(note = "Use RsaPublicKey::encryption_chunk_size instead")]
+pub fn encryption_chunk_size(key: &Key) -> usize {
+    // leave one byte for ENCRYPTED_PREFIX and one byte to make sure that modulo is not overflown
+    cmp::max(super::modulo_size_bytes(&key.modulo) - 2, 1)
+}
+
+#[deprecated(note = "Use RsaPrivateKey::decryption_chunk_size instead")]
+pub fn decryption_chunk_size(key: &Key) -> usize {
+    super::modulo_size_bytes(&key.modulo)
+}
+
+#[deprecated(note = "Use RsaPublicKey::encrypt_bytes instead")]
+pub fn encrypt_bytes(data: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
+    key.prepare().encrypt_bytes(data)
+}
+
+#[deprecated(note = "Use RsaPrivateKey::decrypt_bytes instead")]
+pub fn decrypt_bytes(data: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
+    key.prepare().decrypt_bytes(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::{predefined_keys, get_random_bytes};
+
+    fn predefined_legacy_keys() -> (Key, Key) {
+        let key_pair = predefined_keys();
+        (Key::from(key_pair.public_key), Key::from(key_pair.private_key))
+    }
+
+    #[test]
+    fn should_serialize_key() {
+        let key = Key {
+            exponent: BigInt::from(2),
+            modulo: BigInt::from(13),
+            key_type: KeyType::Public
+        };
+        assert_eq!(key.as_bytes(), vec![48u8, 6u8, 2u8, 1u8, 13u8, 2u8, 1u8, 2u8])
+    }
+
+    #[test]
+    fn should_deserialize_key() {
+        let key_bytes = vec![48u8, 6u8, 2u8, 1u8, 13u8, 2u8, 1u8, 2u8];
+        assert_eq!(Key::from_bytes(&key_bytes, KeyType::Public).unwrap(), Key {
+            exponent: BigInt::from(2),
+            modulo: BigInt::from(13),
+            key_type: KeyType::Public
+        })
+    }
+
+    #[test]
+    fn should_return_same_key_when_running_as_bytes_from_bytes_in_succession() {
+        let key = Key {
+            exponent: BigInt::from(2),
+            modulo: BigInt::from(13),
+            key_type: KeyType::Public
+        };
+        assert_eq!(Key::from_bytes(&key.as_bytes(), key.key_type.clone()).unwrap(), key)
+    }
+
+    #[test]
+    fn should_serialize_and_deserialize_keys() {
+        let (public_key, private_key) = generate_keys(512).unwrap();
+        assert_eq!(Key::deserialize(&public_key.serialize()).unwrap(), public_key);
+        assert_eq!(Key::deserialize(&private_key.serialize()).unwrap(), private_key);
+        assert_eq!(RsaPrivateKey::deserialize(&private_key.serialize()).unwrap(), RsaPrivateKey::try_from(private_key).unwrap());
+    }
+
+    #[test]
+    fn should_recover_components_of_private_key() {
+        let (_, private_key) = predefined_legacy_keys();
+        let key = Key { exponent: private_key.exponent.clone(), modulo: private_key.modulo.clone(), key_type: KeyType::Private };
+        assert_eq!(key.clone(), private_key);
+        assert!(RsaPrivateKey::deserialize(&key.serialize()).unwrap().components().is_some());
+        assert!(RsaPrivateKey::try_from(key).unwrap().components().is_some());
+    }
+
+    #[test]
+    fn should_convert_to_and_from_separate_key_types() {
+        let (public_key, private_key) = predefined_legacy_keys();
+        assert!(RsaPrivateKey::try_from(Key::deserialize(&public_key.serialize()).unwrap()).is_err());
+        assert!(RsaPublicKey::try_from(Key::deserialize(&private_key.serialize()).unwrap()).is_err());
+        let rsa_public_key = RsaPublicKey::try_from(public_key).unwrap();
+        let rsa_private_key = RsaPrivateKey::try_from(private_key).unwrap();
+        assert_eq!(rsa_private_key.public_key(), Some(rsa_public_key.clone()));
+        assert_eq!(Key::from(rsa_public_key).key_type, KeyType::Public);
+        assert_eq!(Key::from(rsa_private_key).key_type, KeyType::Private);
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_with_legacy_api() {
+        let (public_key, private_key) = predefined_legacy_keys();
+        let input: Vec<u8> = get_random_bytes(1000);
+        let encrypted = encrypt_bytes(&input, &public_key).unwrap();
+        assert_eq!(encrypted.len() / decryption_chunk_size(&private_key), input.len().div_ceil(encryption_chunk_size(&public_key)));
+        assert_eq!(decrypt_bytes(&encrypted, &private_key).unwrap(), input);
+        assert_eq!(private_key.prepare().with_blinding(Blinding::None).decrypt_bytes(&encrypted).unwrap(), input);
+    }
+
+    #[test]
+    fn should_not_print_private_exponent() {
+        let (public_key, private_key) = predefined_legacy_keys();
+        assert!(format!("{:?}", public_key).contains("65537"));
+        let printed_private_key = format!("{:?}", private_key);
+        assert!(!printed_private_key.contains("375946200922409"));
+        assert!(printed_private_key.contains("[REDACTED]"));
+    }
+}
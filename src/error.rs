@@ -0,0 +1,52 @@
+use std::io;
+use crate::crypto::FaultDetected;
+
+/*
+ * Error returned by every fallible function of the library, so that callers can tell the failures apart without
+ * matching on messages
+ */
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+    #[error("Invalid PEM: {0}")]
+    Pem(String),
+    #[error("Invalid DER encoding: {0}")]
+    Asn1(#[from] yasna::ASN1Error),
+    #[error("Unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Key not found: {0}")]
+    KeyNotFound(String),
+    #[error("Invalid prime certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("Invalid encrypted container: {0}")]
+    InvalidContainer(String),
+    #[error("Data was encrypted for another key: {0}")]
+    KeyMismatch(String),
+    #[error("Decryption failed: {0}")]
+    Decryption(String),
+    #[error("Invalid signature: {0}")]
+    Signature(String),
+    #[error(transparent)]
+    Fault(#[from] FaultDetected)
+}
+
+// Lets the streaming adapters report failures through the std::io traits
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(error) => error,
+            Error::Decryption(_) => io::Error::new(io::ErrorKind::InvalidData, error),
+            error => io::Error::other(error)
+        }
+    }
+}
+
+// Unwraps the errors of the library which went through the std::io traits
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        error.downcast::<Error>().unwrap_or_else(Error::Io)
+    }
+}
@@ -1,5 +1,5 @@
 use num_bigint::BigInt;
-use num_traits::{FromPrimitive, One, Zero};
+use num_traits::{One, Zero};
 
 //gcd = a * x + b * y
 #[derive(Debug, PartialEq)]
@@ -62,6 +62,7 @@ pub(crate) fn find_gcd_and_bezout_coefficients(first: &BigInt, second: &BigInt)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num_traits::FromPrimitive;
 
     #[test]
     fn should_find_gcd_and_bezout_coefficients() {
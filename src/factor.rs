@@ -0,0 +1,424 @@
+use num_bigint::{BigInt, RandBigInt};
+use num_traits::{One, Zero};
+use rand::thread_rng;
+use std::num::NonZeroUsize;
+use std::{cmp, fmt, thread};
+use crate::euclidean;
+use crate::modulo_arithmetic::ModulusContext;
+use crate::primes;
+
+mod ecm;
+mod quadratic_sieve;
+pub use ecm::{ecm, ecm_with_progress};
+pub use quadratic_sieve::{quadratic_sieve, quadratic_sieve_with_progress};
+
+/*
+ * Factoring of RSA moduli, showing why small or badly generated keys break. Every method only succeeds quickly when
+ * the modulo is small or its primes have a special structure: a small or medium prime, primes close to each other or a
+ * prime p with a smooth p - 1. Properly generated keys of 2048 bits are far out of reach of all of them
+ */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FactorMethod {
+    TrialDivision,
+    Fermat,
+    PollardRho,
+    EllipticCurve,
+    PollardPMinusOne,
+    QuadraticSieve
+}
+
+impl fmt::Display for FactorMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FactorMethod::TrialDivision => "trial division",
+            FactorMethod::Fermat => "Fermat's method",
+            FactorMethod::PollardRho => "Pollard's rho",
+            FactorMethod::EllipticCurve => "the elliptic curve method",
+            FactorMethod::PollardPMinusOne => "Pollard's p - 1",
+            FactorMethod::QuadraticSieve => "the quadratic sieve"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FactorEvent {
+    MethodStarted(FactorMethod),
+    MethodFailed(FactorMethod),
+    // Progress of the elliptic curve method, which fails once all curves are tried
+    CurvesTried { tried: usize, curves: usize },
+    // Progress of the quadratic sieve, which succeeds once enough relations are found
+    RelationsFound { found: usize, needed: usize }
+}
+
+// Limits of the work done by the methods, a method gives up once it reaches its limit
+#[derive(Debug, PartialEq, Clone)]
+pub struct FactorOptions {
+    // Primes up to the limit are tried as divisors
+    pub trial_division_limit: usize,
+    // Fermat's method finds p and q with |p - q| up to about 2 * sqrt(2 * iterations) * n^(1/4)
+    pub fermat_iterations: u64,
+    // Pollard's rho finds a prime p in about sqrt(p) iterations
+    pub rho_iterations: u64,
+    // Every curve of the elliptic curve method is another chance to find a prime p, like Pollard's p - 1 with the
+    // random order of the curve modulo p in place of p - 1. The defaults find most primes of up to 15 digits, about
+    // 100 curves with B1 = 11000 and B2 = 1100000 most primes of up to 20 digits
+    pub ecm_curves: usize,
+    pub ecm_bound: usize,
+    pub ecm_stage_2_bound: usize,
+    // Threads running the curves
+    pub ecm_jobs: usize,
+    // Stage 1 bound B1 of Pollard's p - 1: every prime factor of p - 1 is at most B1 ...
+    pub p_minus_one_bound: usize,
+    // ... except for a single one which is at most the stage 2 bound B2
+    pub p_minus_one_stage_2_bound: usize,
+    // Digits of the largest modulo factor runs the quadratic sieve for, its time grows quickly with the digits
+    pub quadratic_sieve_digits: usize
+}
+
+impl Default for FactorOptions {
+    fn default() -> FactorOptions {
+        FactorOptions {
+            trial_division_limit: 1_000_000,
+            fermat_iterations: 1_000_000,
+            rho_iterations: 1 << 20,
+            ecm_curves: 50,
+            ecm_bound: 2000,
+            ecm_stage_2_bound: 200_000,
+            ecm_jobs: thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1),
+            p_minus_one_bound: 1_000_000,
+            p_minus_one_stage_2_bound: 20_000_000,
+            quadratic_sieve_digits: 80
+        }
+    }
+}
+
+// n = p * q where p <= q, p is a non-trivial factor but not necessarily a prime
+#[derive(Debug, PartialEq, Clone)]
+pub struct Factorization {
+    pub p: BigInt,
+    pub q: BigInt,
+    pub method: FactorMethod
+}
+
+pub fn factor(n: &BigInt, options: &FactorOptions) -> Option<Factorization> {
+    factor_with_progress(n, options, &mut |_| {})
+}
+
+// Methods tried by factor, from the cheapest one
+const PIPELINE: [FactorMethod; 6] = [
+    FactorMethod::TrialDivision,
+    FactorMethod::Fermat,
+    FactorMethod::PollardRho,
+    FactorMethod::EllipticCurve,
+    FactorMethod::PollardPMinusOne,
+    FactorMethod::QuadraticSieve
+];
+
+// None when n is prime or none of the methods succeeds within its limit
+pub fn factor_with_progress(n: &BigInt, options: &FactorOptions, progress: &mut dyn FnMut(FactorEvent)) -> Option<Factorization> {
+    let digits = n.to_string().len();
+    PIPELINE.iter()
+        .filter(|&&method| method != FactorMethod::QuadraticSieve || digits <= options.quadratic_sieve_digits)
+        .find_map(|&method| factor_with_method(n, method, options, progress))
+}
+
+pub fn factor_with_method(n: &BigInt, method: FactorMethod, options: &FactorOptions, progress: &mut dyn FnMut(FactorEvent))
+                          -> Option<Factorization> {
+    if primes::is_probable_prime(n) || n < &BigInt::from(4) {
+        return None;
+    }
+    progress(FactorEvent::MethodStarted(method));
+    let factor = match method {
+        FactorMethod::TrialDivision => trial_division(n, options.trial_division_limit),
+        FactorMethod::Fermat => fermat(n, options.fermat_iterations),
+        FactorMethod::PollardRho => pollard_rho(n, options.rho_iterations),
+        FactorMethod::EllipticCurve => ecm_with_progress(n, options.ecm_curves, options.ecm_bound, options.ecm_stage_2_bound,
+                                                         options.ecm_jobs, progress),
+        FactorMethod::PollardPMinusOne => pollard_p_minus_one(n, options.p_minus_one_bound, options.p_minus_one_stage_2_bound),
+        FactorMethod::QuadraticSieve => quadratic_sieve_with_progress(n, progress)
+    };
+    let Some(factor) = factor else {
+        progress(FactorEvent::MethodFailed(method));
+        return None;
+    };
+    let cofactor = n / &factor;
+    let (p, q) = if factor <= cofactor { (factor, cofactor) } else { (cofactor, factor) };
+    Some(Factorization { p, q, method })
+}
+
+pub fn trial_division(n: &BigInt, limit: usize) -> Option<BigInt> {
+    primes::primes(limit).into_iter()
+        .map(BigInt::from)
+        .take_while(|prime| prime * prime <= *n)
+        .find(|prime| (n % prime).is_zero())
+}
+
+/*
+ * n = a^2 - b^2 = (a - b) * (a + b), trying a = ceil(sqrt(n)), ceil(sqrt(n)) + 1, ... until a^2 - n is a square.
+ * Fast when p and q are close to each other, for example when q is the next prime after p
+ */
+pub fn fermat(n: &BigInt, iterations: u64) -> Option<BigInt> {
+    if (n % 2u32).is_zero() {
+        return Some(BigInt::from(2));
+    }
+    let mut a = n.sqrt();
+    if &a * &a < *n {
+        a += 1;
+    }
+    let mut b_squared: BigInt = &a * &a - n;
+    for _ in 0..iterations {
+        if is_square_modulo_64(&b_squared) {
+            let b = b_squared.sqrt();
+            if &b * &b == b_squared {
+                let factor = &a - &b;
+                return Some(factor).filter(|factor| !factor.is_one());
+            }
+        }
+        // (a + 1)^2 - n = a^2 - n + 2a + 1
+        b_squared += (&a << 1) + 1;
+        a += 1;
+    }
+    None
+}
+
+// Only 12 of the 64 residues modulo 64 are squares, which skips most of the square roots
+fn is_square_modulo_64(n: &BigInt) -> bool {
+    let residue = n.magnitude().iter_u64_digits().next().unwrap_or(0) & 63;
+    matches!(residue, 0 | 1 | 4 | 9 | 16 | 17 | 25 | 33 | 36 | 41 | 49 | 57)
+}
+
+// Steps between two greatest common divisors, multiplying the differences is cheaper than a gcd
+const GCD_BATCH_SIZE: u64 = 128;
+
+/*
+ * Brent's variant of Pollard's rho: the sequence x -> x^2 + c (mod n) repeats modulo a prime p after about sqrt(p)
+ * steps, which shows as gcd(x_i - x_j, n) > 1. Brent compares against the values at powers of two instead of running
+ * a second sequence. A sequence which repeats modulo n itself is restarted with another c
+ */
+pub fn pollard_rho(n: &BigInt, iterations: u64) -> Option<BigInt> {
+    if (n % 2u32).is_zero() {
+        return Some(BigInt::from(2));
+    }
+    let mut rng = thread_rng();
+    let mut remaining_iterations = iterations;
+    while remaining_iterations > 0 {
+        let c = rng.gen_bigint_range(&BigInt::one(), &(n - 1));
+        let step = |x: &BigInt| (x * x + &c) % n;
+        let mut y = rng.gen_bigint_range(&BigInt::zero(), n);
+        let mut x = BigInt::zero();
+        let mut saved_y = BigInt::zero();
+        let mut product = BigInt::one();
+        let mut divisor = BigInt::one();
+        let mut cycle_length = 1;
+        while divisor.is_one() && remaining_iterations > 0 {
+            x.clone_from(&y);
+            for _ in 0..cycle_length {
+                y = step(&y);
+            }
+            let mut done = 0;
+            while done < cycle_length && divisor.is_one() {
+                saved_y.clone_from(&y);
+                let batch_size = cmp::min(GCD_BATCH_SIZE, cycle_length - done);
+                for _ in 0..batch_size {
+                    y = step(&y);
+                    product = (product * (&x - &y)) % n;
+                }
+                divisor = gcd(&product, n);
+                done += batch_size;
+            }
+            remaining_iterations = remaining_iterations.saturating_sub(2 * cycle_length);
+            cycle_length *= 2;
+        }
+        if divisor == *n {
+            // The batch skipped over the factor, repeating its steps one by one
+            loop {
+                saved_y = step(&saved_y);
+                divisor = gcd(&(&x - &saved_y), n);
+                if !divisor.is_one() {
+                    break;
+                }
+            }
+        }
+        if !divisor.is_one() && divisor != *n {
+            return Some(divisor);
+        }
+    }
+    None
+}
+
+// Prime powers multiplied into a single exponent before exponentiating, checked by a single gcd
+const PRIME_POWERS_PER_BATCH: usize = 64;
+// Numbers sieved at once for the primes of stage 2
+const STAGE_2_SEGMENT_SIZE: usize = 1 << 20;
+const STAGE_2_GCD_INTERVAL: usize = 1024;
+
+/*
+ * Pollard's p - 1: a^(p - 1) = 1 (mod p) for a prime p, so gcd(a^M - 1, n) is divisible by p when p - 1 divides M.
+ * Stage 1 takes M as the product of all prime powers up to B1, stage 2 additionally allows a single prime between
+ * B1 and B2 in p - 1. Strong primes are generated so that p - 1 has a large prime factor to defeat it
+ */
+pub fn pollard_p_minus_one(n: &BigInt, bound: usize, stage_2_bound: usize) -> Option<BigInt> {
+    if (n % 2u32).is_zero() {
+        return Some(BigInt::from(2));
+    }
+    let modulus = ModulusContext::for_modulo(n);
+    let mut a = BigInt::from(2);
+    let bound_primes = primes::primes(bound);
+    for batch in bound_primes.chunks(PRIME_POWERS_PER_BATCH) {
+        let exponent = batch.iter().map(|&prime| largest_power_up_to(prime, bound)).product::<BigInt>();
+        let next_a = modulus.exponent(&a, &exponent);
+        let divisor = gcd(&(&next_a - 1), n);
+        if divisor == *n {
+            // Every factor was found within the batch, going through its primes one by one separates them
+            for &prime in batch {
+                a = modulus.exponent(&a, &largest_power_up_to(prime, bound));
+                let divisor = gcd(&(&a - 1), n);
+                if divisor == *n {
+                    return None;
+                }
+                if !divisor.is_one() {
+                    return Some(divisor);
+                }
+            }
+        }
+        if !divisor.is_one() {
+            return Some(divisor);
+        }
+        a = next_a;
+    }
+    p_minus_one_stage_2(n, &a, bound, stage_2_bound)
+}
+
+fn largest_power_up_to(prime: usize, bound: usize) -> BigInt {
+    let mut power = prime;
+    while power <= bound / prime {
+        power *= prime;
+    }
+    BigInt::from(power)
+}
+
+// x = a^q for the successive primes q, each step multiplies by a precomputed a^(difference of the primes)
+fn p_minus_one_stage_2(n: &BigInt, a: &BigInt, bound: usize, stage_2_bound: usize) -> Option<BigInt> {
+    let a_squared = (a * a) % n;
+    // a^2, a^4, a^6, ... indexed by the half of the difference
+    let mut powers_of_differences = vec![a_squared.clone()];
+    let mut x: Option<BigInt> = None;
+    let mut previous_prime = 0;
+    let mut product = BigInt::one();
+    // Differences between odd primes are even, 2 is left to stage 1
+    let mut from = cmp::max(bound + 1, 3);
+    while from <= stage_2_bound {
+        let to = cmp::min(from + STAGE_2_SEGMENT_SIZE - 1, stage_2_bound);
+        for (index, prime) in primes::primes_segment(from, to).into_iter().enumerate() {
+            x = Some(match x {
+                None => modulo_exponent(a, prime, n),
+                Some(x) => {
+                    let half_difference = (prime - previous_prime) / 2;
+                    while powers_of_differences.len() < half_difference {
+                        let next_power = (powers_of_differences.last().expect("Starts with a^2") * &a_squared) % n;
+                        powers_of_differences.push(next_power);
+                    }
+                    (x * &powers_of_differences[half_difference - 1]) % n
+                }
+            });
+            previous_prime = prime;
+            product = (product * (x.as_ref().expect("Set above") - 1)) % n;
+            if index % STAGE_2_GCD_INTERVAL == 0 {
+                let divisor = gcd(&product, n);
+                if divisor == *n {
+                    return None;
+                }
+                if !divisor.is_one() {
+                    return Some(divisor);
+                }
+            }
+        }
+        from = to + 1;
+    }
+    Some(gcd(&product, n)).filter(|divisor| !divisor.is_one() && divisor != n)
+}
+
+fn modulo_exponent(a: &BigInt, power: usize, n: &BigInt) -> BigInt {
+    ModulusContext::for_modulo(n).exponent(a, &BigInt::from(power))
+}
+
+fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let a = if a < &BigInt::zero() { -a } else { a.clone() };
+    euclidean::find_gcd_and_bezout_coefficients(&a, b).gcd
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::crypto::tests::predefined_keys;
+    use rand::seq::SliceRandom;
+
+    fn next_prime(from: &BigInt) -> BigInt {
+        let mut candidate = from + 1;
+        while !primes::is_probable_prime(&candidate) {
+            candidate += 1;
+        }
+        candidate
+    }
+
+    pub(crate) fn random_prime(bits: u64) -> BigInt {
+        next_prime(&thread_rng().gen_bigint_range(&(BigInt::one() << (bits - 1)), &(BigInt::one() << bits)))
+    }
+
+    #[test]
+    fn should_factor_with_each_method() {
+        let large_prime = random_prime(128);
+        let n = &large_prime * 999983;
+        assert_eq!(trial_division(&n, 1_000_000), Some(BigInt::from(999983)));
+        assert_eq!(trial_division(&n, 1000), None);
+
+        let p = random_prime(256);
+        let q = next_prime(&(&p + 1000));
+        assert_eq!(fermat(&(&p * &q), 10), Some(p.clone()));
+        assert_eq!(fermat(&(&p * &large_prime), 10), None);
+
+        let n = predefined_keys().public_key.modulo().clone();
+        let factor = pollard_rho(&n, 1 << 20).unwrap();
+        assert!(!factor.is_one() && factor != n && (&n % &factor).is_zero());
+
+        // p - 1 = 2 * 3 * 7 * ... only has distinct small factors
+        let mut small_primes = primes::primes(1000)[1..].to_vec();
+        small_primes.shuffle(&mut thread_rng());
+        let (base_primes, other_primes) = small_primes.split_at(12);
+        let base: BigInt = base_primes.iter().fold(BigInt::from(2), |product, &prime| product * prime);
+        let smooth = other_primes.iter().enumerate()
+            .flat_map(|(index, &first)| other_primes[index + 1..].iter().map(move |&second| first * second))
+            .map(|multiplier| &base * multiplier)
+            .find(|smooth| primes::is_probable_prime(&(smooth + 1)))
+            .unwrap();
+        let smooth_prime: BigInt = &smooth + 1;
+        let n = &smooth_prime * &large_prime;
+        assert_eq!(pollard_p_minus_one(&n, 1000, 1000), Some(smooth_prime.clone()));
+        // The largest factor of p - 1 is only covered by stage 2
+        let stage_2_prime = primes::primes_segment(1_000_000, 1_100_000).into_iter()
+            .map(|large_factor| &smooth * large_factor + 1)
+            .find(primes::is_probable_prime)
+            .unwrap();
+        let n = &stage_2_prime * &large_prime;
+        assert_eq!(pollard_p_minus_one(&n, 1000, 1000), None);
+        assert_eq!(pollard_p_minus_one(&n, 1000, 1_100_000), Some(stage_2_prime));
+        // 79 - 1 = 2 * 3 * 13, stage 2 primes below the square root of the stage 2 bound are tried as well
+        let n = &large_prime * 79;
+        assert_eq!(pollard_p_minus_one(&n, 10, 2000), Some(BigInt::from(79)));
+        assert_eq!(pollard_p_minus_one(&n, 0, 100), None);
+    }
+
+    #[test]
+    fn should_recover_private_key_of_weak_key() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let mut events = Vec::new();
+        let factorization = factor_with_progress(public_key.modulo(), &FactorOptions::default(), &mut |event| events.push(event)).unwrap();
+        assert_eq!(&factorization.p * &factorization.q, *public_key.modulo());
+        assert_eq!(events.first(), Some(&FactorEvent::MethodStarted(FactorMethod::TrialDivision)));
+        let recovered = KeyPair::from_factors(&public_key, factorization.p, factorization.q).unwrap();
+        assert_eq!(recovered.private_key.exponent(), private_key.exponent());
+        assert_eq!(factor(&BigInt::from(1_000_003), &FactorOptions::default()), None);
+    }
+}
@@ -0,0 +1,292 @@
+use num_bigint::BigInt;
+use num_traits::{One, Signed, Zero};
+use rand::{thread_rng, Rng};
+use std::cmp;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use crate::euclidean;
+use crate::modulo_arithmetic::ModulusContext;
+use crate::primes;
+use super::{gcd, largest_power_up_to, FactorEvent};
+
+/*
+ * Lenstra's elliptic curve method (ECM). Like Pollard's p - 1, but in the group of points of a random elliptic curve
+ * modulo p instead of the multiplicative group: the order of the group varies from curve to curve between
+ * p + 1 - 2 * sqrt(p) and p + 1 + 2 * sqrt(p), and a curve whose order only has prime factors up to B1, except for a
+ * single one up to B2, reveals p. The time to find a prime depends on the size of the prime and hardly on the size of n,
+ * which makes it the method for medium primes of unbalanced or multi-prime moduli.
+ *
+ * Curves are Montgomery curves b * y^2 = x^3 + a * x^2 + x with Suyama's parametrization, whose order is divisible
+ * by 12. Points are kept as projective X:Z coordinates without y, and their coordinates in the Montgomery form of the
+ * modular arithmetic
+ */
+
+// Stage 2 covers the primes m * WHEEL +- j with j <= WHEEL / 2, the baby steps
+const WHEEL: usize = 2310;
+const STAGE_2_SEGMENT_SIZE: usize = 1 << 20;
+
+pub fn ecm(n: &BigInt, curves: usize, bound: usize, stage_2_bound: usize, jobs: usize) -> Option<BigInt> {
+    ecm_with_progress(n, curves, bound, stage_2_bound, jobs, &mut |_| {})
+}
+
+// A non-trivial factor of n, None when n is prime or none of the curves finds a factor. Curves run in jobs threads
+pub fn ecm_with_progress(n: &BigInt, curves: usize, bound: usize, stage_2_bound: usize, jobs: usize,
+                         progress: &mut dyn FnMut(FactorEvent)) -> Option<BigInt> {
+    if (n % 2u32).is_zero() {
+        return Some(BigInt::from(2));
+    }
+    if primes::is_probable_prime(n) || n < &BigInt::from(4) {
+        return None;
+    }
+    let modulus = ModulusContext::for_modulo(n);
+    let next_curve = AtomicUsize::new(0);
+    let is_factor_found = AtomicBool::new(false);
+    let (result_sender, result_receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..cmp::max(jobs, 1) {
+            let result_sender = result_sender.clone();
+            let (modulus, next_curve, is_factor_found) = (&modulus, &next_curve, &is_factor_found);
+            scope.spawn(move || {
+                let mut rng = thread_rng();
+                while !is_factor_found.load(Ordering::Relaxed) && next_curve.fetch_add(1, Ordering::Relaxed) < curves {
+                    let sigma = rng.gen_range(6..u32::MAX);
+                    let factor = match Curve::with_sigma(n, modulus, sigma) {
+                        Ok((curve, point)) => curve.find_factor(&point, bound, stage_2_bound),
+                        Err(factor) => factor
+                    };
+                    if result_sender.send(factor).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+        let mut tried = 0;
+        let mut reported_progress = 0;
+        for factor in result_receiver {
+            if factor.is_some() {
+                // The other threads stop after their current curve
+                is_factor_found.store(true, Ordering::Relaxed);
+                return factor;
+            }
+            tried += 1;
+            let current_progress = 20 * tried / curves;
+            if current_progress > reported_progress {
+                reported_progress = current_progress;
+                progress(FactorEvent::CurvesTried { tried, curves });
+            }
+        }
+        None
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Point {
+    x: BigInt,
+    z: BigInt
+}
+
+struct Curve<'a> {
+    n: &'a BigInt,
+    modulus: &'a ModulusContext,
+    // (a + 2) / 4, the only coefficient of the curve the arithmetic needs
+    a_plus_2_over_4: BigInt
+}
+
+impl<'a> Curve<'a> {
+
+    /*
+     * Suyama's parametrization: u = sigma^2 - 5, v = 4 * sigma, the starting point is u^3 : v^3 and
+     * (a + 2) / 4 = (v - u)^3 * (3 * u + v) / (16 * u^3 * v). Fails with the factor when the denominator has no inverse
+     */
+    fn with_sigma(n: &'a BigInt, modulus: &'a ModulusContext, sigma: u32) -> Result<(Curve<'a>, Point), Option<BigInt>> {
+        let sigma = BigInt::from(sigma);
+        let u: BigInt = (&sigma * &sigma - 5) % n;
+        let v: BigInt = (&sigma << 2) % n;
+        let u_cubed = u.modpow(&BigInt::from(3), n);
+        let v_cubed = v.modpow(&BigInt::from(3), n);
+        let numerator = ((&v - &u).modpow(&BigInt::from(3), n) * (3 * &u + &v)) % n;
+        let denominator = ((&u_cubed * &v) << 4) % n;
+        let inverse = match inverse_or_factor(&denominator, n) {
+            Ok(inverse) => inverse,
+            Err(factor) => return Err(Some(factor).filter(|factor| factor != n))
+        };
+        let a_plus_2_over_4 = modulus.to_montgomery_form(&((numerator * inverse) % n));
+        let point = Point { x: modulus.to_montgomery_form(&u_cubed), z: modulus.to_montgomery_form(&v_cubed) };
+        Ok((Curve { n, modulus, a_plus_2_over_4 }, point))
+    }
+
+    fn find_factor(&self, point: &Point, bound: usize, stage_2_bound: usize) -> Option<BigInt> {
+        // Point at infinity modulo p once the order modulo p divides the product of the prime powers
+        let mut point = point.clone();
+        for prime in primes::primes(bound) {
+            point = self.multiply(&point, &largest_power_up_to(prime, bound));
+        }
+        let divisor = gcd(&point.z, self.n);
+        if divisor == *self.n {
+            return None;
+        }
+        if !divisor.is_one() {
+            return Some(divisor);
+        }
+        let divisor = gcd(&self.stage_2_product(&point, bound, stage_2_bound), self.n);
+        Some(divisor).filter(|divisor| !divisor.is_one() && divisor != self.n)
+    }
+
+    /*
+     * For every prime q = m * WHEEL +- j between B1 and B2: q * P is the point at infinity modulo p exactly when
+     * (m * WHEEL) * P = +-j * P modulo p, so that X_m * Z_j - X_j * Z_m = 0 (mod p). The differences of all primes are
+     * multiplied together, only the giant steps m * WHEEL * P are computed on the way
+     */
+    fn stage_2_product(&self, point: &Point, bound: usize, stage_2_bound: usize) -> BigInt {
+        let mut product = self.modulus.to_montgomery_form(&BigInt::one());
+        if stage_2_bound <= bound {
+            return product;
+        }
+        // j * P for j = 1, 2, ..., WHEEL / 2, with X_j * Z_j
+        let mut baby_steps = vec![point.clone(), self.double(point)];
+        for j in 3..=WHEEL / 2 {
+            baby_steps.push(self.add(&baby_steps[j - 2], point, &baby_steps[j - 3]));
+        }
+        let baby_step_products: Vec<BigInt> = baby_steps.iter().map(|step| self.multiply_modulo(&step.x, &step.z)).collect();
+        let giant_step = self.multiply(point, &BigInt::from(WHEEL));
+        let mut m = (bound + 1 + WHEEL / 2) / WHEEL;
+        let mut previous = self.multiply(point, &BigInt::from(m.saturating_sub(1) * WHEEL));
+        let mut current = self.multiply(point, &BigInt::from(m * WHEEL));
+        let mut current_product = self.multiply_modulo(&current.x, &current.z);
+        let mut from = bound + 1;
+        while from <= stage_2_bound {
+            let to = cmp::min(from + STAGE_2_SEGMENT_SIZE - 1, stage_2_bound);
+            for prime in primes::primes_segment(from, to) {
+                let prime_m = (prime + WHEEL / 2) / WHEEL;
+                while m < prime_m {
+                    let next = match m {
+                        // Adding needs the difference of the points, which is the point at infinity for m = 0 or 1
+                        0 => giant_step.clone(),
+                        1 => self.double(&giant_step),
+                        _ => self.add(&current, &giant_step, &previous)
+                    };
+                    previous = std::mem::replace(&mut current, next);
+                    current_product = self.multiply_modulo(&current.x, &current.z);
+                    m += 1;
+                }
+                let j = prime.abs_diff(m * WHEEL);
+                let baby_step = &baby_steps[j - 1];
+                // (X_m - X_j) * (Z_m + Z_j) - X_m * Z_m + X_j * Z_j = X_m * Z_j - X_j * Z_m
+                let difference = self.multiply_modulo(&self.subtract(&current.x, &baby_step.x), &self.sum(&current.z, &baby_step.z));
+                let difference = self.sum(&self.subtract(&difference, &current_product), &baby_step_products[j - 1]);
+                product = self.multiply_modulo(&product, &difference);
+            }
+            from = to + 1;
+        }
+        product
+    }
+
+    // Montgomery ladder, keeping the difference of the two points equal to the point itself
+    fn multiply(&self, point: &Point, scalar: &BigInt) -> Point {
+        if scalar.is_zero() {
+            return Point { x: self.modulus.to_montgomery_form(&BigInt::one()), z: BigInt::zero() };
+        }
+        let mut lower = point.clone();
+        let mut upper = self.double(point);
+        for bit in (0..scalar.bits() - 1).rev() {
+            if scalar.bit(bit) {
+                lower = self.add(&upper, &lower, point);
+                upper = self.double(&upper);
+            } else {
+                upper = self.add(&lower, &upper, point);
+                lower = self.double(&lower);
+            }
+        }
+        lower
+    }
+
+    fn double(&self, point: &Point) -> Point {
+        let sum = self.sum(&point.x, &point.z);
+        let sum_squared = self.multiply_modulo(&sum, &sum);
+        let difference = self.subtract(&point.x, &point.z);
+        let difference_squared = self.multiply_modulo(&difference, &difference);
+        // 4 * x * z
+        let product = self.subtract(&sum_squared, &difference_squared);
+        Point {
+            x: self.multiply_modulo(&sum_squared, &difference_squared),
+            z: self.multiply_modulo(&product, &self.sum(&difference_squared, &self.multiply_modulo(&self.a_plus_2_over_4, &product)))
+        }
+    }
+
+    // first + second from the points and their difference first - second
+    fn add(&self, first: &Point, second: &Point, difference: &Point) -> Point {
+        let u = self.multiply_modulo(&self.subtract(&first.x, &first.z), &self.sum(&second.x, &second.z));
+        let v = self.multiply_modulo(&self.sum(&first.x, &first.z), &self.subtract(&second.x, &second.z));
+        let sum = self.sum(&u, &v);
+        let difference_of_products = self.subtract(&u, &v);
+        Point {
+            x: self.multiply_modulo(&difference.z, &self.multiply_modulo(&sum, &sum)),
+            z: self.multiply_modulo(&difference.x, &self.multiply_modulo(&difference_of_products, &difference_of_products))
+        }
+    }
+
+    fn multiply_modulo(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        self.modulus.montgomery_form_multiply(a, b)
+    }
+
+    fn sum(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        let sum = a + b;
+        if sum >= *self.n { sum - self.n } else { sum }
+    }
+
+    fn subtract(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        let difference = a - b;
+        if difference.is_negative() { difference + self.n } else { difference }
+    }
+}
+
+// Inverse of a modulo n, or gcd(a, n) when there is none
+fn inverse_or_factor(a: &BigInt, n: &BigInt) -> Result<BigInt, BigInt> {
+    let result = euclidean::find_gcd_and_bezout_coefficients(a, n);
+    if !result.gcd.is_one() {
+        return Err(result.gcd);
+    }
+    // Coefficients are ordered from the larger number to the smaller one
+    let coefficient = if a > n { result.x } else { result.y };
+    Ok(((coefficient % n) + n) % n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factor::tests::random_prime;
+
+    #[test]
+    fn should_find_medium_prime_of_unbalanced_modulo() {
+        let n = random_prime(40) * random_prime(128) * random_prime(128);
+        let mut events = Vec::new();
+        let factor = ecm_with_progress(&n, 1000, 2000, 200_000, 2, &mut |event| events.push(event)).unwrap();
+        assert!(!factor.is_one() && factor != n && (&n % &factor).is_zero());
+        assert!(events.iter().all(|event| matches!(event, FactorEvent::CurvesTried { curves: 1000, .. })));
+    }
+
+    #[test]
+    fn should_find_prime_with_stage_2_primes_below_square_root_of_bound() {
+        // Orders of the curves modulo 1009 are about 1009, stage 2 covers the primes from 11 up to 2000
+        let n = random_prime(128) * 1009;
+        assert_eq!(ecm(&n, 100, 10, 2000, 1), Some(BigInt::from(1009)));
+    }
+
+    #[test]
+    fn should_multiply_points_consistently() {
+        let n = random_prime(128) * random_prime(128);
+        let modulus = ModulusContext::for_modulo(&n);
+        let (curve, point) = Curve::with_sigma(&n, &modulus, 11).ok().unwrap();
+        // The Montgomery form cancels out in X / Z
+        let normalized_x = |point: &Point| (&point.x * inverse_or_factor(&point.z, &n).ok().unwrap()) % &n;
+        // 6P = 2(3P) = 3(2P) = 5P + P
+        let six = curve.multiply(&point, &BigInt::from(6));
+        let five = curve.multiply(&point, &BigInt::from(5));
+        let four = curve.multiply(&point, &BigInt::from(4));
+        assert_eq!(normalized_x(&curve.double(&curve.multiply(&point, &BigInt::from(3)))), normalized_x(&six));
+        assert_eq!(normalized_x(&curve.multiply(&curve.double(&point), &BigInt::from(3))), normalized_x(&six));
+        assert_eq!(normalized_x(&curve.add(&five, &point, &four)), normalized_x(&six));
+    }
+}
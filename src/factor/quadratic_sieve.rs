@@ -0,0 +1,630 @@
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use crate::modulo_arithmetic::ModulusContext;
+use crate::primes;
+use super::{gcd, FactorEvent};
+
+/*
+ * Self-initializing quadratic sieve (SIQS). Values g(x) = ((a * x + b)^2 - n) / a are sieved over -m <= x < m with
+ * the primes p of the factor base, the primes modulo which n is a square. The values which factor completely over the
+ * factor base, possibly except for a single large prime, are relations (a * x + b)^2 = a * g(x) (mod n). Once there
+ * are more relations than primes, some of them multiply to a square y^2 = x^2 (mod n) found with linear algebra over
+ * GF(2), and gcd(x - y, n) is a factor of n with probability 1/2 for every such square.
+ *
+ * a is a product of s primes of the factor base close to sqrt(2 * n) / m, every a gives 2^(s - 1) values of b with
+ * b^2 = n (mod a). Switching between them only costs an addition per prime of the factor base
+ */
+
+// (digits of n, size of the factor base, half-width m of the sieve interval)
+const PARAMETERS: [(usize, usize, usize); 15] = [
+    (20, 100, 8192),
+    (25, 150, 16384),
+    (30, 250, 16384),
+    (35, 400, 32768),
+    (40, 600, 32768),
+    (45, 1000, 32768),
+    (50, 1500, 32768),
+    (55, 2500, 32768),
+    (60, 4000, 32768),
+    (65, 5500, 32768),
+    (70, 7000, 65536),
+    (75, 9000, 65536),
+    (80, 12000, 65536),
+    (90, 20000, 65536),
+    (100, 30000, 98304)
+];
+
+// Relations collected beyond the number of columns, every one of them adds a dependency
+const EXTRA_RELATIONS: usize = 20;
+// Primes below the limit are not sieved, they take long to sieve and contribute little
+const SMALL_PRIME_LIMIT: u32 = 30;
+// Bits of the skipped small primes the threshold accounts for
+const SMALL_PRIME_CORRECTION: u32 = 8;
+// Largest cofactor of a partial relation, as a multiple of the largest prime of the factor base
+const LARGE_PRIME_MULTIPLIER: u64 = 256;
+const SCAN_BLOCK_SIZE: usize = 64;
+const FACTOR_BASE_SEGMENT_SIZE: usize = 1 << 16;
+const COEFFICIENT_ATTEMPTS: usize = 100;
+
+pub fn quadratic_sieve(n: &BigInt) -> Option<BigInt> {
+    quadratic_sieve_with_progress(n, &mut |_| {})
+}
+
+// A non-trivial factor of the odd composite n, None when n is prime or no factor is found
+pub fn quadratic_sieve_with_progress(n: &BigInt, progress: &mut dyn FnMut(FactorEvent)) -> Option<BigInt> {
+    if (n % 2u32).is_zero() {
+        return Some(BigInt::from(2));
+    }
+    if primes::is_probable_prime(n) || n < &BigInt::from(4) {
+        return None;
+    }
+    let square_root = n.sqrt();
+    if &square_root * &square_root == *n {
+        return Some(square_root);
+    }
+    // Relations are found for k * n, their squares are squares modulo n as well
+    let multiplier = knuth_schroeppel_multiplier(n);
+    let multiplied_n: BigInt = n * multiplier;
+    let digits = multiplied_n.to_string().len();
+    let &(_, factor_base_size, half_width) = PARAMETERS.iter()
+        .find(|(parameter_digits, _, _)| digits <= *parameter_digits)
+        .unwrap_or(&PARAMETERS[PARAMETERS.len() - 1]);
+    let factor_base = match FactorBase::new(&multiplied_n, multiplier, factor_base_size) {
+        Ok(factor_base) => factor_base,
+        Err(factor) => return Some(factor)
+    };
+    let relations = collect_relations(&multiplied_n, &factor_base, half_width, progress)?;
+    let columns = factor_base.primes.len() + 1;
+    for dependency in find_dependencies(&relations, columns) {
+        let factor = factor_from_square(n, &factor_base, &relations, &dependency);
+        if factor.is_some() {
+            return factor;
+        }
+    }
+    None
+}
+
+// Primes modulo which n is a square with the square roots of n, 2 is always included
+struct FactorBase {
+    primes: Vec<u32>,
+    square_roots: Vec<u32>,
+    logarithms: Vec<u8>
+}
+
+impl FactorBase {
+
+    // Fails with the prime when a prime divides n, the primes dividing the multiplier are left out
+    fn new(n: &BigInt, multiplier: u32, size: usize) -> Result<FactorBase, BigInt> {
+        let mut factor_base = FactorBase { primes: vec![2], square_roots: vec![1], logarithms: vec![1] };
+        let mut candidates = primes::primes(FACTOR_BASE_SEGMENT_SIZE);
+        let mut from = FACTOR_BASE_SEGMENT_SIZE + 1;
+        loop {
+            for &prime in candidates.iter().filter(|&&prime| prime > 2) {
+                let residue = (n % prime).to_u64().expect("Residue is smaller than the prime");
+                if residue == 0 {
+                    if (multiplier as usize).is_multiple_of(prime) {
+                        continue;
+                    }
+                    return Err(BigInt::from(prime));
+                }
+                if let Some(square_root) = square_root_modulo(residue, prime as u64) {
+                    factor_base.primes.push(prime as u32);
+                    factor_base.square_roots.push(square_root as u32);
+                    factor_base.logarithms.push((prime as f64).log2().round() as u8);
+                    if factor_base.primes.len() == size {
+                        return Ok(factor_base);
+                    }
+                }
+            }
+            // The primes crossed over by the segment are all smaller than its start
+            candidates = primes::primes_segment(from, from + FACTOR_BASE_SEGMENT_SIZE - 1);
+            from += FACTOR_BASE_SEGMENT_SIZE;
+        }
+    }
+
+    fn largest_prime(&self) -> u32 {
+        *self.primes.last().expect("Factor base contains 2")
+    }
+}
+
+// Small square-free multipliers k, k * n should be a square modulo many small primes
+const MULTIPLIERS: [u32; 31] = [1, 3, 5, 7, 11, 13, 15, 17, 19, 21, 23, 29, 31, 33, 35, 37, 39, 41, 43, 47, 51, 53, 55, 57, 59, 61,
+    65, 67, 69, 71, 73];
+const MULTIPLIER_PRIME_LIMIT: usize = 2000;
+
+/*
+ * Knuth-Schroeppel function: the expected contribution of the small primes to the logarithm of the sieved values,
+ * every prime modulo which k * n is a square divides the values twice as often. Larger multipliers make the values
+ * larger, which costs 1/2 * ln(k)
+ */
+fn knuth_schroeppel_multiplier(n: &BigInt) -> u32 {
+    let small_primes = primes::primes(MULTIPLIER_PRIME_LIMIT);
+    let score = |multiplier: u32| {
+        let multiplied_n: BigInt = n * multiplier;
+        let mut score = -0.5 * (multiplier as f64).ln();
+        score += std::f64::consts::LN_2 * match residue(&multiplied_n, 8) {
+            1 => 2.0,
+            5 => 1.0,
+            _ => 0.5
+        };
+        for &prime in small_primes.iter().skip(1) {
+            let logarithm = (prime as f64).ln();
+            if (multiplier as usize).is_multiple_of(prime) {
+                score += logarithm / prime as f64;
+            } else if power_modulo(residue(&multiplied_n, prime as u64), (prime as u64 - 1) / 2, prime as u64) == 1 {
+                score += 2.0 * logarithm / (prime - 1) as f64;
+            }
+        }
+        score
+    };
+    MULTIPLIERS.into_iter()
+        .map(|multiplier| (multiplier, score(multiplier)))
+        .max_by(|(_, first), (_, second)| first.total_cmp(second))
+        .map(|(multiplier, _)| multiplier)
+        .expect("Multipliers are not empty")
+}
+
+/*
+ * (a * x + b)^2 = product of the primes with the exponents * the square of large_primes (mod n).
+ * Column 0 is the sign, column i the i - 1th prime of the factor base
+ */
+struct Relation {
+    u: BigInt,
+    exponents: Vec<(usize, u32)>,
+    large_primes: BigInt
+}
+
+// Sieves polynomials until there are enough relations, None when the coefficients a run out
+fn collect_relations(n: &BigInt, factor_base: &FactorBase, half_width: usize, progress: &mut dyn FnMut(FactorEvent))
+                     -> Option<Vec<Relation>> {
+    let needed = factor_base.primes.len() + 1 + EXTRA_RELATIONS;
+    let largest_prime = factor_base.largest_prime() as u64;
+    let large_prime_bound = largest_prime * std::cmp::min(LARGE_PRIME_MULTIPLIER, largest_prime);
+    // log2(m * sqrt(n / 2)) bounds the size of g(x)
+    let value_bits = (half_width as f64).log2() + (n.bits() as f64 - 1.0) / 2.0;
+    let threshold = (value_bits - (large_prime_bound as f64).log2()).max(0.0) as u32;
+    let threshold = threshold.saturating_sub(SMALL_PRIME_CORRECTION) as u8;
+    let mut coefficients = CoefficientChooser::new(n, factor_base, half_width);
+    let mut relations = Vec::new();
+    let mut partial_relations: HashMap<u64, Relation> = HashMap::new();
+    let mut sieve = vec![0u8; 2 * half_width];
+    let mut reported_progress = 0;
+    while relations.len() < needed {
+        let mut polynomials = coefficients.next_polynomials(factor_base)?;
+        loop {
+            sieve.fill(0);
+            polynomials.sieve(factor_base, &mut sieve);
+            for index in candidate_positions(&sieve, threshold) {
+                match polynomials.relation_at(n, factor_base, index, large_prime_bound) {
+                    Some((relation, None)) => relations.push(relation),
+                    Some((relation, Some(large_prime))) => {
+                        if let Some(other) = partial_relations.get(&large_prime) {
+                            if other.u != relation.u {
+                                relations.push(combine(n, relation, other));
+                            }
+                        } else {
+                            partial_relations.insert(large_prime, relation);
+                        }
+                    },
+                    None => {}
+                }
+            }
+            if !polynomials.next_b(factor_base) {
+                break;
+            }
+        }
+        let current_progress = 20 * relations.len() / needed;
+        if current_progress > reported_progress {
+            reported_progress = current_progress;
+            progress(FactorEvent::RelationsFound { found: std::cmp::min(relations.len(), needed), needed });
+        }
+    }
+    Some(relations)
+}
+
+// Positions reaching the threshold, the maximum of a block is cheap to find with vector instructions
+fn candidate_positions(sieve: &[u8], threshold: u8) -> impl Iterator<Item = usize> + '_ {
+    sieve.chunks(SCAN_BLOCK_SIZE).enumerate()
+        .filter(move |(_, block)| block.iter().fold(0, |maximum, &logarithm| maximum.max(logarithm)) >= threshold)
+        .flat_map(move |(block_index, block)| block.iter().enumerate()
+            .filter(move |(_, &logarithm)| logarithm >= threshold)
+            .map(move |(index, _)| block_index * SCAN_BLOCK_SIZE + index))
+}
+
+// Two partial relations with the same large prime L multiply to a relation with L^2
+fn combine(n: &BigInt, first: Relation, second: &Relation) -> Relation {
+    let mut exponents = first.exponents;
+    for &(column, exponent) in &second.exponents {
+        match exponents.iter_mut().find(|(existing_column, _)| *existing_column == column) {
+            Some((_, existing_exponent)) => *existing_exponent += exponent,
+            None => exponents.push((column, exponent))
+        }
+    }
+    Relation { u: (first.u * &second.u) % n, exponents, large_primes: first.large_primes }
+}
+
+// Picks the coefficients a, products of s primes of the factor base, never the same a twice
+struct CoefficientChooser<'a> {
+    n: &'a BigInt,
+    half_width: usize,
+    // ln(sqrt(2 * n) / m)
+    target_logarithm: f64,
+    prime_count: usize,
+    candidate_indices: Vec<usize>,
+    used: HashSet<BigInt>
+}
+
+impl<'a> CoefficientChooser<'a> {
+
+    fn new(n: &'a BigInt, factor_base: &FactorBase, half_width: usize) -> CoefficientChooser<'a> {
+        let target_logarithm = ((n.bits() as f64 + 1.0) / 2.0 * std::f64::consts::LN_2 - (half_width as f64).ln()).max(1.0);
+        let primes = &factor_base.primes;
+        // The primes of a are not sieved with, they should not be the large primes which find most of the relations
+        let largest_usable = (primes[primes.len() * 3 / 4] as f64).ln();
+        let mut prime_count = std::cmp::max(1, (target_logarithm / 2000f64.ln()).round() as usize);
+        while target_logarithm / (prime_count as f64) > largest_usable {
+            prime_count += 1;
+        }
+        let ideal_prime = (target_logarithm / prime_count as f64).exp();
+        let center = primes.partition_point(|&prime| (prime as f64) < ideal_prime);
+        let window = std::cmp::max(2 * prime_count, 10);
+        // 2 is never a prime of a
+        let candidate_indices = (center.saturating_sub(window).max(1)..std::cmp::min(center + window, primes.len())).collect();
+        CoefficientChooser { n, half_width, target_logarithm, prime_count, candidate_indices, used: HashSet::new() }
+    }
+
+    /*
+     * s - 1 random primes of the window around the ideal size, the last one is the prime bringing a closest to the
+     * target. The window widens when the choices keep repeating
+     */
+    fn next_polynomials(&mut self, factor_base: &FactorBase) -> Option<Polynomials> {
+        let mut rng = rand::thread_rng();
+        for attempt in 0..COEFFICIENT_ATTEMPTS {
+            if attempt > 0 && attempt % 10 == 0 {
+                self.widen_window(factor_base);
+            }
+            let mut a_indices: Vec<usize> = Vec::new();
+            while a_indices.len() + 1 < self.prime_count && a_indices.len() + 1 < self.candidate_indices.len() {
+                let index = self.candidate_indices[rng.gen_range(0..self.candidate_indices.len())];
+                if !a_indices.contains(&index) {
+                    a_indices.push(index);
+                }
+            }
+            let partial_a: BigInt = a_indices.iter().map(|&index| BigInt::from(factor_base.primes[index])).product();
+            let remaining_logarithm = self.target_logarithm - a_indices.iter()
+                .map(|&index| (factor_base.primes[index] as f64).ln())
+                .sum::<f64>();
+            let mut last_candidates: Vec<usize> = (1..factor_base.primes.len() * 3 / 4 + 1)
+                .filter(|index| !a_indices.contains(index))
+                .collect();
+            last_candidates.sort_by(|&first, &second| {
+                let distance = |index: usize| ((factor_base.primes[index] as f64).ln() - remaining_logarithm).abs();
+                distance(first).total_cmp(&distance(second))
+            });
+            let last = last_candidates.into_iter()
+                .take(self.candidate_indices.len())
+                .find(|&index| !self.used.contains(&(&partial_a * factor_base.primes[index])));
+            if let Some(last) = last {
+                a_indices.push(last);
+                let a = &partial_a * factor_base.primes[last];
+                self.used.insert(a.clone());
+                return Some(Polynomials::new(self.n, factor_base, a, a_indices, self.half_width));
+            }
+        }
+        None
+    }
+
+    fn widen_window(&mut self, factor_base: &FactorBase) {
+        let first = self.candidate_indices[0];
+        let last = self.candidate_indices[self.candidate_indices.len() - 1];
+        let width = last - first + 1;
+        self.candidate_indices = (first.saturating_sub(width).max(1)..std::cmp::min(last + width + 1, factor_base.primes.len())).collect();
+    }
+}
+
+/*
+ * The polynomials of a single a: b = +-b_0 +- b_1 ... + b_(s-1), b_l = 0 (mod every prime of a but q_l) and
+ * b_l^2 = n (mod q_l). The sign of b_l is flipped in Gray code order, so that each b differs from the previous one
+ * in a single term
+ */
+struct Polynomials {
+    a: BigInt,
+    b: BigInt,
+    b_terms: Vec<BigInt>,
+    is_term_negative: Vec<bool>,
+    is_a_prime: Vec<bool>,
+    // Offsets in the sieve of the two roots of g(x) modulo every prime of the factor base
+    roots: Vec<[u32; 2]>,
+    // 2 * b_l / a modulo every prime of the factor base, the change of the roots when b_l is flipped
+    root_steps: Vec<Vec<u32>>,
+    b_index: usize,
+    half_width: usize
+}
+
+impl Polynomials {
+
+    fn new(n: &BigInt, factor_base: &FactorBase, a: BigInt, a_indices: Vec<usize>, half_width: usize) -> Polynomials {
+        let b_terms: Vec<BigInt> = a_indices.iter().map(|&index| {
+            let q = factor_base.primes[index] as u64;
+            let a_over_q = &a / q;
+            let inverse = inverse_modulo(residue(&a_over_q, q), q);
+            let mut gamma = factor_base.square_roots[index] as u64 * inverse % q;
+            if gamma > q / 2 {
+                gamma = q - gamma;
+            }
+            a_over_q * gamma
+        }).collect();
+        let b: BigInt = b_terms.iter().sum();
+        let mut is_a_prime = vec![false; factor_base.primes.len()];
+        for &index in &a_indices {
+            is_a_prime[index] = true;
+        }
+        let mut roots = vec![[0u32; 2]; factor_base.primes.len()];
+        let mut root_steps = vec![vec![0u32; factor_base.primes.len()]; b_terms.len()];
+        for (index, &prime) in factor_base.primes.iter().enumerate().skip(1) {
+            if is_a_prime[index] {
+                continue;
+            }
+            let p = prime as u64;
+            let a_inverse = inverse_modulo(residue(&a, p), p);
+            let square_root = factor_base.square_roots[index] as u64;
+            let b_residue = residue(&b, p);
+            let offset = half_width as u64 % p;
+            roots[index] = [
+                ((square_root + p - b_residue) % p * a_inverse % p + offset) % p,
+                ((2 * p - square_root - b_residue) % p * a_inverse % p + offset) % p
+            ].map(|root| root as u32);
+            for (term, steps) in b_terms.iter().zip(root_steps.iter_mut()) {
+                steps[index] = (2 * residue(term, p) % p * a_inverse % p) as u32;
+            }
+        }
+        debug_assert!(((&b * &b - n) % &a).is_zero());
+        Polynomials { a, b, b_terms, is_term_negative: vec![false; a_indices.len()], is_a_prime, roots, root_steps, b_index: 0, half_width }
+    }
+
+    // Switches to the next b, false when all of them have been used
+    fn next_b(&mut self, factor_base: &FactorBase) -> bool {
+        self.b_index += 1;
+        if self.b_index >= 1 << (self.b_terms.len() - 1) {
+            return false;
+        }
+        let term = self.b_index.trailing_zeros() as usize;
+        let is_negative = self.is_term_negative[term];
+        // Roots are a^-1 * (+-t - b) - m, so they move the opposite way than b
+        if is_negative {
+            self.b += &self.b_terms[term] << 1;
+        } else {
+            self.b -= &self.b_terms[term] << 1;
+        }
+        self.is_term_negative[term] = !is_negative;
+        for (index, &prime) in factor_base.primes.iter().enumerate().skip(1) {
+            let step = self.root_steps[term][index];
+            for root in self.roots[index].iter_mut() {
+                *root = if is_negative { *root + prime - step } else { *root + step };
+                if *root >= prime {
+                    *root -= prime;
+                }
+            }
+        }
+        true
+    }
+
+    fn sieve(&self, factor_base: &FactorBase, sieve: &mut [u8]) {
+        for (index, &prime) in factor_base.primes.iter().enumerate() {
+            if prime < SMALL_PRIME_LIMIT || self.is_a_prime[index] {
+                continue;
+            }
+            let logarithm = factor_base.logarithms[index];
+            for &root in &self.roots[index] {
+                let mut position = root as usize;
+                while position < sieve.len() {
+                    sieve[position] = sieve[position].wrapping_add(logarithm);
+                    position += prime as usize;
+                }
+            }
+        }
+    }
+
+    // Trial divides g(x) at the sieve position, with the large prime of a partial relation
+    fn relation_at(&self, n: &BigInt, factor_base: &FactorBase, position: usize, large_prime_bound: u64)
+                   -> Option<(Relation, Option<u64>)> {
+        let u: BigInt = &self.a * (position as i64 - self.half_width as i64) + &self.b;
+        let mut value: BigInt = (&u * &u - n) / &self.a;
+        if value.is_zero() {
+            return None;
+        }
+        let mut exponents = Vec::new();
+        if value.is_negative() {
+            exponents.push((0, 1));
+            value = -value;
+        }
+        let twos = value.trailing_zeros().expect("Value is not zero");
+        if twos > 0 {
+            exponents.push((1, twos as u32));
+            value >>= twos;
+        }
+        for (index, &prime) in factor_base.primes.iter().enumerate().skip(1) {
+            let mut exponent = 0;
+            let is_root = self.roots[index].contains(&((position % prime as usize) as u32));
+            if self.is_a_prime[index] || is_root {
+                while (&value % prime).is_zero() {
+                    value /= prime;
+                    exponent += 1;
+                }
+            }
+            if self.is_a_prime[index] {
+                exponent += 1;
+            }
+            if exponent > 0 {
+                exponents.push((index + 1, exponent));
+            }
+        }
+        if value.is_one() {
+            return Some((Relation { u, exponents, large_primes: BigInt::one() }, None));
+        }
+        let large_prime = value.to_u64().filter(|&large_prime| large_prime < large_prime_bound)?;
+        Some((Relation { u, exponents, large_primes: value }, Some(large_prime)))
+    }
+}
+
+fn residue(number: &BigInt, p: u64) -> u64 {
+    let residue = (number % p).to_i64().expect("Residue is smaller than the prime");
+    if residue < 0 { (residue + p as i64) as u64 } else { residue as u64 }
+}
+
+fn power_modulo(base: u64, exponent: u64, p: u64) -> u64 {
+    let mut result = 1u64;
+    let mut base = base % p;
+    let mut exponent = exponent;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply_modulo(result, base, p);
+        }
+        base = multiply_modulo(base, base, p);
+        exponent >>= 1;
+    }
+    result
+}
+
+fn multiply_modulo(a: u64, b: u64, p: u64) -> u64 {
+    ((a as u128 * b as u128) % p as u128) as u64
+}
+
+// Fermat's little theorem, p is prime
+fn inverse_modulo(a: u64, p: u64) -> u64 {
+    power_modulo(a, p - 2, p)
+}
+
+// Tonelli-Shanks for an odd prime p, None when n is not a square modulo p
+fn square_root_modulo(n: u64, p: u64) -> Option<u64> {
+    let n = n % p;
+    if n == 0 {
+        return Some(0);
+    }
+    if power_modulo(n, (p - 1) / 2, p) != 1 {
+        return None;
+    }
+    if p % 4 == 3 {
+        return Some(power_modulo(n, (p + 1) / 4, p));
+    }
+    // p - 1 = q * 2^s with an odd q
+    let s = (p - 1).trailing_zeros();
+    let q = (p - 1) >> s;
+    let non_residue = (2..p).find(|&z| power_modulo(z, (p - 1) / 2, p) == p - 1).expect("Half of the numbers are non-residues");
+    let mut m = s;
+    let mut c = power_modulo(non_residue, q, p);
+    let mut t = power_modulo(n, q, p);
+    let mut root = power_modulo(n, q.div_ceil(2), p);
+    while t != 1 {
+        let mut i = 0;
+        let mut t_power = t;
+        while t_power != 1 {
+            t_power = multiply_modulo(t_power, t_power, p);
+            i += 1;
+        }
+        let b = power_modulo(c, 1 << (m - i - 1), p);
+        m = i;
+        c = multiply_modulo(b, b, p);
+        t = multiply_modulo(t, c, p);
+        root = multiply_modulo(root, b, p);
+    }
+    Some(root)
+}
+
+/*
+ * Gaussian elimination over GF(2) on the exponent vectors modulo 2 of the relations. Every row which becomes zero is
+ * a subset of the relations whose product is a square, the subset is tracked in the history bits of the row
+ */
+fn find_dependencies(relations: &[Relation], columns: usize) -> Vec<Vec<usize>> {
+    let column_words = columns.div_ceil(64);
+    let history_words = relations.len().div_ceil(64);
+    let mut rows: Vec<Vec<u64>> = relations.iter().enumerate().map(|(index, relation)| {
+        let mut row = vec![0u64; column_words + history_words];
+        for &(column, exponent) in &relation.exponents {
+            if exponent % 2 == 1 {
+                row[column / 64] ^= 1 << (column % 64);
+            }
+        }
+        row[column_words + index / 64] |= 1 << (index % 64);
+        row
+    }).collect();
+    let mut is_pivot = vec![false; rows.len()];
+    for column in 0..columns {
+        let has_bit = |row: &[u64]| row[column / 64] >> (column % 64) & 1 == 1;
+        let Some(pivot) = (0..rows.len()).find(|&index| !is_pivot[index] && has_bit(&rows[index])) else {
+            continue;
+        };
+        is_pivot[pivot] = true;
+        let pivot_row = rows[pivot].clone();
+        for (index, row) in rows.iter_mut().enumerate() {
+            if !is_pivot[index] && has_bit(row) {
+                for (word, pivot_word) in row.iter_mut().zip(pivot_row.iter()) {
+                    *word ^= pivot_word;
+                }
+            }
+        }
+    }
+    rows.iter().zip(is_pivot.iter())
+        .filter(|(_, &is_pivot)| !is_pivot)
+        .map(|(row, _)| (0..relations.len()).filter(|&index| row[column_words + index / 64] >> (index % 64) & 1 == 1).collect())
+        .collect()
+}
+
+// x = product of u, y = square root of the product of the values, gcd(x - y, n)
+fn factor_from_square(n: &BigInt, factor_base: &FactorBase, relations: &[Relation], dependency: &[usize]) -> Option<BigInt> {
+    let mut exponents = vec![0u32; factor_base.primes.len() + 1];
+    let mut x = BigInt::one();
+    let mut y = BigInt::one();
+    for &index in dependency {
+        let relation = &relations[index];
+        x = (x * &relation.u) % n;
+        y = (y * &relation.large_primes) % n;
+        for &(column, exponent) in &relation.exponents {
+            exponents[column] += exponent;
+        }
+    }
+    let modulus = ModulusContext::for_modulo(n);
+    for (&prime, &exponent) in factor_base.primes.iter().zip(exponents.iter().skip(1)) {
+        if exponent > 0 {
+            y = (y * modulus.exponent(&BigInt::from(prime), &BigInt::from(exponent / 2))) % n;
+        }
+    }
+    Some(gcd(&(x - y), n)).filter(|factor| !factor.is_one() && factor != n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::predefined_keys;
+
+    #[test]
+    fn should_find_square_roots_modulo_primes() {
+        for p in [3u64, 5, 13, 17, 41, 97, 65537, 1_000_033] {
+            for n in 1..200 {
+                match square_root_modulo(n, p) {
+                    Some(root) => assert_eq!(root * root % p, n % p),
+                    None => assert_ne!(power_modulo(n, (p - 1) / 2, p), 1)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_factor_with_quadratic_sieve() {
+        let n = predefined_keys().public_key.modulo().clone();
+        let mut found_relations = Vec::new();
+        let factor = quadratic_sieve_with_progress(&n, &mut |event| {
+            if let FactorEvent::RelationsFound { found, needed } = event {
+                found_relations.push((found, needed));
+            }
+        }).unwrap();
+        assert!(!factor.is_one() && factor != n && (&n % &factor).is_zero());
+        assert!(found_relations.last().is_some_and(|(found, needed)| found == needed));
+        // 29 digits
+        let n: BigInt = "68329137224702068428019180673".parse().unwrap();
+        let factor = quadratic_sieve(&n).unwrap();
+        assert!(!factor.is_one() && factor != n && (&n % &factor).is_zero());
+    }
+}
@@ -1,51 +1,928 @@
-use std::io::{self, Read, BufReader};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::env;
+use std::io::{self, Read, BufReader, Seek, SeekFrom};
 use std::path::{ Path, PathBuf };
 use std::fs::{ self, File, OpenOptions };
 use std::io::{Write, BufRead};
-use anyhow::{Result, Error};
-use crate::crypto;
+use zeroize::Zeroizing;
+use crate::Error;
+use crate::atomic_file::AtomicFile;
+use crate::crypto::{self, RsaPublicKey, RsaPrivateKey, PreparedPublicKey, PreparedPrivateKey, KeyFingerprint};
+use crate::armor::{self, ArmorReader};
+use crate::container::{self, CipherSuite, Compression, Header, Recipient};
+use crate::compression::{CompressingReader, DecompressingWriter};
+use crate::prime_certificate::PrimeCertificate;
+use crate::stream::{self, SegmentCipher};
+use log::warn;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use chacha20poly1305::aead::Aead;
+use rand::Rng;
+use sha2::{Digest, Sha256};
 
-pub fn read_key_from(path: &Path) -> Result<crypto::Key, anyhow::Error> {
+pub fn read_public_key_from(path: &Path) -> Result<RsaPublicKey, Error> {
     let bytes = fs::read(path)?;
-    crate::crypto::Key::deserialize(&bytes)
+    RsaPublicKey::deserialize(&bytes)
 }
 
-pub fn save_key_to(key: &crypto::Key, key_path: &Path) -> Result<(), anyhow::Error> {
-    let mut public_key_file = File::create(key_path)?;
-    public_key_file.write_all(&key.serialize())?;
+pub fn read_private_key_from(path: &Path) -> Result<RsaPrivateKey, Error> {
+    let bytes = Zeroizing::new(fs::read(path)?);
+    RsaPrivateKey::deserialize(&bytes)
+}
+
+/*
+ * Keys and certificates are written atomically and never replace an existing file, the force_save functions replace it.
+ * Private keys and prime certificates, which reveal the private key, are only readable by the owner
+ */
+pub fn save_public_key_to(key: &RsaPublicKey, key_path: &Path) -> Result<(), Error> {
+    save_file(key_path, &key.serialize(), false, false)
+}
+
+pub fn save_private_key_to(key: &RsaPrivateKey, key_path: &Path) -> Result<(), Error> {
+    save_file(key_path, &Zeroizing::new(key.serialize()), true, false)
+}
+
+pub fn force_save_public_key_to(key: &RsaPublicKey, key_path: &Path) -> Result<(), Error> {
+    save_file(key_path, &key.serialize(), false, true)
+}
+
+pub fn force_save_private_key_to(key: &RsaPrivateKey, key_path: &Path) -> Result<(), Error> {
+    save_file(key_path, &Zeroizing::new(key.serialize()), true, true)
+}
+
+fn save_file(path: &Path, bytes: &[u8], is_private: bool, overwrite: bool) -> Result<(), Error> {
+    let mut file = if is_private { AtomicFile::create_private(path)? } else { AtomicFile::create(path)? };
+    if !overwrite {
+        file = file.without_overwriting()?;
+    }
+    file.write_all(bytes)?;
+    file.commit()
+}
+
+#[deprecated(note = "Use read_public_key_from or read_private_key_from instead")]
+#[allow(deprecated)]
+pub fn read_key_from(path: &Path) -> Result<crypto::Key, Error> {
+    let bytes = Zeroizing::new(fs::read(path)?);
+    crypto::Key::deserialize(&bytes)
+}
+
+#[deprecated(note = "Use save_public_key_to or save_private_key_to instead")]
+#[allow(deprecated)]
+pub fn save_key_to(key: &crypto::Key, key_path: &Path) -> Result<(), Error> {
+    save_file(key_path, &Zeroizing::new(key.serialize()), key.key_type == crypto::KeyType::Private, false)
+}
+
+// Directories holding private keys are only accessible by the owner, existing directories are left as they are
+pub fn create_private_directory(path: &Path) -> Result<(), Error> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)?;
     Ok(())
 }
 
+pub fn read_prime_certificate_from(path: &Path) -> Result<PrimeCertificate, Error> {
+    let bytes = fs::read(path)?;
+    PrimeCertificate::deserialize(&bytes)
+}
+
+pub fn save_prime_certificate_to(certificate: &PrimeCertificate, certificate_path: &Path) -> Result<(), Error> {
+    save_file(certificate_path, &certificate.serialize(), true, false)
+}
+
+pub fn force_save_prime_certificate_to(certificate: &PrimeCertificate, certificate_path: &Path) -> Result<(), Error> {
+    save_file(certificate_path, &certificate.serialize(), true, true)
+}
+
 pub fn create_key_path(key_directory: &str, key_pair_name: &str, key_prefix: &str) -> PathBuf {
     let key_file_name = format!("{}_{}.pem", key_pair_name, key_prefix);
     Path::new(&key_directory).join(&key_file_name)
 }
 
-pub fn encrypt(reader: &mut Box<dyn BufRead>, writer: &mut Box<dyn Write>, key: &crypto::Key, chunk_size: usize) -> Result<(), anyhow::Error> {
-    process_chunks_of(reader, writer, chunk_size, |chunk, writer| {
-        let encrypted = crypto::encrypt_bytes(&chunk, &key);
-        write_bytes(&encrypted, writer)
+/*
+ * Encrypts the data as RSA chunks without the container header, kept for compatibility.
+ * The chunks are not authenticated: modified, reordered, removed or appended chunks are not detected when decrypting,
+ * neither is the use of a wrong key when the chunks happen to start with the expected prefix
+ */
+#[deprecated(note = "Unauthenticated, use encrypt_for, which also detects modified data")]
+#[allow(deprecated)]
+pub fn encrypt<R, W>(reader: &mut R, writer: &mut W, key: &RsaPublicKey, chunk_size: usize) -> Result<(), Error>
+where R: Read + ?Sized, W: Write + ?Sized {
+    let mut encrypting_writer = EncryptingWriter::with_chunk_size(writer, key, chunk_size);
+    io::copy(reader, &mut encrypting_writer)?;
+    encrypting_writer.finish()?;
+    Ok(())
+}
+
+// Decrypts the output of encrypt, modifications of the data are not detected
+#[deprecated(note = "Unauthenticated, use decrypt_container, or decrypt_unauthenticated_container for data encrypted by encrypt")]
+#[allow(deprecated)]
+pub fn decrypt<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, chunk_size: usize) -> Result<(), Error>
+where R: Read + ?Sized, W: Write + ?Sized {
+    let mut decrypting_reader = DecryptingReader::with_chunk_size(reader, key, chunk_size);
+    io::copy(&mut decrypting_reader, writer)?;
+    Ok(())
+}
+
+// Output is identical to encrypt and just as unauthenticated, chunks are encrypted by the given number of threads
+#[deprecated(note = "Unauthenticated, use encrypt_for_in_parallel, which also detects modified data")]
+pub fn encrypt_in_parallel<R, W>(reader: &mut R, writer: &mut W, key: &RsaPublicKey, chunk_size: usize, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    encrypt_chunks_in_parallel(reader, writer, key, chunk_size, jobs)
+}
+
+// Output is identical to decrypt, modifications of the data are not detected
+#[deprecated(note = "Unauthenticated, use decrypt_container, or decrypt_unauthenticated_container for data encrypted by encrypt")]
+pub fn decrypt_in_parallel<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, chunk_size: usize, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    decrypt_chunks_in_parallel(reader, writer, key, chunk_size, jobs)
+}
+
+// Headerless RSA chunks, only written by the deprecated functions and read for data of earlier versions
+fn encrypt_chunks_in_parallel<R, W>(reader: &mut R, writer: &mut W, key: &RsaPublicKey, chunk_size: usize, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    let prepared_key = key.prepare();
+    process_chunks_in_parallel(reader, writer, chunk_size, jobs, |chunk| {
+        Ok(Zeroizing::new(prepared_key.encrypt_bytes(chunk.data)?))
     })
 }
 
-pub fn decrypt(reader: &mut Box<dyn BufRead>, writer: &mut Box<dyn Write>, key: &crypto::Key, chunk_size: usize) -> Result<(), anyhow::Error> {
-    process_chunks_of(reader, writer, chunk_size, |chunk, writer| {
-        let decrypted = crypto::decrypt_bytes(&chunk, &key);
-        write_bytes(&decrypted, writer)
+fn decrypt_chunks_in_parallel<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, chunk_size: usize, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    let prepared_key = key.prepare();
+    process_chunks_in_parallel(reader, writer, chunk_size, jobs, |chunk| {
+        Ok(Zeroizing::new(prepared_key.decrypt_bytes(chunk.data)?))
     })
 }
 
-pub fn process_chunks_of<F>(input: &mut Box<dyn BufRead>, output: &mut Box<dyn Write>, chunk_size: usize, chunk_processor: F) -> Result<(), anyhow::Error>
-where F: Fn(&Vec<u8>, &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
-    let mut buffer = vec![0u8; chunk_size];
+const CHUNKS_IN_FLIGHT_PER_JOB: usize = 4;
+
+type ChunkResult = Result<Zeroizing<Vec<u8>>, Error>;
+
+// Chunk of the input with its position, only the last chunk can be shorter than the chunk size
+pub struct Chunk<'a> {
+    pub index: usize,
+    pub data: &'a [u8],
+    pub is_last: bool
+}
+
+/*
+ * Pipeline of a reader thread, a pool of worker threads processing the chunks and the calling thread writing the
+ * results in the original order.
+ * Chunks are split the same way as by process_chunks_of, except that empty input gives a single empty last chunk.
+ * A chunk is only read after one of the CHUNKS_IN_FLIGHT_PER_JOB * jobs previous chunks has been written, which
+ * bounds the memory used
+ */
+pub fn process_chunks_in_parallel<R, W, F>(reader: &mut R, writer: &mut W, chunk_size: usize, jobs: usize, chunk_processor: F) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized, F: Fn(Chunk<'_>) -> ChunkResult + Sync {
+    let jobs = cmp::max(jobs, 1);
+    let chunks_in_flight = jobs * CHUNKS_IN_FLIGHT_PER_JOB;
+    let (chunk_sender, chunk_receiver) = mpsc::sync_channel::<(usize, Zeroizing<Vec<u8>>, bool)>(chunks_in_flight);
+    let (result_sender, result_receiver) = mpsc::sync_channel(chunks_in_flight);
+    let (permit_sender, permit_receiver) = mpsc::sync_channel(chunks_in_flight);
+    for _ in 0..chunks_in_flight {
+        permit_sender.send(()).expect("Receiver is alive");
+    }
+    let chunk_receiver = Mutex::new(chunk_receiver);
+    let chunk_processor = &chunk_processor;
+    thread::scope(|scope| {
+        let reading = scope.spawn(move || -> Result<(), Error> {
+            let read_next_chunk = |reader: &mut R| -> Result<Zeroizing<Vec<u8>>, Error> {
+                let mut chunk = Zeroizing::new(vec![0u8; chunk_size]);
+                let read_size = read_chunk(reader, &mut chunk)?;
+                chunk.truncate(read_size);
+                Ok(chunk)
+            };
+            // Next chunk is read ahead, so that the last chunk is known when it is sent
+            let mut chunk = read_next_chunk(reader)?;
+            for index in 0.. {
+                // Writer has stopped because of an error
+                if permit_receiver.recv().is_err() {
+                    return Ok(());
+                }
+                let next_chunk = read_next_chunk(reader)?;
+                let is_last = next_chunk.is_empty();
+                if chunk_sender.send((index, chunk, is_last)).is_err() || is_last {
+                    return Ok(());
+                }
+                chunk = next_chunk;
+            }
+            Ok(())
+        });
+        for _ in 0..jobs {
+            let (chunk_receiver, result_sender) = (&chunk_receiver, result_sender.clone());
+            scope.spawn(move || {
+                loop {
+                    let next_chunk = chunk_receiver.lock().expect("Chunk reader does not panic").recv();
+                    let Ok((index, data, is_last)) = next_chunk else {
+                        return;
+                    };
+                    let result = chunk_processor(Chunk { index, data: &data, is_last });
+                    if result_sender.send((index, result)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+        let writing = write_in_order(result_receiver, permit_sender, writer);
+        let reading = reading.join().expect("Reader thread does not panic");
+        writing.and(reading)
+    })
+}
+
+fn write_in_order<W>(results: Receiver<(usize, ChunkResult)>, permits: SyncSender<()>, writer: &mut W) -> Result<(), Error>
+where W: Write + ?Sized {
+    let mut pending_results = BTreeMap::new();
+    let mut next_index = 0;
+    for (index, result) in results {
+        pending_results.insert(index, result);
+        // Errors are returned in order as well, after everything processed before them has been written
+        while let Some(result) = pending_results.remove(&next_index) {
+            writer.write_all(&result?)?;
+            next_index += 1;
+            // Reader has already finished when the permit can not be returned
+            let _ = permits.send(());
+        }
+    }
+    Ok(())
+}
+
+// Fills the whole chunk unless the end of the input is reached first
+fn read_chunk<R: Read + ?Sized>(reader: &mut R, chunk: &mut [u8]) -> Result<usize, Error> {
+    let mut read_size = 0;
+    while read_size < chunk.len() {
+        match reader.read(&mut chunk[read_size..]) {
+            Ok(0) => break,
+            Ok(size) => read_size += size,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(Error::from(error))
+        }
+    }
+    Ok(read_size)
+}
+
+// Writes the container header followed by the encrypted data
+#[deprecated(note = "Use encrypt_for, which also detects modified data")]
+pub fn encrypt_container<R, W>(reader: &mut R, writer: &mut W, key: &RsaPublicKey, original_length: Option<u64>, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    let recipients = vec![Recipient::new(key.fingerprint(), Vec::new())];
+    Header::new(CipherSuite::RsaChunks, recipients, original_length).write_to(writer)?;
+    encrypt_chunks_in_parallel(reader, writer, key, key.encryption_chunk_size(), jobs)
+}
+
+/*
+ * Data is encrypted once with a random content key, which is encrypted for every recipient and stored in the header,
+ * so that any of the recipients can decrypt the data. Every segment of the data is authenticated together with
+ * the header, so decryption fails on any modification
+ */
+pub fn encrypt_for<R, W>(reader: &mut R, writer: &mut W, recipients: &[RsaPublicKey], original_length: Option<u64>) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    encrypt_for_in_parallel(reader, writer, recipients, original_length, Compression::None, 1)
+}
+
+/*
+ * Output can be decrypted the same as the output of encrypt_for, segments are sealed by the given number of threads.
+ * Data is compressed before it is encrypted with the given compression. The length of compressed data depends on its
+ * contents, so secrets should not be compressed together with data an attacker can influence
+ */
+pub fn encrypt_for_in_parallel<R, W>(reader: &mut R, writer: &mut W, recipients: &[RsaPublicKey], original_length: Option<u64>,
+                                     compression: Compression, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    encrypt_segments(reader, writer, recipients, original_length, compression, jobs, None)
+}
+
+/*
+ * Same as encrypt_for, but the plaintext is signed with the key of the sender as well. The signature covers the header
+ * naming the recipients, so that a recipient can not pass the data on as if it was sent to someone else.
+ * It is stored inside the encrypted data, so only the recipients learn who sent it
+ */
+pub fn seal_container<R, W>(reader: &mut R, writer: &mut W, signing_key: &RsaPrivateKey, recipients: &[RsaPublicKey],
+                            original_length: Option<u64>, compression: Compression, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    encrypt_segments(reader, writer, recipients, original_length, compression, jobs, Some(signing_key))
+}
+
+fn encrypt_segments<R, W>(reader: &mut R, writer: &mut W, recipients: &[RsaPublicKey], original_length: Option<u64>,
+                          compression: Compression, jobs: usize, signing_key: Option<&RsaPrivateKey>) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    if recipients.is_empty() {
+        return Err(Error::InvalidKey("At least one recipient is needed".to_string()));
+    }
+    let mut content_key = Zeroizing::new([0u8; CONTENT_KEY_SIZE]);
+    rand::thread_rng().fill(&mut content_key[..]);
+    let recipients = recipients.iter()
+        .map(|key| Ok(Recipient::new(key.fingerprint(), wrap_content_key(key, &content_key)?)))
+        .collect::<Result<Vec<Recipient>, Error>>()?;
+    let mut header = Header::new(CipherSuite::RsaWrappedChaCha20Poly1305, recipients, original_length).with_compression(compression);
+    if signing_key.is_some() {
+        header = header.signed();
+    }
+    let header = header.to_bytes()?;
+    writer.write_all(&header)?;
+    let cipher = SegmentCipher::new(&content_key, &header);
+    let seal_segment = |chunk: Chunk<'_>| Ok(Zeroizing::new(cipher.seal(chunk.index, chunk.data, chunk.is_last)?));
+    match signing_key {
+        None => {
+            let mut compressing_reader = CompressingReader::new(reader, compression)?;
+            process_chunks_in_parallel(&mut compressing_reader, writer, stream::SEGMENT_SIZE, jobs, seal_segment)
+        },
+        Some(signing_key) => {
+            let signer = signing_key.fingerprint();
+            let mut signing_reader = SigningReader::new(reader, signing_key, &header);
+            let mut compressing_reader = CompressingReader::new((&signer.0[..]).chain(&mut signing_reader), compression)?;
+            process_chunks_in_parallel(&mut compressing_reader, writer, stream::SEGMENT_SIZE, jobs, seal_segment)
+        }
+    }
+}
+
+/*
+ * Checks the container header against the key before decrypting and the length of the decrypted data after.
+ * Armored data is recognized and decoded. Data of the older cipher suites and data without the header can not be
+ * authenticated and is rejected, so that a modified header can not turn off the authentication
+ */
+pub fn decrypt_container<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    decrypt_possibly_armored(reader, writer, key, None, false, jobs)
+}
+
+/*
+ * Same as decrypt_container, but data encrypted by earlier versions is decrypted as well: data of the older cipher
+ * suites and data without the header, encrypted before the container was introduced, which is decrypted as it is.
+ * Modifications of such data can not be detected
+ */
+pub fn decrypt_unauthenticated_container<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    decrypt_possibly_armored(reader, writer, key, None, true, jobs)
+}
+
+/*
+ * Decrypts data sealed by seal_container and checks that it was signed by the verifying key.
+ * Nothing is written when the data was signed by another sender, but the signature itself is at the end of the
+ * plaintext, so the plaintext is written before it has been checked and has to be discarded when an error is returned.
+ * open_container_to takes care of that for the output of output_writer
+ */
+pub fn open_container<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, verifying_key: &RsaPublicKey, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    decrypt_possibly_armored(reader, writer, key, Some(verifying_key), false, jobs)
+}
+
+/*
+ * Opens the container into the output and finishes it, nothing is released before the signature has been checked.
+ * A file is only committed after the check. The standard output can not be taken back, so the data, still encrypted,
+ * is kept in a temporary file and opened twice: once to check the signature and once more to write the plaintext
+ */
+pub fn open_container_to<R>(reader: &mut R, output: OutputWriter, key: &RsaPrivateKey, verifying_key: &RsaPublicKey, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized {
+    match output {
+        OutputWriter::File(mut file) => {
+            open_container(reader, &mut file, key, verifying_key, jobs)?;
+            file.commit()
+        },
+        OutputWriter::Stdout(mut stdout) => {
+            let mut spool = SpoolFile::create()?;
+            io::copy(reader, &mut spool)?;
+            spool.rewind()?;
+            open_container(&mut spool, &mut io::sink(), key, verifying_key, jobs)?;
+            spool.rewind()?;
+            open_container(&mut spool, &mut stdout, key, verifying_key, jobs)?;
+            Ok(stdout.flush()?)
+        }
+    }
+}
+
+fn decrypt_possibly_armored<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, verifying_key: Option<&RsaPublicKey>,
+                                  allow_unauthenticated: bool, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    let mut prefix = [0u8; container::MAGIC.len()];
+    let prefix_size = read_chunk(reader, &mut prefix)?;
+    let mut reader = (&prefix[..prefix_size]).chain(reader);
+    if armor::is_armored(&prefix[..prefix_size]) {
+        decrypt_binary_container(&mut ArmorReader::new(BufReader::new(reader))?, writer, key, verifying_key, allow_unauthenticated, jobs)
+    } else {
+        decrypt_binary_container(&mut reader, writer, key, verifying_key, allow_unauthenticated, jobs)
+    }
+}
+
+// Reads the container header of binary or armored data
+pub fn read_container_header<R: Read + ?Sized>(reader: &mut R) -> Result<Header, Error> {
+    let mut prefix = [0u8; container::MAGIC.len()];
+    let prefix_size = read_chunk(reader, &mut prefix)?;
+    let mut reader = (&prefix[..prefix_size]).chain(reader);
+    if armor::is_armored(&prefix[..prefix_size]) {
+        Header::read_from(&mut ArmorReader::new(BufReader::new(reader))?)
+    } else {
+        Header::read_from(&mut reader)
+    }
+}
+
+fn decrypt_binary_container<R, W>(reader: &mut R, writer: &mut W, key: &RsaPrivateKey, verifying_key: Option<&RsaPublicKey>,
+                                  allow_unauthenticated: bool, jobs: usize) -> Result<(), Error>
+where R: Read + Send + ?Sized, W: Write + ?Sized {
+    let chunk_size = key.decryption_chunk_size();
+    let mut magic = [0u8; container::MAGIC.len()];
+    let magic_size = read_chunk(reader, &mut magic)?;
+    if magic_size < magic.len() || magic != container::MAGIC {
+        if verifying_key.is_some() {
+            return Err(Error::InvalidContainer("Missing magic number, data is not a sealed container".to_string()));
+        }
+        if !allow_unauthenticated {
+            return Err(Error::InvalidContainer("Missing magic number, data without the container header is only decrypted when unauthenticated data is allowed".to_string()));
+        }
+        warn!("Data has no container header, decrypting it without any checks");
+        return decrypt_chunks_in_parallel(&mut (&magic[..magic_size]).chain(reader), writer, key, chunk_size, jobs);
+    }
+    let header = Header::read_after_magic_from(reader)?;
+    let fingerprint = key.fingerprint();
+    let Some(recipient) = header.recipient(&fingerprint) else {
+        let recipients = header.recipients.iter().map(|recipient| recipient.key_fingerprint.to_string()).collect::<Vec<_>>();
+        return Err(Error::KeyMismatch(format!("expected key {}, found {}", recipients.join(" or "), fingerprint)));
+    };
+    match (header.is_signed, verifying_key) {
+        (true, None) => return Err(Error::InvalidContainer("Data is signed, it has to be opened with the key of the sender".to_string())),
+        (false, Some(_)) => return Err(Error::Signature("Data is not signed".to_string())),
+        (true, Some(_)) if !header.cipher_suite.is_authenticated() =>
+            return Err(Error::InvalidContainer(format!("Signed data can not use {}", header.cipher_suite))),
+        _ if header.compression != Compression::None && !header.cipher_suite.is_authenticated() =>
+            return Err(Error::InvalidContainer(format!("Compressed data can not use {}", header.cipher_suite))),
+        _ => ()
+    }
+    if !header.cipher_suite.is_authenticated() {
+        if !allow_unauthenticated {
+            return Err(Error::InvalidContainer(format!("Data encrypted with {} is not authenticated, it is only decrypted when unauthenticated data is allowed",
+                header.cipher_suite)));
+        }
+        warn!("Data encrypted with {} is not authenticated, modifications can not be detected", header.cipher_suite);
+    }
+    let mut counting_writer = CountingWriter { inner: writer, count: 0 };
+    match header.cipher_suite {
+        CipherSuite::RsaChunks => decrypt_chunks_in_parallel(reader, &mut counting_writer, key, chunk_size, jobs)?,
+        CipherSuite::RsaWrappedChaCha20 => {
+            let content_key = unwrap_content_key(key, &recipient.wrapped_key)?;
+            apply_content_cipher(reader, &mut counting_writer, &content_key)?;
+        },
+        CipherSuite::RsaWrappedChaCha20Poly1305 => {
+            let content_key = unwrap_content_key(key, &recipient.wrapped_key)?;
+            let header_bytes = header.to_bytes()?;
+            let cipher = SegmentCipher::new(&content_key, &header_bytes);
+            // Only authenticated segments are written, in order, so nothing after a modified segment is written
+            let open_segment = |chunk: Chunk<'_>| cipher.open(chunk.index, chunk.data, chunk.is_last);
+            let sealed_segment_size = stream::SEGMENT_SIZE + stream::TAG_SIZE;
+            match verifying_key {
+                None => {
+                    let mut decompressing_writer = DecompressingWriter::new(&mut counting_writer, header.compression, header.original_length)?;
+                    process_chunks_in_parallel(reader, &mut decompressing_writer, sealed_segment_size, jobs, open_segment)?;
+                    decompressing_writer.finish()?;
+                },
+                Some(verifying_key) => {
+                    let mut verifying_writer = VerifyingWriter::new(&mut counting_writer, verifying_key, &header_bytes);
+                    // The fingerprint of the sender and the signature are compressed together with the data
+                    let signed_length = header.original_length
+                        .map(|length| length + (SENDER_FINGERPRINT_SIZE + verifying_writer.trailer_size()) as u64);
+                    let mut decompressing_writer = DecompressingWriter::new(&mut verifying_writer, header.compression, signed_length)?;
+                    process_chunks_in_parallel(reader, &mut decompressing_writer, sealed_segment_size, jobs, open_segment)?;
+                    decompressing_writer.finish()?;
+                    verifying_writer.finish()?;
+                }
+            }
+        }
+    }
+    check_original_length(&header, counting_writer.count)
+}
+
+fn check_original_length(header: &Header, length: u64) -> Result<(), Error> {
+    match header.original_length {
+        Some(original_length) if original_length != length =>
+            Err(Error::Decryption(format!("Expected {} bytes, decrypted {}", original_length, length))),
+        _ => Ok(())
+    }
+}
+
+const CONTENT_KEY_SIZE: usize = 32;
+const CONTENT_BUFFER_SIZE: usize = 64 * 1024;
+
+// Domain separation of the key wrapping keys from anything else derived from an encapsulated secret
+const KEY_WRAPPING_CONTEXT: &[u8] = b"EULER CONTENT KEY WRAPPING\0";
+const KEY_WRAPPING_TAG_SIZE: usize = 16;
+
+/*
+ * The content key is wrapped for every recipient with RSA-KEM: it is encrypted with ChaCha20-Poly1305 under a key
+ * derived from a secret encapsulated for the recipient. Every secret is random and used once, so the nonce can be
+ * constant. The wrapped key is the encapsulated secret followed by the encrypted content key and its tag
+ */
+fn wrap_content_key(key: &RsaPublicKey, content_key: &[u8; CONTENT_KEY_SIZE]) -> Result<Vec<u8>, Error> {
+    let (secret, mut wrapped_key) = key.encapsulate()?;
+    let encrypted_key = key_wrapping_cipher(&secret).encrypt(&Nonce::default(), &content_key[..])
+        .map_err(|_| Error::InvalidKey("Content key can not be wrapped".to_string()))?;
+    wrapped_key.extend_from_slice(&encrypted_key);
+    Ok(wrapped_key)
+}
+
+// Every failure returns the same error, so that modified wrapped keys do not tell anything about the private key
+fn unwrap_content_key(key: &RsaPrivateKey, wrapped_key: &[u8]) -> Result<Zeroizing<[u8; CONTENT_KEY_SIZE]>, Error> {
+    let unwrapping_error = || Error::Decryption("Content key can not be unwrapped".to_string());
+    let encapsulated_size = wrapped_key.len().checked_sub(CONTENT_KEY_SIZE + KEY_WRAPPING_TAG_SIZE).ok_or_else(unwrapping_error)?;
+    let (encapsulated, encrypted_key) = wrapped_key.split_at(encapsulated_size);
+    let secret = key.decapsulate(encapsulated).map_err(|_| unwrapping_error())?;
+    let content_key = Zeroizing::new(key_wrapping_cipher(&secret).decrypt(&Nonce::default(), encrypted_key)
+        .map_err(|_| unwrapping_error())?);
+    let content_key = content_key.as_slice().try_into().map_err(|_| unwrapping_error())?;
+    Ok(Zeroizing::new(content_key))
+}
+
+fn key_wrapping_cipher(secret: &[u8]) -> ChaCha20Poly1305 {
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_WRAPPING_CONTEXT);
+    hasher.update(secret);
+    let key_wrapping_key = Zeroizing::new(<[u8; 32]>::from(hasher.finalize()));
+    ChaCha20Poly1305::new(&(*key_wrapping_key).into())
+}
+
+/*
+ * Encrypts and decrypts alike. Every content key is random and used for a single stream,
+ * so the nonce can be constant
+ */
+fn apply_content_cipher<R, W>(reader: &mut R, writer: &mut W, content_key: &[u8; CONTENT_KEY_SIZE]) -> Result<(), Error>
+where R: Read + ?Sized, W: Write + ?Sized {
+    let mut cipher = ChaCha20::new(content_key.into(), &[0u8; 12].into());
+    let mut buffer = Zeroizing::new(vec![0u8; CONTENT_BUFFER_SIZE]);
+    loop {
+        let read_size = read_chunk(reader, &mut buffer)?;
+        if read_size == 0 {
+            return Ok(());
+        }
+        cipher.apply_keystream(&mut buffer[..read_size]);
+        writer.write_all(&buffer[..read_size])?;
+    }
+}
+
+// Domain separation of the signatures of sealed data from signatures of anything else made with the same key
+const SEAL_SIGNATURE_CONTEXT: &[u8] = b"EULER SEALED MESSAGE\0";
+const SENDER_FINGERPRINT_SIZE: usize = 32;
+
+// Hash of the data signed when sealing: the context, the container header and the plaintext
+fn seal_hasher(header: &[u8]) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update(SEAL_SIGNATURE_CONTEXT);
+    hasher.update(header);
+    hasher
+}
+
+/*
+ * Returns the data of the inner reader followed by its signature and the length of the signature (2, big-endian),
+ * the signature is made once the inner reader is exhausted
+ */
+struct SigningReader<'a, R: Read + ?Sized> {
+    inner: &'a mut R,
+    key: &'a RsaPrivateKey,
+    hasher: Sha256,
+    // Signature and its length which have not been read yet, starting at the position
+    trailer: Option<Vec<u8>>,
+    position: usize
+}
+
+impl<'a, R: Read + ?Sized> SigningReader<'a, R> {
+
+    fn new(inner: &'a mut R, key: &'a RsaPrivateKey, header: &[u8]) -> SigningReader<'a, R> {
+        SigningReader { inner, key, hasher: seal_hasher(header), trailer: None, position: 0 }
+    }
+
+    fn sign(&mut self) -> Result<Vec<u8>, Error> {
+        let mut trailer = self.key.sign_digest(&self.hasher.finalize_reset().into())?;
+        let signature_size = u16::try_from(trailer.len())
+            .map_err(|_| Error::InvalidKey(format!("Signature too long {}", trailer.len())))?;
+        trailer.extend_from_slice(&signature_size.to_be_bytes());
+        Ok(trailer)
+    }
+}
+
+impl<'a, R: Read + ?Sized> Read for SigningReader<'a, R> {
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        if self.trailer.is_none() {
+            let read_size = self.inner.read(data)?;
+            if read_size > 0 {
+                self.hasher.update(&data[..read_size]);
+                return Ok(read_size);
+            }
+            self.trailer = Some(self.sign()?);
+        }
+        let trailer = self.trailer.as_ref().expect("Trailer has just been set");
+        let read_size = cmp::min(data.len(), trailer.len() - self.position);
+        data[..read_size].copy_from_slice(&trailer[self.position..self.position + read_size]);
+        self.position += read_size;
+        Ok(read_size)
+    }
+}
+
+/*
+ * Counterpart of SigningReader: checks the fingerprint of the sender in front of the data and writes the data to the
+ * inner writer, holding back the trailer, which is only checked by finish
+ */
+struct VerifyingWriter<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    key: &'a RsaPublicKey,
+    hasher: Sha256,
+    has_checked_sender: bool,
+    // Data which may still be a part of the trailer
+    pending: Zeroizing<Vec<u8>>
+}
+
+impl<'a, W: Write + ?Sized> VerifyingWriter<'a, W> {
+
+    fn new(inner: &'a mut W, key: &'a RsaPublicKey, header: &[u8]) -> VerifyingWriter<'a, W> {
+        VerifyingWriter { inner, key, hasher: seal_hasher(header), has_checked_sender: false, pending: Zeroizing::new(Vec::new()) }
+    }
+
+    fn trailer_size(&self) -> usize {
+        self.key.signature_size() + 2
+    }
+
+    fn check_sender(&mut self) -> Result<(), Error> {
+        let sender = KeyFingerprint(self.pending[..SENDER_FINGERPRINT_SIZE].try_into().expect("Fingerprint size is fixed"));
+        if sender != self.key.fingerprint() {
+            return Err(Error::Signature(format!("Data was signed by {}, expected {}", sender, self.key.fingerprint())));
+        }
+        self.pending.drain(..SENDER_FINGERPRINT_SIZE);
+        self.has_checked_sender = true;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        if !self.has_checked_sender || self.pending.len() != self.trailer_size() {
+            return Err(Error::Signature("Signature is missing".to_string()));
+        }
+        let signature_size = self.key.signature_size();
+        if self.pending[signature_size..] != (signature_size as u16).to_be_bytes() {
+            return Err(Error::Signature("Signature does not match the key of the sender".to_string()));
+        }
+        self.key.verify_digest(&self.hasher.finalize_reset().into(), &self.pending[..signature_size])
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for VerifyingWriter<'a, W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(data);
+        if !self.has_checked_sender && self.pending.len() >= SENDER_FINGERPRINT_SIZE {
+            self.check_sender()?;
+        }
+        let trailer_size = self.trailer_size();
+        if self.has_checked_sender && self.pending.len() > trailer_size {
+            let plaintext_size = self.pending.len() - trailer_size;
+            self.hasher.update(&self.pending[..plaintext_size]);
+            self.inner.write_all(&self.pending[..plaintext_size])?;
+            self.pending.drain(..plaintext_size);
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/*
+ * Temporary file holding encrypted data which has to be read twice, only readable by the owner and removed when
+ * dropped
+ */
+struct SpoolFile {
+    file: File,
+    path: PathBuf
+}
+
+impl SpoolFile {
+
+    fn create() -> Result<SpoolFile, Error> {
+        let path = env::temp_dir().join(format!("euler-cryptor-spool-{:016x}.tmp", rand::thread_rng().gen::<u64>()));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        Ok(SpoolFile { file, path })
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
+
+impl Read for SpoolFile {
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        self.file.read(data)
+    }
+}
+
+impl Write for SpoolFile {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.file.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct CountingWriter<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    count: u64
+}
+
+impl<'a, W: Write + ?Sized> Write for CountingWriter<'a, W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written_size = self.inner.write(data)?;
+        self.count += written_size as u64;
+        Ok(written_size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/*
+ * Encrypts everything written to it and writes the encrypted chunks to the inner writer.
+ * Data is only encrypted once a whole chunk is buffered, so the last chunk is written by finish, or when the writer
+ * is dropped, in which case errors are ignored.
+ * Produces the same unauthenticated chunks as encrypt
+ */
+#[deprecated(note = "Unauthenticated, use encrypt_for, which also detects modified data")]
+pub struct EncryptingWriter<'a, W: Write> {
+    inner: Option<W>,
+    key: PreparedPublicKey<'a>,
+    chunk_size: usize,
+    // Plaintext which does not fill a whole chunk yet
+    buffer: Zeroizing<Vec<u8>>
+}
+
+#[allow(deprecated)]
+impl<'a, W: Write> EncryptingWriter<'a, W> {
+
+    pub fn new(inner: W, key: &'a RsaPublicKey) -> EncryptingWriter<'a, W> {
+        EncryptingWriter::with_chunk_size(inner, key, key.encryption_chunk_size())
+    }
+
+    pub fn with_chunk_size(inner: W, key: &'a RsaPublicKey, chunk_size: usize) -> EncryptingWriter<'a, W> {
+        let chunk_size = cmp::max(chunk_size, 1);
+        EncryptingWriter {
+            inner: Some(inner),
+            key: key.prepare(),
+            chunk_size,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size))
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("Inner writer is only taken by finish")
+    }
+
+    // Encrypts the buffered data and returns the inner writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_buffered_chunk()?;
+        let mut inner = self.inner.take().expect("Inner writer is only taken by finish");
+        inner.flush()?;
+        Ok(inner)
+    }
+
+    fn write_buffered_chunk(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let encrypted = self.key.encrypt_bytes(&self.buffer)?;
+        self.buffer.clear();
+        if let Some(inner) = self.inner.as_mut() {
+            inner.write_all(&encrypted)?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, W: Write> Write for EncryptingWriter<'a, W> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written_size = cmp::min(data.len(), self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..written_size]);
+        if self.buffer.len() == self.chunk_size {
+            self.write_buffered_chunk()?;
+        }
+        Ok(written_size)
+    }
+
+    // Chunks are only encrypted when full, so flushing does not write the buffered data
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, W: Write> Drop for EncryptingWriter<'a, W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_buffered_chunk();
+        }
+    }
+}
+
+/*
+ * Reads encrypted data from the inner reader and returns it decrypted.
+ * Corrupted or truncated data is reported as an error of kind InvalidData, but like decrypt it can not detect
+ * modified chunks which still decrypt to the expected prefix
+ */
+#[deprecated(note = "Unauthenticated, use decrypt_container, or decrypt_unauthenticated_container for data encrypted by encrypt")]
+pub struct DecryptingReader<'a, R: Read> {
+    inner: R,
+    key: PreparedPrivateKey<'a>,
+    chunk_size: usize,
+    // Decrypted data which has not been read yet, starting at position
+    decrypted: Zeroizing<Vec<u8>>,
+    position: usize
+}
+
+#[allow(deprecated)]
+impl<'a, R: Read> DecryptingReader<'a, R> {
+
+    pub fn new(inner: R, key: &'a RsaPrivateKey) -> DecryptingReader<'a, R> {
+        DecryptingReader::with_chunk_size(inner, key, key.decryption_chunk_size())
+    }
+
+    // Chunk size has to be a multiple of the decryption chunk size of the key
+    pub fn with_chunk_size(inner: R, key: &'a RsaPrivateKey, chunk_size: usize) -> DecryptingReader<'a, R> {
+        DecryptingReader {
+            inner,
+            key: key.prepare(),
+            chunk_size: cmp::max(chunk_size, 1),
+            decrypted: Zeroizing::new(Vec::new()),
+            position: 0
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Returns false when the inner reader has no more data
+    fn decrypt_next_chunk(&mut self) -> Result<bool, Error> {
+        let mut encrypted = vec![0u8; self.chunk_size];
+        let read_size = read_chunk(&mut self.inner, &mut encrypted)?;
+        if read_size == 0 {
+            return Ok(false);
+        }
+        self.decrypted = Zeroizing::new(self.key.decrypt_bytes(&encrypted[..read_size])?);
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, R: Read> Read for DecryptingReader<'a, R> {
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decrypted.len() {
+            if !self.decrypt_next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let read_size = cmp::min(data.len(), self.decrypted.len() - self.position);
+        data[..read_size].copy_from_slice(&self.decrypted[self.position..self.position + read_size]);
+        self.position += read_size;
+        Ok(read_size)
+    }
+}
+
+pub fn process_chunks_of<F>(input: &mut Box<dyn BufRead>, output: &mut Box<dyn Write>, chunk_size: usize, chunk_processor: F) -> Result<(), Error>
+where F: Fn(&Vec<u8>, &mut Box<dyn Write>) -> Result<(), Error> {
+    // Plaintext passes through the buffer either on the way in or on the way out
+    let mut buffer = Zeroizing::new(vec![0u8; chunk_size]);
     let mut read_buffer_size = 0;
     let mut read_bytes_size = 1;
     while read_bytes_size != 0 {
         read_bytes_size = input.read(&mut buffer[read_buffer_size..])?;
-        read_buffer_size = read_buffer_size + read_bytes_size;
+        read_buffer_size += read_bytes_size;
         let has_finished_reading_chunk = (read_bytes_size == 0 && read_buffer_size > 0) || (read_buffer_size == chunk_size);
         if has_finished_reading_chunk {
-            let read_bytes = buffer[0..read_buffer_size].to_vec();
+            let read_bytes = Zeroizing::new(buffer[0..read_buffer_size].to_vec());
             read_buffer_size = 0;
             chunk_processor(&read_bytes, output)?;
         }
@@ -53,42 +930,458 @@ where F: Fn(&Vec<u8>, &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-pub fn input_reader(input: &Option<String>) -> Result<Box<dyn BufRead>, anyhow::Error> {
+// Known for regular files only
+pub fn input_length(input: &Option<String>) -> Result<Option<u64>, Error> {
+    match input {
+        Some(input_path) => {
+            let metadata = fs::metadata(input_path)?;
+            Ok(metadata.is_file().then_some(metadata.len()))
+        },
+        None => Ok(None)
+    }
+}
+
+pub fn input_reader(input: &Option<String>) -> Result<Box<dyn BufRead + Send>, Error> {
     match input {
         Some(input_path) =>
-            file_reader(&input_path),
+            file_reader(input_path),
         None =>
             stdin_reader()
     }
 }
 
-fn file_reader(input_path: &str) -> Result<Box<dyn BufRead>, anyhow::Error> {
+fn file_reader(input_path: &str) -> Result<Box<dyn BufRead + Send>, Error> {
     let file = OpenOptions::new().read(true).open(input_path)?;
     Ok(Box::new(BufReader::new(file)))
 }
 
-fn stdin_reader() -> Result<Box<dyn BufRead>, anyhow::Error> {
-    Ok(Box::new(BufReader::new(io::stdin().lock())))
+// Not locked, so that the input can be read by another thread
+fn stdin_reader() -> Result<Box<dyn BufRead + Send>, Error> {
+    Ok(Box::new(BufReader::new(io::stdin())))
 }
 
-pub fn output_writer(output: &Option<String>) -> Result<Box<dyn Write>, anyhow::Error> {
+/*
+ * Standard output or a file which is only replaced by finish, so that a failed command does not leave a partial
+ * or truncated file behind
+ */
+pub enum OutputWriter {
+    File(AtomicFile),
+    Stdout(io::StdoutLock<'static>)
+}
+
+impl OutputWriter {
+
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            OutputWriter::File(file) => file.commit(),
+            OutputWriter::Stdout(mut stdout) => Ok(stdout.flush()?)
+        }
+    }
+}
+
+impl Write for OutputWriter {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::File(file) => file.write(data),
+            OutputWriter::Stdout(stdout) => stdout.write(data)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::File(file) => file.flush(),
+            OutputWriter::Stdout(stdout) => stdout.flush()
+        }
+    }
+}
+
+pub fn output_writer(output: &Option<String>) -> Result<OutputWriter, Error> {
     match output {
         Some(output_path) =>
-            file_writer(&output_path),
+            Ok(OutputWriter::File(AtomicFile::create(Path::new(output_path))?)),
         None =>
             stdout_writer()
     }
 }
 
-fn file_writer(output_path: &str) -> Result<Box<dyn Write>, anyhow::Error> {
-    let file = OpenOptions::new().write(true).create(true).open(output_path)?;
-    Ok(Box::new(file))
+// Output file is only readable by the owner, for exported private keys
+pub fn private_output_writer(output: &Option<String>) -> Result<OutputWriter, Error> {
+    match output {
+        Some(output_path) =>
+            Ok(OutputWriter::File(AtomicFile::create_private(Path::new(output_path))?)),
+        None =>
+            stdout_writer()
+    }
 }
 
-fn stdout_writer() -> Result<Box<dyn Write>, anyhow::Error> {
-    Ok(Box::new(io::stdout().lock()))
+fn stdout_writer() -> Result<OutputWriter, Error> {
+    Ok(OutputWriter::Stdout(io::stdout().lock()))
 }
 
-pub fn write_bytes(bytes: &Vec<u8>, write: &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
+pub fn write_bytes<W: Write + ?Sized>(bytes: &[u8], write: &mut W) -> Result<(), Error> {
     write.write_all(bytes).map_err(Error::from)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::armor::ArmorWriter;
+    use crate::crypto::KeyPair;
+    use crate::crypto::tests::{predefined_keys, get_random_bytes};
+    use crate::test_util::TemporaryDirectory;
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_encrypt_and_decrypt_streams() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let mut writer = EncryptingWriter::new(Vec::new(), &public_key);
+        for part in input.chunks(7) {
+            writer.write_all(part).unwrap();
+        }
+        let encrypted = writer.finish().unwrap();
+        assert_eq!(encrypted, public_key.encrypt_bytes(&input).unwrap());
+        let mut decrypted = Vec::new();
+        io::copy(&mut DecryptingReader::new(encrypted.as_slice(), &private_key), &mut decrypted).unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_encrypt_last_chunk_when_writer_is_dropped() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let mut encrypted = Vec::new();
+        {
+            let mut writer = EncryptingWriter::new(&mut encrypted, &public_key);
+            writer.write_all(b"Hello").unwrap();
+        }
+        let mut decrypted = String::new();
+        DecryptingReader::new(encrypted.as_slice(), &private_key).read_to_string(&mut decrypted).unwrap();
+        assert_eq!(decrypted, "Hello");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_report_truncated_stream() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let encrypted = public_key.encrypt_bytes(&get_random_bytes(100)).unwrap();
+        let truncated = &encrypted[..encrypted.len() - 3];
+        let mut reader = DecryptingReader::new(truncated, &private_key);
+        let error = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let result = decrypt(&mut &truncated[..], &mut io::sink(), &private_key, private_key.decryption_chunk_size());
+        assert!(matches!(result, Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_encrypt_and_decrypt_in_parallel_same_as_sequentially() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(10000);
+        let mut encrypted = Vec::new();
+        encrypt(&mut input.as_slice(), &mut encrypted, &public_key, public_key.encryption_chunk_size()).unwrap();
+        for jobs in [1, 2, 8] {
+            let mut encrypted_in_parallel = Vec::new();
+            encrypt_in_parallel(&mut input.as_slice(), &mut encrypted_in_parallel, &public_key, public_key.encryption_chunk_size(), jobs).unwrap();
+            assert_eq!(encrypted_in_parallel, encrypted);
+            let mut decrypted = Vec::new();
+            decrypt_in_parallel(&mut encrypted.as_slice(), &mut decrypted, &private_key, private_key.decryption_chunk_size(), jobs).unwrap();
+            assert_eq!(decrypted, input);
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_write_chunks_before_error_when_decrypting_in_parallel() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let mut encrypted = public_key.encrypt_bytes(&input).unwrap();
+        let corrupted_chunk = 50 * private_key.decryption_chunk_size();
+        encrypted[corrupted_chunk..corrupted_chunk + private_key.decryption_chunk_size()].fill(0);
+        let mut decrypted = Vec::new();
+        let result = decrypt_in_parallel(&mut encrypted.as_slice(), &mut decrypted, &private_key, private_key.decryption_chunk_size(), 4);
+        assert!(matches!(result, Err(Error::Decryption(_))));
+        assert_eq!(decrypted, &input[..50 * public_key.encryption_chunk_size()]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_encrypt_and_decrypt_container() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let mut encrypted = Vec::new();
+        encrypt_container(&mut input.as_slice(), &mut encrypted, &public_key, Some(1000), 2).unwrap();
+        let header = Header::read_from(&mut encrypted.as_slice()).unwrap();
+        let recipients = vec![Recipient::new(public_key.fingerprint(), Vec::new())];
+        assert_eq!(header, Header::new(CipherSuite::RsaChunks, recipients, Some(1000)));
+        let header_size = header.to_bytes().unwrap().len();
+        assert_eq!(&encrypted[header_size..], public_key.encrypt_bytes(&input).unwrap());
+        let mut decrypted = Vec::new();
+        decrypt_unauthenticated_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 2).unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[test]
+    fn should_decrypt_data_without_container_header() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let encrypted = public_key.encrypt_bytes(&input).unwrap();
+        let result = decrypt_container(&mut encrypted.as_slice(), &mut io::sink(), &private_key, 1);
+        assert!(matches!(result, Err(Error::InvalidContainer(_))));
+        let mut decrypted = Vec::new();
+        decrypt_unauthenticated_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 1).unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn should_check_container_key_and_length() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let mut encrypted = Vec::new();
+        encrypt_container(&mut get_random_bytes(1000).as_slice(), &mut encrypted, &public_key, Some(1000), 1).unwrap();
+        let KeyPair { private_key: other_private_key, .. } = KeyPair::generate(512).unwrap();
+        let result = decrypt_container(&mut encrypted.as_slice(), &mut io::sink(), &other_private_key, 1);
+        assert!(matches!(result, Err(Error::KeyMismatch(_))));
+        let truncated = &encrypted[..encrypted.len() - private_key.decryption_chunk_size()];
+        let result = decrypt_unauthenticated_container(&mut &truncated[..], &mut io::sink(), &private_key, 1);
+        assert!(matches!(result, Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn should_encrypt_for_several_recipients() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let other_key_pair = KeyPair::generate(512).unwrap();
+        let input = get_random_bytes(100000);
+        let mut encrypted = Vec::new();
+        let recipients = [public_key, other_key_pair.public_key.clone()];
+        encrypt_for(&mut input.as_slice(), &mut encrypted, &recipients, Some(100000)).unwrap();
+        let header = Header::read_from(&mut encrypted.as_slice()).unwrap();
+        assert_eq!(header.cipher_suite, CipherSuite::RsaWrappedChaCha20Poly1305);
+        assert_eq!(header.recipients.len(), 2);
+        for private_key in [&private_key, &other_key_pair.private_key] {
+            let mut decrypted = Vec::new();
+            decrypt_container(&mut encrypted.as_slice(), &mut decrypted, private_key, 1).unwrap();
+            assert_eq!(decrypted, input);
+        }
+        let KeyPair { private_key: third_private_key, .. } = KeyPair::generate(512).unwrap();
+        let result = decrypt_container(&mut encrypted.as_slice(), &mut io::sink(), &third_private_key, 1);
+        assert!(matches!(result, Err(Error::KeyMismatch(_))));
+        let result = encrypt_for(&mut input.as_slice(), &mut io::sink(), &[], None);
+        assert!(matches!(result, Err(Error::InvalidKey(_))));
+    }
+
+    #[test]
+    fn should_decrypt_armored_container() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let mut armor_writer = ArmorWriter::new(Vec::new()).unwrap();
+        encrypt_for(&mut input.as_slice(), &mut armor_writer, std::slice::from_ref(&public_key), Some(1000)).unwrap();
+        let armored = armor_writer.finish().unwrap();
+        assert_eq!(read_container_header(&mut armored.as_slice()).unwrap().recipients[0].key_fingerprint, public_key.fingerprint());
+        let mut decrypted = Vec::new();
+        decrypt_container(&mut armored.as_slice(), &mut decrypted, &private_key, 1).unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[test]
+    fn should_return_same_error_for_any_modified_wrapped_key() {
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let content_key = Zeroizing::new([5u8; CONTENT_KEY_SIZE]);
+        let wrapped_key = wrap_content_key(&public_key, &content_key).unwrap();
+        assert_eq!(unwrap_content_key(&private_key, &wrapped_key).unwrap(), content_key);
+        let modified = |position: usize| {
+            let mut modified_key = wrapped_key.clone();
+            modified_key[position] ^= 1;
+            modified_key
+        };
+        let mut larger_than_modulo = wrapped_key.clone();
+        larger_than_modulo[..64].fill(0xff);
+        for modified_key in [modified(0), modified(63), modified(64), modified(wrapped_key.len() - 1),
+                             wrapped_key[1..].to_vec(), wrapped_key[..40].to_vec(), larger_than_modulo] {
+            let error = unwrap_content_key(&private_key, &modified_key).unwrap_err();
+            assert_eq!(error.to_string(), unwrap_content_key(&private_key, &[]).unwrap_err().to_string());
+        }
+    }
+
+    #[test]
+    fn should_decrypt_unauthenticated_container() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(1000);
+        let content_key = [3u8; CONTENT_KEY_SIZE];
+        let recipients = vec![Recipient::new(public_key.fingerprint(), wrap_content_key(&public_key, &content_key).unwrap())];
+        let mut encrypted = Header::new(CipherSuite::RsaWrappedChaCha20, recipients, Some(1000)).to_bytes().unwrap();
+        apply_content_cipher(&mut input.as_slice(), &mut encrypted, &content_key).unwrap();
+        let result = decrypt_container(&mut encrypted.as_slice(), &mut io::sink(), &private_key, 1);
+        assert!(matches!(result, Err(Error::InvalidContainer(_))));
+        let mut decrypted = Vec::new();
+        decrypt_unauthenticated_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 1).unwrap();
+        assert_eq!(decrypted, input);
+    }
+
+    #[test]
+    fn should_not_decrypt_container_with_downgraded_cipher_suite() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let mut encrypted = Vec::new();
+        encrypt_for(&mut get_random_bytes(1000).as_slice(), &mut encrypted, std::slice::from_ref(&public_key), Some(1000)).unwrap();
+        for cipher_suite in [CipherSuite::RsaChunks, CipherSuite::RsaWrappedChaCha20] {
+            let mut downgraded = encrypted.clone();
+            downgraded[container::MAGIC.len() + 1] = cipher_suite as u8;
+            let mut decrypted = Vec::new();
+            let result = decrypt_container(&mut downgraded.as_slice(), &mut decrypted, &private_key, 1);
+            assert!(matches!(result, Err(Error::InvalidContainer(_))));
+            assert!(decrypted.is_empty());
+        }
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_empty_input() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let mut encrypted = Vec::new();
+        encrypt_for(&mut io::empty(), &mut encrypted, std::slice::from_ref(&public_key), Some(0)).unwrap();
+        let mut decrypted = Vec::new();
+        decrypt_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 1).unwrap();
+        assert!(decrypted.is_empty());
+    }
+
+    #[test]
+    fn should_detect_modified_data() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(3 * stream::SEGMENT_SIZE + 100);
+        let mut encrypted = Vec::new();
+        encrypt_for_in_parallel(&mut input.as_slice(), &mut encrypted, std::slice::from_ref(&public_key), None, Compression::None, 2).unwrap();
+        let header_size = read_container_header(&mut encrypted.as_slice()).unwrap().to_bytes().unwrap().len();
+        let sealed_segment_size = stream::SEGMENT_SIZE + stream::TAG_SIZE;
+        let decrypt = |encrypted: &[u8]| decrypt_container(&mut &encrypted[..], &mut io::sink(), &private_key, 2);
+        decrypt(&encrypted).unwrap();
+        let mut flipped_bit = encrypted.clone();
+        flipped_bit[header_size + sealed_segment_size + 10] ^= 1;
+        assert!(matches!(decrypt(&flipped_bit), Err(Error::Decryption(_))));
+        let mut swapped_segments = encrypted[..header_size].to_vec();
+        swapped_segments.extend_from_slice(&encrypted[header_size + sealed_segment_size..header_size + 2 * sealed_segment_size]);
+        swapped_segments.extend_from_slice(&encrypted[header_size..header_size + sealed_segment_size]);
+        swapped_segments.extend_from_slice(&encrypted[header_size + 2 * sealed_segment_size..]);
+        assert!(matches!(decrypt(&swapped_segments), Err(Error::Decryption(_))));
+        let truncated = &encrypted[..header_size + 3 * sealed_segment_size];
+        assert!(matches!(decrypt(truncated), Err(Error::Decryption(_))));
+        let mut appended = encrypted.clone();
+        appended.extend_from_slice(&encrypted[header_size..header_size + sealed_segment_size]);
+        assert!(matches!(decrypt(&appended), Err(Error::Decryption(_))));
+        // Claimed original length is authenticated together with the rest of the header
+        let mut modified_header = encrypted.clone();
+        modified_header[container::MAGIC.len() + 2] = 1;
+        assert!(matches!(decrypt(&modified_header), Err(Error::Decryption(_))));
+    }
+
+    #[test]
+    fn should_not_write_data_after_modified_segment() {
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let input = get_random_bytes(4 * stream::SEGMENT_SIZE);
+        let mut encrypted = Vec::new();
+        encrypt_for(&mut input.as_slice(), &mut encrypted, std::slice::from_ref(&public_key), None).unwrap();
+        let third_segment = encrypted.len() - 2 * (stream::SEGMENT_SIZE + stream::TAG_SIZE);
+        encrypted[third_segment] ^= 1;
+        let mut decrypted = Vec::new();
+        let result = decrypt_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 3);
+        assert!(matches!(result, Err(Error::Decryption(_))));
+        assert_eq!(decrypted, &input[..2 * stream::SEGMENT_SIZE]);
+    }
+
+    #[test]
+    fn should_seal_and_open_container() {
+        let sender = KeyPair::generate(512).unwrap();
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let input = get_random_bytes(2 * stream::SEGMENT_SIZE);
+        let mut sealed = Vec::new();
+        seal_container(&mut input.as_slice(), &mut sealed, &sender.private_key, std::slice::from_ref(&public_key), Some(input.len() as u64), Compression::None, 2).unwrap();
+        assert!(read_container_header(&mut sealed.as_slice()).unwrap().is_signed);
+        let mut opened = Vec::new();
+        open_container(&mut sealed.as_slice(), &mut opened, &private_key, &sender.public_key, 2).unwrap();
+        assert_eq!(opened, input);
+        let result = decrypt_container(&mut sealed.as_slice(), &mut io::sink(), &private_key, 1);
+        assert!(matches!(result, Err(Error::InvalidContainer(_))));
+        let result = open_container(&mut sealed.as_slice(), &mut io::sink(), &private_key, &public_key, 1);
+        assert!(matches!(result, Err(Error::Signature(_))));
+        let mut encrypted = Vec::new();
+        encrypt_for(&mut input.as_slice(), &mut encrypted, std::slice::from_ref(&public_key), None).unwrap();
+        let result = open_container(&mut encrypted.as_slice(), &mut io::sink(), &private_key, &sender.public_key, 1);
+        assert!(matches!(result, Err(Error::Signature(_))));
+    }
+
+    #[test]
+    fn should_reject_signature_of_other_sender() {
+        let sender = KeyPair::generate(512).unwrap();
+        let impostor = KeyPair::generate(512).unwrap();
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let input = get_random_bytes(1000);
+        let mut sealed = Vec::new();
+        seal_container(&mut input.as_slice(), &mut sealed, &impostor.private_key, std::slice::from_ref(&public_key), None, Compression::None, 1).unwrap();
+        let mut opened = Vec::new();
+        open_container(&mut sealed.as_slice(), &mut opened, &private_key, &impostor.public_key, 1).unwrap();
+        assert_eq!(opened, input);
+        // Sender fingerprint replaced, the signature still belongs to the impostor
+        let header = read_container_header(&mut sealed.as_slice()).unwrap();
+        let header_size = header.to_bytes().unwrap().len();
+        let content_key = unwrap_content_key(&private_key, &header.recipients[0].wrapped_key).unwrap();
+        let cipher = SegmentCipher::new(&content_key, &sealed[..header_size]);
+        let mut payload = cipher.open(0, &sealed[header_size..], true).unwrap().to_vec();
+        payload[..SENDER_FINGERPRINT_SIZE].copy_from_slice(&sender.public_key.fingerprint().0);
+        let mut forged = sealed[..header_size].to_vec();
+        forged.extend_from_slice(&cipher.seal(0, &payload, true).unwrap());
+        // The plaintext is written before the signature at its end is checked, open_container_to discards it
+        let mut opened = Vec::new();
+        let result = open_container(&mut forged.as_slice(), &mut opened, &private_key, &sender.public_key, 1);
+        assert!(matches!(result, Err(Error::Signature(_))));
+        assert_eq!(opened, input);
+    }
+
+    #[test]
+    fn should_only_commit_output_when_signature_is_valid() {
+        let sender = KeyPair::generate(512).unwrap();
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let input = get_random_bytes(1000);
+        let mut sealed = Vec::new();
+        seal_container(&mut input.as_slice(), &mut sealed, &sender.private_key, std::slice::from_ref(&public_key), None, Compression::None, 1).unwrap();
+        // Last byte of the signature modified, the sender still matches
+        let header = read_container_header(&mut sealed.as_slice()).unwrap();
+        let header_size = header.to_bytes().unwrap().len();
+        let content_key = unwrap_content_key(&private_key, &header.recipients[0].wrapped_key).unwrap();
+        let cipher = SegmentCipher::new(&content_key, &sealed[..header_size]);
+        let mut payload = cipher.open(0, &sealed[header_size..], true).unwrap().to_vec();
+        let signature_end = payload.len() - 3;
+        payload[signature_end] ^= 1;
+        let mut forged = sealed[..header_size].to_vec();
+        forged.extend_from_slice(&cipher.seal(0, &payload, true).unwrap());
+        let directory = TemporaryDirectory::create();
+        let output_path = directory.path().join("opened.bin");
+        let output = output_writer(&Some(output_path.to_string_lossy().to_string())).unwrap();
+        let result = open_container_to(&mut forged.as_slice(), output, &private_key, &sender.public_key, 1);
+        assert!(matches!(result, Err(Error::Signature(_))));
+        assert!(!output_path.exists());
+        let output = output_writer(&Some(output_path.to_string_lossy().to_string())).unwrap();
+        open_container_to(&mut sealed.as_slice(), output, &private_key, &sender.public_key, 1).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), input);
+    }
+
+    #[test]
+    fn should_compress_before_encrypting() {
+        let sender = KeyPair::generate(512).unwrap();
+        let KeyPair { public_key, private_key } = KeyPair::generate(512).unwrap();
+        let input = b"2024-01-01 12:00:00 INFO request handled\n".repeat(10000);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut encrypted = Vec::new();
+            let recipients = std::slice::from_ref(&public_key);
+            encrypt_for_in_parallel(&mut input.as_slice(), &mut encrypted, recipients, Some(input.len() as u64), compression, 2).unwrap();
+            assert!(encrypted.len() < input.len() / 10);
+            assert_eq!(read_container_header(&mut encrypted.as_slice()).unwrap().compression, compression);
+            let mut decrypted = Vec::new();
+            decrypt_container(&mut encrypted.as_slice(), &mut decrypted, &private_key, 2).unwrap();
+            assert_eq!(decrypted, input);
+            let mut sealed = Vec::new();
+            seal_container(&mut input.as_slice(), &mut sealed, &sender.private_key, recipients, None, compression, 1).unwrap();
+            let mut opened = Vec::new();
+            open_container(&mut sealed.as_slice(), &mut opened, &private_key, &sender.public_key, 1).unwrap();
+            assert_eq!(opened, input);
+        }
+    }
+}
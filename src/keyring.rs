@@ -0,0 +1,240 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::warn;
+use crate::Error;
+use crate::crypto::{KeyFingerprint, RsaPrivateKey, RsaPublicKey};
+use crate::io;
+
+const PUBLIC_KEY_SUFFIX: &str = "pub";
+const PRIVATE_KEY_SUFFIX: &str = "sec";
+// Shortest fingerprint prefix accepted in lookups, shorter prefixes match too many keys by chance
+const MINIMAL_FINGERPRINT_PREFIX: usize = 8;
+
+/*
+ * Directory of key pairs stored as <name>_pub.pem and <name>_sec.pem, the same layout as generated by
+ * generate-key-pair. Keys are looked up by name or by fingerprint, either of the keys of a pair may be missing
+ */
+pub struct Keyring {
+    directory: PathBuf
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyringEntry {
+    pub name: String,
+    pub fingerprint: KeyFingerprint,
+    pub public_key_path: Option<PathBuf>,
+    pub private_key_path: Option<PathBuf>
+}
+
+impl Keyring {
+
+    pub fn new(directory: PathBuf) -> Keyring {
+        Keyring { directory }
+    }
+
+    // $XDG_DATA_HOME/euler-cryptor, $XDG_DATA_HOME defaults to ~/.local/share
+    pub fn default_directory() -> Result<PathBuf, Error> {
+        let data_home = match env::var_os("XDG_DATA_HOME").filter(|data_home| !data_home.is_empty()) {
+            Some(data_home) => PathBuf::from(data_home),
+            None => {
+                let home = env::var_os("HOME").filter(|home| !home.is_empty())
+                    .ok_or(Error::KeyNotFound("Neither XDG_DATA_HOME nor HOME is set, the keyring directory is unknown".to_string()))?;
+                Path::new(&home).join(".local").join("share")
+            }
+        };
+        Ok(data_home.join("euler-cryptor"))
+    }
+
+    pub fn open_default() -> Result<Keyring, Error> {
+        Ok(Keyring::new(Keyring::default_directory()?))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn public_key_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{}_{}.pem", name, PUBLIC_KEY_SUFFIX))
+    }
+
+    pub fn private_key_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{}_{}.pem", name, PRIVATE_KEY_SUFFIX))
+    }
+
+    /*
+     * Sorted by name, a missing directory is an empty keyring. Key files which can not be read are skipped with
+     * a warning, so that a single broken file does not hide all the other keys
+     */
+    pub fn entries(&self) -> Result<Vec<KeyringEntry>, Error> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for directory_entry in fs::read_dir(&self.directory)? {
+            let file_name = directory_entry?.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let name = [PUBLIC_KEY_SUFFIX, PRIVATE_KEY_SUFFIX].iter()
+                .find_map(|suffix| file_name.strip_suffix(&format!("_{}.pem", suffix)));
+            if let Some(name) = name {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names.iter().filter_map(|name| match self.entry(name) {
+            Ok(entry) => Some(entry),
+            Err(error) => {
+                warn!("Skipping key {} in {}: {}", name, self.directory.display(), error);
+                None
+            }
+        }).collect())
+    }
+
+    fn entry(&self, name: &str) -> Result<KeyringEntry, Error> {
+        let public_key_path = Some(self.public_key_path(name)).filter(|path| path.exists());
+        let private_key_path = Some(self.private_key_path(name)).filter(|path| path.exists());
+        let fingerprint = match (&public_key_path, &private_key_path) {
+            (Some(path), _) => io::read_public_key_from(path)?.fingerprint(),
+            (None, Some(path)) => io::read_private_key_from(path)?.fingerprint(),
+            (None, None) => return Err(Error::KeyNotFound(format!("No key named {} in {}", name, self.directory.display())))
+        };
+        Ok(KeyringEntry { name: name.to_string(), fingerprint, public_key_path, private_key_path })
+    }
+
+    /*
+     * Finds the key pair with the given name, or else the one whose fingerprint is the query or starts with it,
+     * with or without the SHA256: prefix
+     */
+    pub fn find(&self, query: &str) -> Result<KeyringEntry, Error> {
+        let entries = self.entries()?;
+        if let Some(entry) = entries.iter().find(|entry| entry.name == query) {
+            return Ok(entry.clone());
+        }
+        let hex_query = query.strip_prefix("SHA256:").unwrap_or(query).to_lowercase();
+        let matching_entries = if hex_query.len() < MINIMAL_FINGERPRINT_PREFIX {
+            Vec::new()
+        } else {
+            entries.into_iter()
+                .filter(|entry| entry.fingerprint.to_string()["SHA256:".len()..].starts_with(&hex_query))
+                .collect::<Vec<_>>()
+        };
+        match matching_entries.as_slice() {
+            [] => Err(Error::KeyNotFound(format!("No key named {} or with that fingerprint in {}", query, self.directory.display()))),
+            [entry] => Ok(entry.clone()),
+            _ => {
+                let names = matching_entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>();
+                Err(Error::KeyNotFound(format!("Fingerprint {} matches several keys: {}", query, names.join(", "))))
+            }
+        }
+    }
+
+    pub fn public_key(&self, query: &str) -> Result<RsaPublicKey, Error> {
+        let entry = self.find(query)?;
+        match (&entry.public_key_path, &entry.private_key_path) {
+            (Some(path), _) => io::read_public_key_from(path),
+            (None, Some(path)) => io::read_private_key_from(path)?.public_key()
+                .ok_or(Error::KeyNotFound(format!("Key {} has no public key", entry.name))),
+            (None, None) => Err(Error::KeyNotFound(format!("Key {} has no public key", entry.name)))
+        }
+    }
+
+    pub fn private_key(&self, query: &str) -> Result<RsaPrivateKey, Error> {
+        let entry = self.find(query)?;
+        let path = entry.private_key_path.ok_or(Error::KeyNotFound(format!("Key {} has no private key", entry.name)))?;
+        io::read_private_key_from(&path)
+    }
+
+    /*
+     * Copies a public or a private key file into the keyring under the name. The public key is stored next to an
+     * imported private key when the private key knows its public exponent
+     */
+    pub fn import(&self, key_path: &Path, name: &str) -> Result<KeyringEntry, Error> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(Error::InvalidKey(format!("Invalid key name {}", name)));
+        }
+        if self.public_key_path(name).exists() || self.private_key_path(name).exists() {
+            return Err(Error::InvalidKey(format!("Key {} already exists in {}", name, self.directory.display())));
+        }
+        io::create_private_directory(&self.directory)?;
+        match io::read_public_key_from(key_path) {
+            Ok(public_key) => io::save_public_key_to(&public_key, &self.public_key_path(name))?,
+            Err(_) => {
+                let private_key = io::read_private_key_from(key_path)?;
+                io::save_private_key_to(&private_key, &self.private_key_path(name))?;
+                if let Some(public_key) = private_key.public_key() {
+                    io::save_public_key_to(&public_key, &self.public_key_path(name))?;
+                }
+            }
+        }
+        self.entry(name)
+    }
+
+    // Removes both keys of the pair and returns what was removed
+    pub fn delete(&self, query: &str) -> Result<KeyringEntry, Error> {
+        let entry = self.find(query)?;
+        for path in entry.public_key_path.iter().chain(entry.private_key_path.iter()) {
+            fs::remove_file(path)?;
+        }
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::crypto::tests::predefined_keys;
+    use crate::test_util::TemporaryDirectory;
+
+    #[test]
+    fn should_find_keys_by_name_and_fingerprint() {
+        let directory = TemporaryDirectory::create();
+        let keyring = Keyring::new(directory.path().join("keyring"));
+        assert!(keyring.entries().unwrap().is_empty());
+        let KeyPair { public_key, private_key } = predefined_keys();
+        fs::create_dir_all(keyring.directory()).unwrap();
+        io::save_public_key_to(&public_key, &keyring.public_key_path("alice")).unwrap();
+        io::save_private_key_to(&private_key, &keyring.private_key_path("alice")).unwrap();
+        let fingerprint = public_key.fingerprint().to_string();
+        assert_eq!(keyring.public_key("alice").unwrap(), public_key);
+        assert_eq!(keyring.public_key(&fingerprint).unwrap(), public_key);
+        assert_eq!(keyring.private_key(&fingerprint["SHA256:".len()..][..12]).unwrap(), private_key);
+        assert!(matches!(keyring.find(&fingerprint["SHA256:".len()..][..4]), Err(Error::KeyNotFound(_))));
+        assert!(matches!(keyring.find("bob"), Err(Error::KeyNotFound(_))));
+        // A broken key file is skipped, the other keys can still be found
+        fs::write(keyring.public_key_path("broken"), b"not a key").unwrap();
+        let names = keyring.entries().unwrap().into_iter().map(|entry| entry.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["alice".to_string()]);
+        assert_eq!(keyring.public_key(&fingerprint).unwrap(), public_key);
+        assert!(matches!(keyring.find("broken"), Err(Error::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn should_import_and_delete_keys() {
+        let directory = TemporaryDirectory::create();
+        let keyring = Keyring::new(directory.path().join("keyring"));
+        let source = Keyring::new(directory.path().to_path_buf());
+        let KeyPair { public_key, private_key } = predefined_keys();
+        let private_key_path = source.private_key_path("original");
+        io::save_private_key_to(&private_key, &private_key_path).unwrap();
+        let entry = keyring.import(&private_key_path, "alice").unwrap();
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(keyring.directory()).unwrap().permissions()) & 0o777, 0o700);
+        assert_eq!(entry.fingerprint, public_key.fingerprint());
+        assert!(entry.public_key_path.is_some() && entry.private_key_path.is_some());
+        assert_eq!(keyring.public_key("alice").unwrap(), public_key);
+        assert!(matches!(keyring.import(&private_key_path, "alice"), Err(Error::InvalidKey(_))));
+        let public_key_path = source.public_key_path("original");
+        io::save_public_key_to(&public_key, &public_key_path).unwrap();
+        keyring.import(&public_key_path, "bob").unwrap();
+        assert!(matches!(keyring.private_key("bob"), Err(Error::KeyNotFound(_))));
+        // Both keys have the same fingerprint now
+        assert!(matches!(keyring.find(&public_key.fingerprint().to_string()), Err(Error::KeyNotFound(_))));
+        keyring.delete("alice").unwrap();
+        let names = keyring.entries().unwrap().into_iter().map(|entry| entry.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["bob".to_string()]);
+    }
+}
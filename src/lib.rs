@@ -1,8 +1,25 @@
 pub mod io;
-mod primes;
+pub mod atomic_file;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod primes;
 mod euclidean;
 pub mod crypto;
+pub mod prime_certificate;
+pub mod container;
+pub mod armor;
+pub mod keyring;
+pub mod factor;
+mod stream;
+mod compression;
 mod pkcs8;
 mod spki;
 mod pem;
-mod modulo_arithmetic;
\ No newline at end of file
+mod modulo_arithmetic;
+mod constant_time;
+pub mod secret;
+mod error;
+#[cfg(test)]
+mod test_util;
+
+pub use error::Error;
\ No newline at end of file
@@ -1,15 +1,27 @@
-use clap::{ Parser, Subcommand };
+use anyhow::anyhow;
+use clap::{ Parser, Subcommand, ValueEnum };
+use num_bigint::BigInt;
+use euler_cryptor::armor::ArmorWriter;
+use euler_cryptor::container::Compression;
+use euler_cryptor::crypto::{KeyPair, RsaPrivateKey, RsaPublicKey};
+use euler_cryptor::factor::{self, FactorEvent, FactorMethod, FactorOptions};
 use euler_cryptor::io;
-use std::fs;
-use std::path::Path;
+use euler_cryptor::keyring::Keyring;
+use euler_cryptor::primes::{self, PrimeSearchEvent};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 use log::{info, debug};
-use env_logger;
 
 /// Cryptographic utility to help encrypt and decrypt data
 #[derive(Parser)]
 #[command(name = "euler-cryptor")]
 #[command(about = "Command line utility to encrypt and decrypt data", long_about = None)]
 struct CliInterface {
+    /// Directory of the keyring holding the keys looked up by name or fingerprint,
+    /// $XDG_DATA_HOME/euler-cryptor (~/.local/share/euler-cryptor) by default
+    #[arg(long, global = true)]
+    keyring: Option<String>,
     #[command(subcommand)]
     command: Command
 }
@@ -18,73 +30,579 @@ struct CliInterface {
 enum Command {
     /// Generate a key pair (public and private keys)
     GenerateKeyPair {
-        /// Name of the directory where the keys should be generated
-        #[arg(long, default_value = ".")]
-        key_directory: String,
+        /// Name of the directory where the keys should be generated, the keyring directory by default
+        #[arg(long)]
+        key_directory: Option<String>,
         /// Name of the key pair to be generated
         #[arg(long, default_value = "default")]
         key_pair_name: String,
-        /// Size of the key, 2048 is a good default, for better but slower encryption select 3072 or 4096
-        #[arg(long, default_value = "2048")]
-        key_size: u16
+        /// Size of the key, at least 32, 2048 is a good default, for better but slower encryption select 3072 or 4096
+        #[arg(long, default_value = "2048", value_parser = clap::value_parser!(u16).range(euler_cryptor::crypto::MINIMUM_KEY_SIZE as i64..))]
+        key_size: u16,
+        /// Generate provable primes and store their primality certificates next to the keys.
+        /// The certificates reveal the factors of the modulus and should be kept as secret as the private key
+        #[arg(long)]
+        prime_certificates: bool,
+        /// Replace existing keys and certificates with the same name
+        #[arg(long)]
+        force: bool
     },
     /// Use key to encrypt the contents read from the standard input
     Encrypt {
         /// Path to the key to be used
-        #[arg(long, default_value = "default")]
-        key_path: String,
+        #[arg(long, conflicts_with_all = ["recipient", "key"])]
+        key_path: Option<String>,
+        /// Name or fingerprint of the key in the keyring, the key named default is used when no key is given
+        #[arg(long, conflicts_with = "recipient")]
+        key: Option<String>,
+        /// Path to the public key of a recipient or its name or fingerprint in the keyring, can be repeated so that
+        /// any of the recipients can decrypt. The data is always encrypted with a random content key,
+        /// only the content key is encrypted for the recipients
+        #[arg(long)]
+        recipient: Vec<String>,
         /// Path to the file to be encrypted
         #[arg(long)]
         input: Option<String>,
         /// Path to the file to store the results in
         #[arg(long)]
-        output: Option<String>
+        output: Option<String>,
+        /// Compress the contents before encrypting them, decrypt decompresses automatically.
+        /// Do not compress secrets together with data an attacker can influence, the length of the output can then
+        /// reveal the secret (compression oracle, as in CRIME and BREACH)
+        #[arg(long, value_enum, default_value = "none")]
+        compress: CompressionKind,
+        /// Number of threads encrypting the segments, decryption does not depend on it
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+        /// Write the encrypted data as base64 text between BEGIN and END lines, decrypt recognizes it automatically
+        #[arg(long)]
+        armor: bool
     },
     /// Use key to decrypt the contents read from the standard input
     Decrypt {
         /// Path to the key to be used
-        #[arg(long, default_value = "default")]
-        key_path: String,
+        #[arg(long, conflicts_with = "key")]
+        key_path: Option<String>,
+        /// Name or fingerprint of the key in the keyring, the key named default is used when no key is given
+        #[arg(long)]
+        key: Option<String>,
         /// Path to the file to be decrypted
         #[arg(long)]
         input: Option<String>,
         /// Path to the file to store the results in
         #[arg(long)]
+        output: Option<String>,
+        /// Number of threads processing the chunks, the output does not depend on it
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+        /// Also decrypt data encrypted by earlier versions without authentication, modifications of such data
+        /// can not be detected
+        #[arg(long)]
+        allow_unauthenticated: bool
+    },
+    /// Sign the contents with the key of the sender and encrypt them for the recipients
+    Seal {
+        /// Path to the private key of the sender or its name or fingerprint in the keyring
+        #[arg(long)]
+        sign_key: String,
+        /// Path to the public key of a recipient or its name or fingerprint in the keyring, can be repeated so that
+        /// any of the recipients can open the data
+        #[arg(long, required = true)]
+        recipient: Vec<String>,
+        /// Path to the file to be sealed
+        #[arg(long)]
+        input: Option<String>,
+        /// Path to the file to store the results in
+        #[arg(long)]
+        output: Option<String>,
+        /// Compress the contents before encrypting them, open decompresses automatically.
+        /// Do not compress secrets together with data an attacker can influence, the length of the output can then
+        /// reveal the secret (compression oracle, as in CRIME and BREACH)
+        #[arg(long, value_enum, default_value = "none")]
+        compress: CompressionKind,
+        /// Number of threads encrypting the segments, opening does not depend on it
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+        /// Write the sealed data as base64 text between BEGIN and END lines, open recognizes it automatically
+        #[arg(long)]
+        armor: bool
+    },
+    /// Decrypt sealed contents and check that they were signed by the sender.
+    /// Nothing is written before the signature at the end has been checked
+    Open {
+        /// Path to the private key of the recipient
+        #[arg(long, conflicts_with = "key")]
+        key_path: Option<String>,
+        /// Name or fingerprint of the private key in the keyring, the key named default is used when no key is given
+        #[arg(long)]
+        key: Option<String>,
+        /// Path to the public key of the sender or its name or fingerprint in the keyring
+        #[arg(long)]
+        verify_key: String,
+        /// Path to the file to be opened
+        #[arg(long)]
+        input: Option<String>,
+        /// Path to the file to store the results in
+        #[arg(long)]
+        output: Option<String>,
+        /// Number of threads decrypting the segments
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16
+    },
+    /// List the keys in the keyring with their fingerprints
+    ListKeys,
+    /// Copy a public or private key file into the keyring
+    ImportKey {
+        /// Path to the key to be imported
+        #[arg(long)]
+        key_path: String,
+        /// Name of the key in the keyring, the file name without _pub.pem or _sec.pem by default
+        #[arg(long)]
+        name: Option<String>
+    },
+    /// Print a key of the keyring, the public key unless the private key is requested
+    ExportKey {
+        /// Name or fingerprint of the key in the keyring
+        #[arg(long)]
+        key: String,
+        /// Export the private key instead of the public key
+        #[arg(long)]
+        private: bool,
+        /// Path to the file to store the key in
+        #[arg(long)]
         output: Option<String>
+    },
+    /// Remove both keys of a pair from the keyring
+    DeleteKey {
+        /// Name or fingerprint of the key in the keyring
+        #[arg(long)]
+        key: String
+    },
+    /// Print the header of an encrypted file, the private key is not needed
+    FileInfo {
+        /// Path to the encrypted file, the standard input is read when missing
+        #[arg(long)]
+        input: Option<String>
+    },
+    /// Generate a random prime of the given kind and print it to the standard output
+    GeneratePrime {
+        /// Kind of the prime to be generated
+        #[arg(long, value_enum, default_value = "safe")]
+        kind: PrimeKind,
+        /// Size of the prime in bits
+        #[arg(long, default_value = "2048", value_parser = clap::value_parser!(u16).range(32..))]
+        bits: u16
+    },
+    /// Recover the private key of a weak public key by factoring its modulus and print it.
+    /// Only small keys and keys with badly generated primes can be factored
+    Factor {
+        /// Path to the public key to be factored
+        #[arg(long)]
+        key_path: String,
+        /// Method to factor with, all of them from the cheapest one by default
+        #[arg(long, value_enum, default_value = "all")]
+        method: FactorMethodKind,
+        /// Largest prime tried as a divisor
+        #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        trial_division_limit: Option<usize>,
+        /// Number of steps of Fermat's method, which finds primes close to each other
+        #[arg(long)]
+        fermat_iterations: Option<u64>,
+        /// Number of steps of Pollard's rho, which finds primes up to about the square of the steps
+        #[arg(long)]
+        rho_iterations: Option<u64>,
+        /// Number of curves of the elliptic curve method, every curve is another chance to find a prime
+        #[arg(long)]
+        ecm_curves: Option<usize>,
+        /// Stage 1 bound B1 of the elliptic curve method, larger bounds find larger primes with fewer but slower curves
+        #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        ecm_bound: Option<usize>,
+        /// Stage 2 bound B2 of the elliptic curve method, usually about 100 times B1
+        #[arg(long)]
+        ecm_stage_2_bound: Option<usize>,
+        /// Number of threads running the curves of the elliptic curve method, all available processors by default
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: Option<u16>,
+        /// Stage 1 bound B1 of Pollard's p - 1, which finds primes p whose p - 1 only has factors up to B1
+        #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        p_minus_one_bound: Option<usize>,
+        /// Stage 2 bound B2 of Pollard's p - 1, p - 1 can also have a single factor between B1 and B2
+        #[arg(long)]
+        p_minus_one_stage_2_bound: Option<usize>,
+        /// Largest number of digits of the modulus the quadratic sieve is tried for when all methods are tried
+        #[arg(long)]
+        quadratic_sieve_digits: Option<usize>,
+        /// Path to the file to store the recovered private key in
+        #[arg(long)]
+        output: Option<String>
+    },
+    /// Check a primality certificate, optionally that the certified prime is a factor of the key modulus
+    VerifyPrimeCertificate {
+        /// Path to the certificate to be verified
+        #[arg(long)]
+        certificate_path: String,
+        /// Path to the key the certified prime should belong to
+        #[arg(long)]
+        key_path: Option<String>
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum PrimeKind {
+    /// p = 2q + 1 where q is also prime
+    Safe,
+    /// p - 1 and p + 1 have large prime factors r and s, r - 1 has a large prime factor
+    Strong
+}
+
+#[derive(Clone, ValueEnum)]
+enum FactorMethodKind {
+    All,
+    /// Division by the small primes
+    TrialDivision,
+    /// Primes close to each other
+    Fermat,
+    /// Pollard's rho, small primes
+    Rho,
+    /// Lenstra's elliptic curve method, medium primes of moduli of any size
+    Ecm,
+    /// Pollard's p - 1, primes p where p - 1 only has small factors
+    PMinusOne,
+    /// Self-initializing quadratic sieve, any primes of moduli up to about 80 digits
+    Qs
+}
+
+// None for all methods
+impl From<FactorMethodKind> for Option<FactorMethod> {
+    fn from(kind: FactorMethodKind) -> Option<FactorMethod> {
+        match kind {
+            FactorMethodKind::All => None,
+            FactorMethodKind::TrialDivision => Some(FactorMethod::TrialDivision),
+            FactorMethodKind::Fermat => Some(FactorMethod::Fermat),
+            FactorMethodKind::Rho => Some(FactorMethod::PollardRho),
+            FactorMethodKind::Ecm => Some(FactorMethod::EllipticCurve),
+            FactorMethodKind::PMinusOne => Some(FactorMethod::PollardPMinusOne),
+            FactorMethodKind::Qs => Some(FactorMethod::QuadraticSieve)
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum CompressionKind {
+    Zstd,
+    Gzip,
+    None
+}
+
+impl From<CompressionKind> for Compression {
+    fn from(kind: CompressionKind) -> Compression {
+        match kind {
+            CompressionKind::Zstd => Compression::Zstd,
+            CompressionKind::Gzip => Compression::Gzip,
+            CompressionKind::None => Compression::None
+        }
     }
 }
 
 fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
     let cli = CliInterface::parse();
+    let keyring_directory = cli.keyring;
     let command = cli.command;
     match command {
-        Command::GenerateKeyPair { key_directory, key_pair_name, key_size } => {
+        Command::GenerateKeyPair { key_directory, key_pair_name, key_size, prime_certificates, force } => {
             debug!("Generating key pair");
-            fs::create_dir_all(&key_directory)?;
-            let (public_key, private_key) = euler_cryptor::crypto::generate_keys(key_size)?;
+            let key_directory = match key_directory {
+                Some(key_directory) => key_directory,
+                None => open_keyring(&keyring_directory)?.directory().to_string_lossy().into_owned()
+            };
+            euler_cryptor::io::create_private_directory(Path::new(&key_directory))?;
             let public_key_path = euler_cryptor::io::create_key_path(&key_directory, &key_pair_name, "pub");
-            euler_cryptor::io::save_key_to(&public_key, public_key_path.as_path())?;
             let private_key_path = euler_cryptor::io::create_key_path(&key_directory, &key_pair_name, "sec");
-            euler_cryptor::io::save_key_to(&private_key, private_key_path.as_path())?;
+            let p_certificate_path = euler_cryptor::io::create_key_path(&key_directory, &key_pair_name, "p_cert");
+            let q_certificate_path = euler_cryptor::io::create_key_path(&key_directory, &key_pair_name, "q_cert");
+            // Checked before the slow key generation, saving checks again
+            if !force {
+                let mut paths = vec![&public_key_path, &private_key_path];
+                if prime_certificates {
+                    paths.extend([&p_certificate_path, &q_certificate_path]);
+                }
+                if let Some(existing_path) = paths.into_iter().find(|path| path.exists()) {
+                    return Err(anyhow!("{} already exists, use --force to replace it", existing_path.display()));
+                }
+            }
+            let key_pair = if prime_certificates {
+                let (key_pair, p_certificate, q_certificate) = KeyPair::generate_with_certificates(key_size)?;
+                if force {
+                    euler_cryptor::io::force_save_prime_certificate_to(&p_certificate, p_certificate_path.as_path())?;
+                    euler_cryptor::io::force_save_prime_certificate_to(&q_certificate, q_certificate_path.as_path())?;
+                } else {
+                    euler_cryptor::io::save_prime_certificate_to(&p_certificate, p_certificate_path.as_path())?;
+                    euler_cryptor::io::save_prime_certificate_to(&q_certificate, q_certificate_path.as_path())?;
+                }
+                key_pair
+            } else {
+                KeyPair::generate(key_size)?
+            };
+            if force {
+                euler_cryptor::io::force_save_public_key_to(&key_pair.public_key, public_key_path.as_path())?;
+                euler_cryptor::io::force_save_private_key_to(&key_pair.private_key, private_key_path.as_path())?;
+            } else {
+                euler_cryptor::io::save_public_key_to(&key_pair.public_key, public_key_path.as_path())?;
+                euler_cryptor::io::save_private_key_to(&key_pair.private_key, private_key_path.as_path())?;
+            }
             info!("Generated a new key pair {}, {}", key_directory, key_pair_name);
             Ok(())
         },
-        Command::Encrypt { key_path, input, output } => {
+        Command::Encrypt { key_path, key, recipient, input, output, compress, jobs, armor } => {
             debug!("Encrypting input");
+            // Key is the single recipient when no recipients are given
+            let recipients = if !recipient.is_empty() {
+                recipient.iter().map(|recipient| read_public_key(&keyring_directory, recipient)).collect::<Result<Vec<_>, _>>()?
+            } else if let Some(key_path) = key_path {
+                vec![euler_cryptor::io::read_public_key_from(Path::new(&key_path))?]
+            } else {
+                vec![open_keyring(&keyring_directory)?.public_key(key.as_deref().unwrap_or(DEFAULT_KEY_NAME))?]
+            };
             let mut reader = euler_cryptor::io::input_reader(&input)?;
             let mut writer = euler_cryptor::io::output_writer(&output)?;
-            let key = euler_cryptor::io::read_key_from(&Path::new(&key_path))?;
-            let chunk_size = euler_cryptor::crypto::encryption_chunk_size(&key);
-            io::encrypt(&mut reader, &mut writer, &key, chunk_size)
+            let original_length = euler_cryptor::io::input_length(&input)?;
+            if armor {
+                let mut armor_writer = ArmorWriter::new(writer)?;
+                encrypt_input(&mut reader, &mut armor_writer, &recipients, None, original_length, compress.into(), jobs)?;
+                armor_writer.finish()?.finish()?;
+            } else {
+                encrypt_input(&mut reader, &mut writer, &recipients, None, original_length, compress.into(), jobs)?;
+                writer.finish()?;
+            }
+            Ok(())
         },
-        Command::Decrypt { key_path, input, output } => {
+        Command::Seal { sign_key, recipient, input, output, compress, jobs, armor } => {
+            debug!("Sealing input");
+            let signing_key = read_private_key(&keyring_directory, &sign_key)?;
+            let recipients = recipient.iter()
+                .map(|recipient| read_public_key(&keyring_directory, recipient))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut reader = euler_cryptor::io::input_reader(&input)?;
+            let mut writer = euler_cryptor::io::output_writer(&output)?;
+            let original_length = euler_cryptor::io::input_length(&input)?;
+            if armor {
+                let mut armor_writer = ArmorWriter::new(writer)?;
+                encrypt_input(&mut reader, &mut armor_writer, &recipients, Some(&signing_key), original_length, compress.into(), jobs)?;
+                armor_writer.finish()?.finish()?;
+            } else {
+                encrypt_input(&mut reader, &mut writer, &recipients, Some(&signing_key), original_length, compress.into(), jobs)?;
+                writer.finish()?;
+            }
+            Ok(())
+        },
+        Command::Open { key_path, key, verify_key, input, output, jobs } => {
+            debug!("Opening input");
+            let key = select_private_key(&keyring_directory, key_path, key)?;
+            let verifying_key = read_public_key(&keyring_directory, &verify_key)?;
+            let mut reader = euler_cryptor::io::input_reader(&input)?;
+            let writer = euler_cryptor::io::output_writer(&output)?;
+            io::open_container_to(&mut reader, writer, &key, &verifying_key, jobs as usize)?;
+            Ok(())
+        },
+        Command::Decrypt { key_path, key, input, output, jobs, allow_unauthenticated } => {
             debug!("Decrypting input");
+            let key = select_private_key(&keyring_directory, key_path, key)?;
             let mut reader = euler_cryptor::io::input_reader(&input)?;
             let mut writer = euler_cryptor::io::output_writer(&output)?;
-            let key = euler_cryptor::io::read_key_from(&Path::new(&key_path))?;
-            let chunk_size = euler_cryptor::crypto::decryption_chunk_size(&key);
-            io::decrypt(&mut reader, &mut writer, &key, chunk_size)
+            if allow_unauthenticated {
+                io::decrypt_unauthenticated_container(&mut reader, &mut writer, &key, jobs as usize)?;
+            } else {
+                io::decrypt_container(&mut reader, &mut writer, &key, jobs as usize)?;
+            }
+            writer.finish()?;
+            Ok(())
+        },
+        Command::ListKeys => {
+            let keyring = open_keyring(&keyring_directory)?;
+            for entry in keyring.entries()? {
+                let keys = match (&entry.public_key_path, &entry.private_key_path) {
+                    (Some(_), Some(_)) => "public and private",
+                    (Some(_), None) => "public",
+                    _ => "private"
+                };
+                println!("{}\t{}\t{}", entry.name, entry.fingerprint, keys);
+            }
+            Ok(())
+        },
+        Command::ImportKey { key_path, name } => {
+            debug!("Importing key");
+            let name = match name {
+                Some(name) => name,
+                None => key_name_of(Path::new(&key_path)).ok_or(anyhow!("Cannot derive a key name from {}, use --name", key_path))?
+            };
+            let entry = open_keyring(&keyring_directory)?.import(Path::new(&key_path), &name)?;
+            println!("Imported {} {}", entry.name, entry.fingerprint);
+            Ok(())
+        },
+        Command::ExportKey { key, private, output } => {
+            debug!("Exporting key");
+            let keyring = open_keyring(&keyring_directory)?;
+            let serialized = if private {
+                Zeroizing::new(keyring.private_key(&key)?.serialize())
+            } else {
+                Zeroizing::new(keyring.public_key(&key)?.serialize())
+            };
+            let mut writer = if private {
+                euler_cryptor::io::private_output_writer(&output)?
+            } else {
+                euler_cryptor::io::output_writer(&output)?
+            };
+            euler_cryptor::io::write_bytes(&serialized, &mut writer)?;
+            writer.finish()?;
+            Ok(())
+        },
+        Command::DeleteKey { key } => {
+            debug!("Deleting key");
+            let entry = open_keyring(&keyring_directory)?.delete(&key)?;
+            println!("Deleted {} {}", entry.name, entry.fingerprint);
+            Ok(())
+        },
+        Command::FileInfo { input } => {
+            debug!("Reading container header");
+            let mut reader = euler_cryptor::io::input_reader(&input)?;
+            let header = io::read_container_header(&mut reader)?;
+            println!("Format version: {}", header.version);
+            println!("Cipher suite: {}", header.cipher_suite);
+            for recipient in &header.recipients {
+                println!("Recipient key: {}", recipient.key_fingerprint);
+            }
+            println!("Signed: {}", if header.is_signed { "yes" } else { "no" });
+            println!("Compression: {}", header.compression);
+            match header.original_length {
+                Some(original_length) => println!("Original length: {} bytes", original_length),
+                None => println!("Original length: unknown")
+            }
+            Ok(())
+        },
+        Command::GeneratePrime { kind, bits } => {
+            debug!("Generating prime");
+            let prime = match kind {
+                PrimeKind::Safe => primes::random_safe_prime_with_progress(bits as usize, &mut print_search_progress),
+                PrimeKind::Strong => primes::random_strong_prime_with_progress(bits as usize, &mut print_search_progress)
+            };
+            eprintln!();
+            println!("{}", prime);
+            Ok(())
+        },
+        Command::Factor { key_path, method, trial_division_limit, fermat_iterations, rho_iterations, ecm_curves, ecm_bound,
+                          ecm_stage_2_bound, jobs, p_minus_one_bound, p_minus_one_stage_2_bound, quadratic_sieve_digits, output } => {
+            debug!("Factoring modulus");
+            let public_key = euler_cryptor::io::read_public_key_from(Path::new(&key_path))?;
+            let defaults = FactorOptions::default();
+            let options = FactorOptions {
+                trial_division_limit: trial_division_limit.unwrap_or(defaults.trial_division_limit),
+                fermat_iterations: fermat_iterations.unwrap_or(defaults.fermat_iterations),
+                rho_iterations: rho_iterations.unwrap_or(defaults.rho_iterations),
+                ecm_curves: ecm_curves.unwrap_or(defaults.ecm_curves),
+                ecm_bound: ecm_bound.unwrap_or(defaults.ecm_bound),
+                ecm_stage_2_bound: ecm_stage_2_bound.unwrap_or(defaults.ecm_stage_2_bound),
+                ecm_jobs: jobs.map(usize::from).unwrap_or(defaults.ecm_jobs),
+                p_minus_one_bound: p_minus_one_bound.unwrap_or(defaults.p_minus_one_bound),
+                p_minus_one_stage_2_bound: p_minus_one_stage_2_bound.unwrap_or(defaults.p_minus_one_stage_2_bound),
+                quadratic_sieve_digits: quadratic_sieve_digits.unwrap_or(defaults.quadratic_sieve_digits)
+            };
+            let modulo = public_key.modulo();
+            let factorization = match method.into() {
+                Some(method) => factor::factor_with_method(modulo, method, &options, &mut print_factor_progress),
+                None => factor::factor_with_progress(modulo, &options, &mut print_factor_progress)
+            }.ok_or(anyhow!("Could not factor the modulus of {}", key_path))?;
+            eprintln!("Factored with {}:\np = {}\nq = {}", factorization.method, factorization.p, factorization.q);
+            let key_pair = KeyPair::from_factors(&public_key, factorization.p, factorization.q)?;
+            let mut writer = euler_cryptor::io::private_output_writer(&output)?;
+            euler_cryptor::io::write_bytes(&Zeroizing::new(key_pair.private_key.serialize()), &mut writer)?;
+            writer.finish()?;
+            Ok(())
+        },
+        Command::VerifyPrimeCertificate { certificate_path, key_path } => {
+            debug!("Verifying prime certificate");
+            let certificate = euler_cryptor::io::read_prime_certificate_from(Path::new(&certificate_path))?;
+            let prime = certificate.verify()?;
+            if let Some(key_path) = key_path {
+                // Certificate can be checked against either of the keys of the pair
+                let modulo = match euler_cryptor::io::read_public_key_from(Path::new(&key_path)) {
+                    Ok(public_key) => public_key.modulo().clone(),
+                    Err(_) => euler_cryptor::io::read_private_key_from(Path::new(&key_path))?.modulo().clone()
+                };
+                if &modulo % &prime != BigInt::ZERO {
+                    return Err(anyhow!("Certified prime is not a factor of the modulus of {}", key_path));
+                }
+            }
+            println!("Certificate is valid, proven prime: {}", prime);
+            Ok(())
         }
     }
+}
+
+// Signs the input as well when the signing key is given
+fn encrypt_input(reader: &mut Box<dyn BufRead + Send>, writer: &mut dyn Write, recipients: &[RsaPublicKey],
+                 signing_key: Option<&RsaPrivateKey>, original_length: Option<u64>, compression: Compression, jobs: u16)
+                 -> Result<(), anyhow::Error> {
+    match signing_key {
+        Some(signing_key) => io::seal_container(reader, writer, signing_key, recipients, original_length, compression, jobs as usize)?,
+        None => io::encrypt_for_in_parallel(reader, writer, recipients, original_length, compression, jobs as usize)?
+    }
+    Ok(())
+}
+
+const DEFAULT_KEY_NAME: &str = "default";
+
+fn open_keyring(keyring_directory: &Option<String>) -> Result<Keyring, euler_cryptor::Error> {
+    match keyring_directory {
+        Some(keyring_directory) => Ok(Keyring::new(PathBuf::from(keyring_directory))),
+        None => Keyring::open_default()
+    }
+}
+
+// Key file when the path exists, otherwise the name or fingerprint of a key in the keyring
+fn read_public_key(keyring_directory: &Option<String>, path_or_key: &str) -> Result<RsaPublicKey, euler_cryptor::Error> {
+    if Path::new(path_or_key).is_file() {
+        euler_cryptor::io::read_public_key_from(Path::new(path_or_key))
+    } else {
+        open_keyring(keyring_directory)?.public_key(path_or_key)
+    }
+}
+
+fn read_private_key(keyring_directory: &Option<String>, path_or_key: &str) -> Result<RsaPrivateKey, euler_cryptor::Error> {
+    if Path::new(path_or_key).is_file() {
+        euler_cryptor::io::read_private_key_from(Path::new(path_or_key))
+    } else {
+        open_keyring(keyring_directory)?.private_key(path_or_key)
+    }
+}
+
+// Key given by its path, or by its name or fingerprint in the keyring, or the default key of the keyring
+fn select_private_key(keyring_directory: &Option<String>, key_path: Option<String>, key: Option<String>) -> Result<RsaPrivateKey, euler_cryptor::Error> {
+    match key_path {
+        Some(key_path) => euler_cryptor::io::read_private_key_from(Path::new(&key_path)),
+        None => open_keyring(keyring_directory)?.private_key(key.as_deref().unwrap_or(DEFAULT_KEY_NAME))
+    }
+}
+
+// mykeys_pub.pem and mykeys_sec.pem are both imported as mykeys
+fn key_name_of(key_path: &Path) -> Option<String> {
+    let file_stem = key_path.file_stem()?.to_str()?;
+    let name = file_stem.strip_suffix("_pub").or_else(|| file_stem.strip_suffix("_sec")).unwrap_or(file_stem);
+    Some(name.to_string())
+}
+
+fn print_factor_progress(event: FactorEvent) {
+    match event {
+        FactorEvent::MethodStarted(method) => eprintln!("Trying {}", method),
+        FactorEvent::MethodFailed(method) => eprintln!("No factor found with {}", method),
+        FactorEvent::CurvesTried { tried, curves } => eprintln!("Tried {} of {} curves", tried, curves),
+        FactorEvent::RelationsFound { found, needed } => eprintln!("Found {} of {} relations", found, needed)
+    }
+}
+
+fn print_search_progress(event: PrimeSearchEvent) {
+    match event {
+        PrimeSearchEvent::CandidateTested => eprint!("."),
+        PrimeSearchEvent::AuxiliaryPrimeFound => eprint!("+")
+    }
 }
\ No newline at end of file
@@ -1,24 +1,114 @@
 use num_bigint::BigInt;
 use num_traits::{One, Zero};
+use std::cmp;
 use crate::euclidean;
 
 pub(crate) fn exponent(number: &BigInt, power: &BigInt, modulo: &BigInt) -> BigInt {
-    fast_exponent(number, power, modulo)
+    ModulusContext::for_modulo(modulo).exponent(number, power)
 }
 
-fn fast_exponent(number: &BigInt, power: &BigInt, modulo: &BigInt) -> BigInt {
-    let optimization = MontgomeryOptimization::for_modulo(&modulo);
-    let mut result: BigInt = optimization.to_montgomery_form(&BigInt::one());
-    let mut number_to_exponentiate: BigInt = optimization.to_montgomery_form(number);
-    for i in 0..power.bits() {
-        if power.bit(i) {
-            result = optimization.montgomery_form_multiply(&result, &number_to_exponentiate);
+/*
+ * Precomputed data for the exponentiations modulo the same number.
+ * Computing it requires the extended Euclidean algorithm, so it should be built once per key or prime candidate
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct ModulusContext {
+    optimization: MontgomeryOptimization
+}
+
+impl ModulusContext {
+
+    pub(crate) fn for_modulo(modulo: &BigInt) -> ModulusContext {
+        ModulusContext {
+            optimization: MontgomeryOptimization::for_modulo(modulo)
+        }
+    }
+
+    pub(crate) fn exponent(&self, number: &BigInt, power: &BigInt) -> BigInt {
+        fast_exponent(number, power, &self.optimization)
+    }
+
+    /*
+     * Numbers in the Montgomery form a * R (mod n) are added and subtracted as usual, but multiplied without a division.
+     * Worth it for long chains of multiplications, such as the point arithmetic of elliptic curves. Multiplying by 1
+     * converts a number back
+     */
+    pub(crate) fn to_montgomery_form(&self, number: &BigInt) -> BigInt {
+        self.optimization.to_montgomery_form(number)
+    }
+
+    // Both numbers are in the Montgomery form and smaller than the modulo
+    pub(crate) fn montgomery_form_multiply(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        self.optimization.montgomery_form_multiply(a, b)
+    }
+}
+
+/*
+ * Left-to-right sliding window exponentiation: the exponent is split into windows of up to window_size bits which start
+ * and end with a set bit, every window costs a single multiplication by a precomputed odd power of the number
+ */
+fn fast_exponent(number: &BigInt, power: &BigInt, optimization: &MontgomeryOptimization) -> BigInt {
+    let window_size = window_size_for(power.bits());
+    let number_in_montgomery_form = optimization.to_montgomery_form(number);
+    let odd_powers = odd_powers_of(&number_in_montgomery_form, window_size, optimization);
+
+    let mut result: Option<BigInt> = None;
+    let mut bit_index = power.bits() as i64 - 1;
+    while bit_index >= 0 {
+        if !power.bit(bit_index as u64) {
+            result = result.map(|result| optimization.montgomery_form_multiply(&result, &result));
+            bit_index -= 1;
+            continue;
         }
-        number_to_exponentiate = optimization.montgomery_form_multiply(&number_to_exponentiate, &number_to_exponentiate);
+        // The window ends with the lowest set bit within window_size bits
+        let mut window_end = cmp::max(bit_index - window_size as i64 + 1, 0);
+        while !power.bit(window_end as u64) {
+            window_end += 1;
+        }
+        let mut window_value = 0usize;
+        for i in (window_end..=bit_index).rev() {
+            window_value = (window_value << 1) | power.bit(i as u64) as usize;
+        }
+        let odd_power = &odd_powers[window_value >> 1];
+        result = Some(match result {
+            Some(mut result) => {
+                for _ in window_end..=bit_index {
+                    result = optimization.montgomery_form_multiply(&result, &result);
+                }
+                optimization.montgomery_form_multiply(&result, odd_power)
+            },
+            None => odd_power.clone()
+        });
+        bit_index = window_end - 1;
     }
+    let result = result.unwrap_or_else(|| optimization.to_montgomery_form(&BigInt::one()));
     optimization.from_montgomery_form(&result)
 }
 
+// Same thresholds as in OpenSSL: larger windows need more precomputation, but fewer multiplications
+fn window_size_for(power_bits: u64) -> usize {
+    match power_bits {
+        0..=23 => 1,
+        24..=79 => 3,
+        80..=239 => 4,
+        240..=671 => 5,
+        _ => 6
+    }
+}
+
+// number ^ 1, number ^ 3, ..., number ^ (2 ^ window_size - 1)
+fn odd_powers_of(number: &BigInt, window_size: usize, optimization: &MontgomeryOptimization) -> Vec<BigInt> {
+    let mut odd_powers = vec![number.clone()];
+    if window_size > 1 {
+        let square = optimization.montgomery_form_multiply(number, number);
+        for i in 1..(1 << (window_size - 1)) {
+            let next_power = optimization.montgomery_form_multiply(&odd_powers[i - 1], &square);
+            odd_powers.push(next_power);
+        }
+    }
+    odd_powers
+}
+
 #[derive(Debug, PartialEq, Clone)]
 struct MontgomeryOptimization {
     n: BigInt,
@@ -30,8 +120,8 @@ struct MontgomeryOptimization {
 impl MontgomeryOptimization {
 
     fn for_modulo(n: &BigInt) -> MontgomeryOptimization {
-        let r = BigInt::one() << &n.bits();
-        let result = euclidean::find_gcd_and_bezout_coefficients(&r, &n);
+        let r = BigInt::one() << n.bits();
+        let result = euclidean::find_gcd_and_bezout_coefficients(&r, n);
         let r_stroke = if result.x < BigInt::zero() {
             result.x + n
         } else {
@@ -49,8 +139,8 @@ impl MontgomeryOptimization {
     // Implementation of the REDC algorithm, see https://en.wikipedia.org/wiki/Montgomery_modular_multiplication
     fn redc(&self, a: &BigInt) -> BigInt {
         let m = ((a & (&self.r - 1)) * &self.n_stroke) & (&self.r - 1);
-        let t: BigInt = (a + &m * &self.n) >> &self.n.bits();
-        if &t >= &self.n {
+        let t: BigInt = (a + &m * &self.n) >> self.n.bits();
+        if t >= self.n {
             t - &self.n
         } else {
             t
@@ -58,9 +148,10 @@ impl MontgomeryOptimization {
     }
 
     fn to_montgomery_form(&self, a: &BigInt) -> BigInt {
-        (a << &self.n.bits()) % &self.n
+        (a << self.n.bits()) % &self.n
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn from_montgomery_form(&self, a: &BigInt) -> BigInt {
        self.redc(a)
     }
@@ -69,6 +160,7 @@ impl MontgomeryOptimization {
         self.redc(&(a * b))
     }
 
+    #[cfg(test)]
     fn multiply(&self, a: &BigInt, b: &BigInt) -> BigInt {
         let x = self.to_montgomery_form(a);
         let y = self.to_montgomery_form(b);
@@ -79,7 +171,9 @@ impl MontgomeryOptimization {
 
 #[cfg(test)]
 mod tests {
+    use num_bigint::{RandBigInt, ToBigInt};
     use num_traits::FromPrimitive;
+    use rand::{thread_rng, Rng};
 
     use super::*;
 
@@ -102,7 +196,7 @@ mod tests {
         let power = BigInt::from_u64(8752368742787).unwrap();
         let modulo = BigInt::from_u64(404790586766519).unwrap();
 
-        assert_eq!(fast_exponent(&number, &power, &modulo), slow_exponent(&number, &power, &modulo))
+        assert_eq!(fast_exponent(&number, &power, &MontgomeryOptimization::for_modulo(&modulo)), slow_exponent(&number, &power, &modulo))
     }
 
     #[test]
@@ -137,6 +231,55 @@ mod tests {
         assert_eq!(optimization.multiply(&a, &b), (a * b) % n)
     }
 
+    #[test]
+    fn should_reuse_context_for_exponentiations_with_same_modulo() {
+        let modulo = BigInt::from_u64(404790586766519).unwrap();
+        let context = ModulusContext::for_modulo(&modulo);
+        for number in [2u64, 65, 32817698412213213] {
+            let number = BigInt::from_u64(number).unwrap();
+            let power = BigInt::from_u64(8752368742787).unwrap();
+            assert_eq!(context.exponent(&number, &power), slow_exponent(&number, &power, &modulo));
+        }
+    }
+
+    #[test]
+    fn sliding_window_exponent_should_match_slow_exponent_for_random_inputs() {
+        let mut rng = thread_rng();
+        for modulo_bits in [8, 17, 64, 255, 512, 1024] {
+            for _ in 0..5 {
+                let modulo = rng.gen_biguint(modulo_bits).to_bigint().unwrap() | (BigInt::one() << (modulo_bits - 1)) | BigInt::one();
+                let number = rng.gen_biguint(modulo_bits + 16).to_bigint().unwrap();
+                let power_bits = rng.gen_range(0..=modulo_bits);
+                let power = rng.gen_biguint(power_bits).to_bigint().unwrap();
+                let optimization = MontgomeryOptimization::for_modulo(&modulo);
+                assert_eq!(fast_exponent(&number, &power, &optimization), slow_exponent(&number, &power, &modulo),
+                    "{} ^ {} mod {}", number, power, modulo);
+            }
+        }
+    }
+
+    #[test]
+    fn sliding_window_exponent_should_handle_edge_case_powers() {
+        let modulo = BigInt::from_u64(404790586766519).unwrap();
+        let number = BigInt::from_u64(32817698412213213).unwrap();
+        let optimization = MontgomeryOptimization::for_modulo(&modulo);
+        let all_bits_set = (BigInt::one() << 300) - 1;
+        let highest_bit_set = BigInt::one() << 300;
+        for power in [BigInt::zero(), BigInt::one(), BigInt::from(2), all_bits_set, highest_bit_set] {
+            assert_eq!(fast_exponent(&number, &power, &optimization), slow_exponent(&number, &power, &modulo));
+        }
+    }
+
+    #[test]
+    fn should_multiply_in_montgomery_form_with_context() {
+        let modulo = BigInt::from_u64(404790586766519).unwrap();
+        let context = ModulusContext::for_modulo(&modulo);
+        let a = BigInt::from_u64(32817698412213).unwrap();
+        let b = BigInt::from_u64(8752368742787).unwrap();
+        let product = context.montgomery_form_multiply(&context.to_montgomery_form(&a), &context.to_montgomery_form(&b));
+        assert_eq!(context.montgomery_form_multiply(&product, &BigInt::one()), (a * b) % modulo);
+    }
+
     #[test]
     fn should_exponentiate_correctly() {
         let result = exponent(&BigInt::from_u16(2).unwrap(), &BigInt::from_u16(4).unwrap(), &BigInt::from_u16(17).unwrap());
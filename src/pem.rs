@@ -1,39 +1,62 @@
-use crate::crypto;
-use anyhow::{anyhow, Result};
+use crate::Error;
 use base64::prelude::*;
 
-const LINE_SIZE: usize = 64;
+pub(crate) const LINE_SIZE: usize = 64;
+// Number of bytes encoded into a single line
+pub(crate) const LINE_DATA_SIZE: usize = LINE_SIZE / 4 * 3;
 
-pub(crate) fn serialize(key_data: &[u8], key_type: &crypto::KeyType) -> Vec<u8> {
-    let encoded = BASE64_STANDARD.encode(key_data);
-    let encoded_lines = encoded.chars().collect::<Vec<_>>().chunks(LINE_SIZE).map(|chunk| {
-        chunk.iter().collect::<String>()
-    }).collect::<Vec<_>>().join("\n");
+pub(crate) fn serialize_block(data: &[u8], label: &str) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::new();
-    let key_type = if key_type == &crypto::KeyType::Private {
-        "PRIVATE"
-    } else {
-        "PUBLIC"
-    };
-    result.extend(format!("-----BEGIN {} KEY-----\n", key_type).as_bytes());
-    result.extend(encoded_lines.as_bytes());
-    result.extend(format!("\n-----END {} KEY-----\n", key_type).as_bytes());
+    result.extend(begin_line(label).as_bytes());
+    result.extend(encode_lines(data).as_bytes());
+    result.extend(end_line(label).as_bytes());
     result
 }
 
-pub(crate) fn deserialize(input: &[u8]) -> Result<(Vec<u8>, crypto::KeyType), anyhow::Error> {
-    let input_str = String::from_utf8(input.to_vec())?;
+pub(crate) fn begin_line(label: &str) -> String {
+    format!("-----BEGIN {}-----\n", label)
+}
+
+pub(crate) fn end_line(label: &str) -> String {
+    format!("-----END {}-----\n", label)
+}
+
+// Base64 wrapped at LINE_SIZE characters, every line terminated with a new line
+pub(crate) fn encode_lines(data: &[u8]) -> String {
+    let encoded = BASE64_STANDARD.encode(data);
+    let mut encoded_lines = String::with_capacity(encoded.len() + encoded.len() / LINE_SIZE + 1);
+    for line in encoded.as_bytes().chunks(LINE_SIZE) {
+        encoded_lines.push_str(std::str::from_utf8(line).expect("Base64 is ASCII"));
+        encoded_lines.push('\n');
+    }
+    encoded_lines
+}
+
+pub(crate) fn deserialize_block(input: &[u8]) -> Result<(Vec<u8>, String), Error> {
+    let input_str = String::from_utf8(input.to_vec()).map_err(|err| Error::Pem(err.to_string()))?;
     let lines: Vec<&str> = input_str.split_terminator('\n').collect();
-    let mut encoded_key: Vec<u8> = Vec::new();
+    let header = lines.first().ok_or(Error::Pem("Could not find header".to_string()))?;
+    if lines.len() < 2 {
+        return Err(Error::Pem("Could not find footer".to_string()));
+    }
+    let mut encoded_data: Vec<u8> = Vec::new();
     for s in lines[1..lines.len() - 1].iter() {
-        encoded_key.extend(s.as_bytes());
+        encoded_data.extend(s.as_bytes());
+    }
+    let label = header.trim_start_matches("-----BEGIN ").trim_end_matches("-----").to_string();
+    let decoded = BASE64_STANDARD.decode(encoded_data).map_err(|err| Error::Pem(err.to_string()))?;
+    Ok((decoded, label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_and_deserialize_block_with_label() {
+        let data: Vec<u8> = (0..=255).collect();
+        let serialized = serialize_block(&data, "PRIME CERTIFICATE");
+        assert!(serialized.starts_with(b"-----BEGIN PRIME CERTIFICATE-----\n"));
+        assert_eq!(deserialize_block(&serialized).unwrap(), (data, "PRIME CERTIFICATE".to_string()));
     }
-    let header = lines.get(0).ok_or(anyhow!("Could not find header"))?;
-    let key_type = if header.contains("PRIVATE") {
-        crypto::KeyType::Private
-    } else {
-        crypto::KeyType::Public
-    };
-    let decoded = BASE64_STANDARD.decode(encoded_key)?;
-    Ok((decoded, key_type))
-}
\ No newline at end of file
+}
@@ -1,13 +1,12 @@
-use crate::crypto::Key;
-use anyhow::Result;
 use yasna::{self, models::ObjectIdentifier};
+use zeroize::Zeroizing;
 
 // PKCS#8 https://datatracker.ietf.org/doc/html/rfc5208#appendix-A
 #[derive(Default)]
 pub(crate) struct PrivateKeyInfo {
     pub(crate) version: u32,
     pub(crate) private_key_algorithm: AlgorithmIdentifier,
-    pub(crate) private_key: Vec<u8>
+    pub(crate) private_key: Zeroizing<Vec<u8>>
 }
 
 #[derive(Default)]
@@ -15,17 +14,17 @@ pub(crate) struct AlgorithmIdentifier {
     pub(crate) algorithm: Vec<u64>
 }
 
-const RSA_IDENTIFIER: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
+pub(crate) const RSA_IDENTIFIER: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
 
 impl PrivateKeyInfo {
 
-    pub(crate) fn wrap(key: &Key) -> PrivateKeyInfo {
+    pub(crate) fn wrap(private_key: Zeroizing<Vec<u8>>) -> PrivateKeyInfo {
         PrivateKeyInfo {
             version: 0,
             private_key_algorithm: AlgorithmIdentifier {
                 algorithm: RSA_IDENTIFIER.to_vec()
             },
-            private_key: key.as_bytes()
+            private_key
         }
     }
 
@@ -37,7 +36,7 @@ impl PrivateKeyInfo {
                     let algorithm = reader.next().read_oid()?.components().to_vec();
                     Ok(AlgorithmIdentifier { algorithm })
                 })?;
-                let private_key = reader.next().read_bytes()?;
+                let private_key = Zeroizing::new(reader.next().read_bytes()?);
                 Ok(PrivateKeyInfo {
                     version,
                     private_key_algorithm,
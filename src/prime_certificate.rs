@@ -0,0 +1,282 @@
+use num_bigint::{BigInt, RandBigInt, Sign};
+use num_traits::{One, Zero};
+use rand::thread_rng;
+use yasna;
+
+use crate::Error;
+use crate::euclidean;
+use crate::constant_time::ConstantTimeModulus;
+use crate::modulo_arithmetic;
+use crate::pem;
+use crate::primes;
+
+const PEM_LABEL: &str = "PRIME CERTIFICATE";
+
+// Primes up to this size are proven by trial division instead of a Pocklington step
+const BASE_PRIME_BITS: usize = 32;
+
+// Smaller sizes have too few primes to pick from, and there is no prime with a single bit at all
+pub const MINIMUM_PRIME_BITS: usize = 16;
+
+/*
+ * Pocklington-Lehmer certificate for a prime produced by the Shawe-Taylor recursive construction.
+ *
+ * The certificate starts with a small base prime proven by trial division, every following step proves
+ * n = 2 * r * q + 1 prime where q is the prime proven by the previous step and q > sqrt(n):
+ *
+ * a ^ (n - 1) = 1 (mod n) and gcd(a ^ ((n - 1) / q) - 1, n) = 1 => every prime factor p of n is p = 1 (mod q),
+ * so p > q > sqrt(n), which is only possible when n itself is prime
+ */
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrimeCertificate {
+    pub(crate) base_prime: BigInt,
+    pub(crate) steps: Vec<PocklingtonStep>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct PocklingtonStep {
+    pub(crate) n: BigInt,
+    pub(crate) witness: BigInt
+}
+
+impl PrimeCertificate {
+
+    pub fn prime(&self) -> &BigInt {
+        self.steps.last().map(|step| &step.n).unwrap_or(&self.base_prime)
+    }
+
+    // Independently checks every step of the certificate, returns the proven prime
+    pub fn verify(&self) -> Result<BigInt, Error> {
+        verify_base_prime(&self.base_prime)?;
+        let mut proven_prime = self.base_prime.clone();
+        for (index, step) in self.steps.iter().enumerate() {
+            verify_step(step, &proven_prime).map_err(|err| Error::InvalidCertificate(format!("Step {} is invalid: {}", index + 1, err)))?;
+            proven_prime = step.n.clone();
+        }
+        Ok(proven_prime)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        pem::serialize_block(&self.as_bytes(), PEM_LABEL)
+    }
+
+    pub fn deserialize(input: &[u8]) -> Result<PrimeCertificate, Error> {
+        let (data, label) = pem::deserialize_block(input)?;
+        if label != PEM_LABEL {
+            return Err(Error::InvalidCertificate(format!("Expected {}, found {}", PEM_LABEL, label)));
+        }
+        Ok(PrimeCertificate::from_bytes(&data)?)
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_bigint_bytes(&self.base_prime.to_bytes_be().1, true);
+                writer.next().write_sequence_of(|writer| {
+                    for step in self.steps.iter() {
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_bigint_bytes(&step.n.to_bytes_be().1, true);
+                            writer.next().write_bigint_bytes(&step.witness.to_bytes_be().1, true);
+                        })
+                    }
+                })
+            })
+        })
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<PrimeCertificate, yasna::ASN1Error> {
+        yasna::parse_der(bytes, |reader| {
+            reader.read_sequence(|reader| {
+                let base_prime = read_positive_bigint(reader.next())?;
+                let steps = reader.next().collect_sequence_of(|reader| {
+                    reader.read_sequence(|reader| {
+                        let n = read_positive_bigint(reader.next())?;
+                        let witness = read_positive_bigint(reader.next())?;
+                        Ok(PocklingtonStep { n, witness })
+                    })
+                })?;
+                Ok(PrimeCertificate { base_prime, steps })
+            })
+        })
+    }
+}
+
+fn read_positive_bigint(reader: yasna::BERReader) -> Result<BigInt, yasna::ASN1Error> {
+    let (bytes, is_positive) = reader.read_bigint_bytes()?;
+    if !is_positive {
+        return Err(yasna::ASN1Error::new(yasna::ASN1ErrorKind::Invalid));
+    }
+    Ok(BigInt::from_bytes_be(Sign::Plus, &bytes))
+}
+
+fn verify_base_prime(base_prime: &BigInt) -> Result<(), Error> {
+    if base_prime.bits() > BASE_PRIME_BITS as u64 {
+        return Err(Error::InvalidCertificate(format!("Base prime {} is too large to be proven by trial division", base_prime)));
+    }
+    if !is_prime_by_trial_division(base_prime) {
+        return Err(Error::InvalidCertificate(format!("Base prime {} is not prime", base_prime)));
+    }
+    Ok(())
+}
+
+fn is_prime_by_trial_division(n: &BigInt) -> bool {
+    if n < &BigInt::from(2) {
+        return false;
+    }
+    let largest_divisor = 1usize << BASE_PRIME_BITS.div_ceil(2);
+    primes::primes(largest_divisor).iter()
+        .take_while(|&&prime| BigInt::from(prime) * BigInt::from(prime) <= *n)
+        .all(|&prime| n % prime != BigInt::zero())
+}
+
+fn verify_step(step: &PocklingtonStep, q: &BigInt) -> Result<(), String> {
+    let n = &step.n;
+    let a = &step.witness;
+    if n % 2 == BigInt::zero() {
+        return Err(format!("{} is even", n));
+    }
+    let n_minus_one: BigInt = n - 1;
+    if &n_minus_one % q != BigInt::zero() {
+        return Err(format!("{} does not divide {} - 1", q, n));
+    }
+    if q * q <= *n {
+        return Err(format!("{} is not larger than the square root of {}", q, n));
+    }
+    if a < &BigInt::from(2) || a >= &n_minus_one {
+        return Err(format!("Witness {} is out of range", a));
+    }
+    if !is_pocklington_witness(n, q, a, |number, power| modulo_arithmetic::exponent(number, power, n)) {
+        return Err(format!("{} is not a Pocklington witness for {}", a, n));
+    }
+    Ok(())
+}
+
+// exponent computes powers modulo n, verification can use faster variable-time arithmetic than generation of a secret prime
+fn is_pocklington_witness<F>(n: &BigInt, q: &BigInt, a: &BigInt, exponent: F) -> bool
+where F: Fn(&BigInt, &BigInt) -> BigInt {
+    let n_minus_one: BigInt = n - 1;
+    // a ^ ((n - 1) / q)
+    let partial_power = exponent(a, &(&n_minus_one / q));
+    // a ^ (n - 1) = 1 (mod n)
+    if exponent(&partial_power, q) != BigInt::one() {
+        return false;
+    }
+    // gcd(a ^ ((n - 1) / q) - 1, n) = 1
+    let partial_power_minus_one = if partial_power.is_zero() {
+        n_minus_one
+    } else {
+        partial_power - 1
+    };
+    euclidean::find_gcd_and_bezout_coefficients(&partial_power_minus_one, n).gcd == BigInt::one()
+}
+
+// Shawe-Taylor construction: a prime with exactly prime_bits bits together with its Pocklington certificate
+pub fn random_provable_prime(prime_bits: usize) -> Result<PrimeCertificate, Error> {
+    if prime_bits < MINIMUM_PRIME_BITS {
+        return Err(Error::InvalidCertificate(format!("Cannot prove a prime of {} bits, at least {} bits are needed", prime_bits, MINIMUM_PRIME_BITS)));
+    }
+    Ok(construct_provable_prime(prime_bits))
+}
+
+fn construct_provable_prime(prime_bits: usize) -> PrimeCertificate {
+    let mut rng = thread_rng();
+    if prime_bits <= BASE_PRIME_BITS {
+        let lower_bound = BigInt::one() << (prime_bits - 1);
+        let upper_bound = BigInt::one() << prime_bits;
+        loop {
+            let candidate = rng.gen_bigint_range(&lower_bound, &upper_bound);
+            if is_prime_by_trial_division(&candidate) {
+                return PrimeCertificate {
+                    base_prime: candidate,
+                    steps: Vec::new()
+                };
+            }
+        }
+    }
+    // q >= 2 ^ ceil(prime_bits / 2) makes sure that q > sqrt(n)
+    let mut certificate = construct_provable_prime(prime_bits.div_ceil(2) + 1);
+    let q = certificate.prime().clone();
+    let first_primes = primes::primes(1000);
+    let two_q: BigInt = &q << 1;
+    // n = 2 * r * q + 1 has exactly prime_bits bits
+    let smallest_r = ((BigInt::one() << (prime_bits - 1)) - 1 + &two_q - 1) / &two_q;
+    let largest_r = ((BigInt::one() << prime_bits) - 2) / &two_q;
+    loop {
+        let r = rng.gen_bigint_range(&smallest_r, &(&largest_r + 1));
+        let n: BigInt = &two_q * &r + 1;
+        if first_primes.iter().any(|&prime| &n % prime == BigInt::zero()) {
+            continue;
+        }
+        let witness = rng.gen_bigint_range(&BigInt::from(2), &(&n - 1));
+        let modulus = ConstantTimeModulus::for_modulo(&n);
+        if is_pocklington_witness(&n, &q, &witness, |number, power| modulus.exponent(number, power)) {
+            certificate.steps.push(PocklingtonStep { n, witness });
+            return certificate;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_generate_verifiable_prime_of_requested_size() {
+        for prime_bits in [16, 33, 128, 512] {
+            let certificate = random_provable_prime(prime_bits).unwrap();
+            assert_eq!(certificate.prime().bits(), prime_bits as u64);
+            assert_eq!(&certificate.verify().unwrap(), certificate.prime());
+            assert!(primes::miller_rabin_primality_test(certificate.prime()));
+        }
+    }
+
+    #[test]
+    fn should_reject_too_small_prime_size() {
+        for prime_bits in [0, 1, MINIMUM_PRIME_BITS - 1] {
+            assert!(matches!(random_provable_prime(prime_bits), Err(Error::InvalidCertificate(_))));
+        }
+    }
+
+    #[test]
+    fn should_verify_known_certificate() {
+        // 103 = 2 * 3 * 17 + 1
+        let certificate = PrimeCertificate {
+            base_prime: BigInt::from(17),
+            steps: vec![PocklingtonStep { n: BigInt::from(103), witness: BigInt::from(5) }]
+        };
+        assert_eq!(certificate.verify().unwrap(), BigInt::from(103));
+    }
+
+    #[test]
+    fn should_reject_certificate_for_composite_number() {
+        // 69 = 2 * 2 * 17 + 1 = 3 * 23
+        let certificate = PrimeCertificate {
+            base_prime: BigInt::from(17),
+            steps: vec![PocklingtonStep { n: BigInt::from(69), witness: BigInt::from(2) }]
+        };
+        assert!(certificate.verify().is_err());
+    }
+
+    #[test]
+    fn should_reject_certificate_with_composite_base() {
+        let certificate = PrimeCertificate {
+            base_prime: BigInt::from(91),
+            steps: Vec::new()
+        };
+        assert!(certificate.verify().is_err());
+    }
+
+    #[test]
+    fn should_reject_tampered_certificate() {
+        let mut certificate = random_provable_prime(256).unwrap();
+        let last_step = certificate.steps.last_mut().unwrap();
+        last_step.n = &last_step.n + 2;
+        assert!(certificate.verify().is_err());
+    }
+
+    #[test]
+    fn should_serialize_and_deserialize_certificate() {
+        let certificate = random_provable_prime(256).unwrap();
+        assert_eq!(PrimeCertificate::deserialize(&certificate.serialize()).unwrap(), certificate);
+    }
+}
@@ -1,13 +1,17 @@
 use num_bigint::{BigInt, RandBigInt};
-use num_traits::{FromPrimitive, One, Zero};
+use num_traits::{One, ToPrimitive, Zero};
 use rand::thread_rng;
+use std::cmp;
 
-use crate::modulo_arithmetic;
+use crate::constant_time::ConstantTimeModulus;
 
 /*
  * Slow and inefficient "naive" implementation of the Sieve of Eratosthenes
  */
 pub(crate) fn primes(up_to: usize) -> Vec<usize> {
+    if up_to < 2 {
+        return Vec::new();
+    }
     let mut is_prime: Vec<bool> = vec![true; up_to + 1];
     is_prime[0] = false;
     is_prime[1] = false;
@@ -16,11 +20,11 @@ pub(crate) fn primes(up_to: usize) -> Vec<usize> {
         let mut proven_as_not_prime = current_number * current_number;
         while proven_as_not_prime <= up_to {
             is_prime[proven_as_not_prime] = false;
-            proven_as_not_prime = proven_as_not_prime + current_number;
+            proven_as_not_prime += current_number;
         }
-        current_number = current_number + 1;
+        current_number += 1;
         while current_number <= up_to && !is_prime[current_number] {
-            current_number = current_number + 1;
+            current_number += 1;
         }
     }
     let mut primes: Vec<usize> = Vec::new();
@@ -38,17 +42,18 @@ pub(crate) fn primes_segment(from: usize, to: usize) -> Vec<usize> {
     let segment_size = to - from + 1;
     let mut is_prime: Vec<bool> = vec![true; segment_size];
     for prime in primes_to_cross_over {
-        let start_in_segment = from % prime;
-        let mut prime_multiple_index_in_segment = if start_in_segment == 0 {
-            0
-        } else {
-            prime - start_in_segment
-        };
+        // Smaller multiples have a smaller prime factor, crossing them out would also cross out the prime itself
+        let first_multiple = cmp::max(prime * prime, from.div_ceil(prime) * prime);
+        let mut prime_multiple_index_in_segment = first_multiple - from;
         while prime_multiple_index_in_segment < segment_size {
             is_prime[prime_multiple_index_in_segment] = false;
-            prime_multiple_index_in_segment = prime_multiple_index_in_segment + prime;
+            prime_multiple_index_in_segment += prime;
         }
     }
+    // 0 and 1 are not primes
+    for number in from..cmp::min(2, to + 1) {
+        is_prime[number - from] = false;
+    }
     let mut primes: Vec<usize> = Vec::new();
     for (index_in_segment, &is_current_prime) in is_prime.iter().enumerate() {
         if is_current_prime {
@@ -75,24 +80,26 @@ pub(crate) fn miller_rabin_primality_test(n: &BigInt) -> bool {
     let mut d: BigInt = n - 1;
     let two = BigInt::one() << 1;
     while &d % 2 == BigInt::zero() {
-        s = s + 1;
-        d = d / &two;
+        s += 1;
+        d /= &two;
     }
+    // n is usually a candidate for a secret prime
+    let modulus = ConstantTimeModulus::for_modulo(n);
     let mut passed_check: bool = true;
     let mut bases_to_try = NUM_OF_BASES_TO_TRY;
 
     while passed_check && bases_to_try > 0 {
-        bases_to_try = bases_to_try - 1;
+        bases_to_try -= 1;
         let base = rng.gen_bigint_range(&BigInt::from(2), &(n - &BigInt::from(1)));
-        let mut base_exponent = modulo_arithmetic::exponent(&base, &d, &n);
+        let mut base_exponent = modulus.exponent(&base, &d);
 
         // a ^ d != 1 (mod p)
         if base_exponent != BigInt::one() {
             let mut r = 0;
             // a ^ (2 ^ r) ^ d != -1 (mod p)
             while base_exponent != n - 1 && r < s {
-                base_exponent = (&base_exponent * &base_exponent) % n;
-                r = r + 1;
+                base_exponent = modulus.multiply(&base_exponent, &base_exponent);
+                r += 1;
             }
             if r == s {
                 passed_check = false;
@@ -102,13 +109,203 @@ pub(crate) fn miller_rabin_primality_test(n: &BigInt) -> bool {
     passed_check
 }
 
+// Also handles the numbers below 4, which are too small to pick bases for Miller-Rabin
+pub(crate) fn is_probable_prime(n: &BigInt) -> bool {
+    if n < &BigInt::from(4) {
+        n >= &BigInt::from(2)
+    } else {
+        miller_rabin_primality_test(n)
+    }
+}
+
+// Small primes used to sieve out prime candidates before running the expensive primality tests
+const SIEVE_LIMIT: usize = 2000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PrimeSearchEvent {
+    // A candidate survived the sieve and is being tested for primality
+    CandidateTested,
+    // An auxiliary prime needed for the construction has been found
+    AuxiliaryPrimeFound
+}
+
+/*
+ * Safe prime p = 2q + 1 where q is also prime
+ */
+pub fn random_safe_prime(prime_bits: usize) -> BigInt {
+    random_safe_prime_with_progress(prime_bits, &mut |_| {})
+}
+
+pub fn random_safe_prime_with_progress(prime_bits: usize, progress: &mut dyn FnMut(PrimeSearchEvent)) -> BigInt {
+    assert!(prime_bits >= 16, "Safe primes should have at least 16 bits, requested {}", prime_bits);
+    let q_bits = prime_bits - 1;
+    let sieve_primes = sieve_primes(q_bits);
+    loop {
+        let start = random_odd_number(q_bits);
+        // Combined sieve: neither q nor p = 2q + 1 can be divisible by a small prime
+        let candidates = SievedProgression::new(&start, &BigInt::from(2), &sieve_primes, |residue, prime| {
+            residue == 0 || residue == (prime - 1) / 2
+        });
+        for q in candidates.take_while(|q| q.bits() == q_bits as u64) {
+            progress(PrimeSearchEvent::CandidateTested);
+            let p: BigInt = (&q << 1) + 1;
+            if passes_fermat_test(&p) && miller_rabin_primality_test(&q) && miller_rabin_primality_test(&p) {
+                return p;
+            }
+        }
+    }
+}
+
+/*
+ * Strong prime p generated with Gordon's algorithm:
+ * p - 1 has a large prime factor r, p + 1 has a large prime factor s and r - 1 has a large prime factor t
+ */
+pub fn random_strong_prime(prime_bits: usize) -> BigInt {
+    random_strong_prime_with_progress(prime_bits, &mut |_| {})
+}
+
+pub fn random_strong_prime_with_progress(prime_bits: usize, progress: &mut dyn FnMut(PrimeSearchEvent)) -> BigInt {
+    assert!(prime_bits >= 32, "Strong primes should have at least 32 bits, requested {}", prime_bits);
+    let mut rng = thread_rng();
+    let slack_bits = prime_bits / 16;
+    let s_bits = prime_bits / 2 - slack_bits;
+    let t_bits = s_bits - slack_bits;
+    let sieve_primes = sieve_primes(t_bits);
+    loop {
+        let s = random_sieved_prime(s_bits, progress);
+        progress(PrimeSearchEvent::AuxiliaryPrimeFound);
+        let t = random_sieved_prime(t_bits, progress);
+        progress(PrimeSearchEvent::AuxiliaryPrimeFound);
+
+        // r = 2 * i * t + 1
+        let two_t: BigInt = &t << 1;
+        let smallest_i = BigInt::one() << (s_bits - t_bits - 2);
+        let r_start: BigInt = &two_t * rng.gen_bigint_range(&smallest_i, &(&smallest_i << 1)) + 1;
+        let r = SievedProgression::new(&r_start, &two_t, &sieve_primes, is_divisible)
+            .find(|r| {
+                progress(PrimeSearchEvent::CandidateTested);
+                passes_fermat_test(r) && miller_rabin_primality_test(r)
+            })
+            .expect("Infinite progression");
+        progress(PrimeSearchEvent::AuxiliaryPrimeFound);
+
+        // p0 = 1 (mod r) and p0 = -1 (mod s)
+        let two_r_s: BigInt = (&r * &s) << 1;
+        let p0: BigInt = ((ConstantTimeModulus::for_modulo(&r).exponent(&s, &(&r - 2)) * &s) << 1) - 1;
+
+        // p = p0 + 2 * j * r * s has exactly prime_bits bits
+        let smallest_j = ((BigInt::one() << (prime_bits - 1)) - &p0 + &two_r_s - 1) / &two_r_s;
+        let largest_j = ((BigInt::one() << prime_bits) - 1 - &p0) / &two_r_s;
+        if smallest_j > largest_j {
+            continue;
+        }
+        let p_start = &p0 + &two_r_s * rng.gen_bigint_range(&smallest_j, &(&largest_j + 1));
+        let candidates = SievedProgression::new(&p_start, &two_r_s, &sieve_primes, is_divisible);
+        for p in candidates.take_while(|p| p.bits() == prime_bits as u64) {
+            progress(PrimeSearchEvent::CandidateTested);
+            if passes_fermat_test(&p) && miller_rabin_primality_test(&p) {
+                return p;
+            }
+        }
+    }
+}
+
+fn random_sieved_prime(prime_bits: usize, progress: &mut dyn FnMut(PrimeSearchEvent)) -> BigInt {
+    let sieve_primes = sieve_primes(prime_bits);
+    loop {
+        let start = random_odd_number(prime_bits);
+        let candidates = SievedProgression::new(&start, &BigInt::from(2), &sieve_primes, is_divisible);
+        for candidate in candidates.take_while(|candidate| candidate.bits() == prime_bits as u64) {
+            progress(PrimeSearchEvent::CandidateTested);
+            if passes_fermat_test(&candidate) && miller_rabin_primality_test(&candidate) {
+                return candidate;
+            }
+        }
+    }
+}
+
+fn random_odd_number(bits: usize) -> BigInt {
+    let lower_bound = BigInt::one() << (bits - 1);
+    thread_rng().gen_bigint_range(&lower_bound, &(&lower_bound << 1)) | BigInt::one()
+}
+
+// Odd primes which are smaller than any number with the given number of bits
+fn sieve_primes(candidate_bits: usize) -> Vec<usize> {
+    primes(SIEVE_LIMIT).into_iter()
+        .skip(1)
+        .take_while(|&prime| (usize::BITS - prime.leading_zeros()) < candidate_bits as u32)
+        .collect()
+}
+
+fn is_divisible(residue: usize, _prime: usize) -> bool {
+    residue == 0
+}
+
+// Cheap test with base 2 to quickly discard most of the composite candidates before running Miller-Rabin
+fn passes_fermat_test(n: &BigInt) -> bool {
+    ConstantTimeModulus::for_modulo(n).exponent(&BigInt::from(2), &(n - 1)) == BigInt::one()
+}
+
+/*
+ * Arithmetic progression start, start + step, start + 2 * step, ... skipping the members excluded by the sieve.
+ * The residues modulo the sieve primes are updated incrementally, so the sieve never divides big numbers
+ */
+struct SievedProgression<'a, F: Fn(usize, usize) -> bool> {
+    current: BigInt,
+    step: BigInt,
+    sieve_primes: &'a [usize],
+    residues: Vec<usize>,
+    step_residues: Vec<usize>,
+    is_excluded: F
+}
+
+impl<'a, F: Fn(usize, usize) -> bool> SievedProgression<'a, F> {
+
+    fn new(start: &BigInt, step: &BigInt, sieve_primes: &'a [usize], is_excluded: F) -> SievedProgression<'a, F> {
+        let residue_of = |number: &BigInt, prime: usize| {
+            (number % prime).to_usize().expect("Residue is smaller than the prime")
+        };
+        SievedProgression {
+            current: start.clone(),
+            step: step.clone(),
+            sieve_primes,
+            residues: sieve_primes.iter().map(|&prime| residue_of(start, prime)).collect(),
+            step_residues: sieve_primes.iter().map(|&prime| residue_of(step, prime)).collect(),
+            is_excluded
+        }
+    }
+}
+
+impl<F: Fn(usize, usize) -> bool> Iterator for SievedProgression<'_, F> {
+    type Item = BigInt;
+
+    fn next(&mut self) -> Option<BigInt> {
+        loop {
+            let is_excluded = self.sieve_primes.iter().zip(self.residues.iter())
+                .any(|(&prime, &residue)| (self.is_excluded)(residue, prime));
+            let candidate = self.current.clone();
+            self.current += &self.step;
+            for ((residue, step_residue), prime) in self.residues.iter_mut().zip(self.step_residues.iter()).zip(self.sieve_primes.iter()) {
+                *residue = (*residue + step_residue) % prime;
+            }
+            if !is_excluded {
+                return Some(candidate);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num_traits::FromPrimitive;
 
     #[test]
     fn should_find_first_prime_numbers() {
-        assert_eq!(primes(100), vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97])
+        assert_eq!(primes(100), vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97]);
+        assert!(primes(0).is_empty());
+        assert!(primes(1).is_empty());
+        assert_eq!(primes(2), vec![2]);
     }
 
     #[test]
@@ -116,11 +313,49 @@ mod tests {
         assert_eq!(primes_segment(10, 20), vec![11, 13, 17, 19]);
         assert_eq!(primes_segment(40, 50), vec![41, 43, 47]);
         assert_eq!(primes_segment(80, 100), vec![83, 89, 97]);
+        // Segments below the square of their end contain the primes used for sieving
+        assert_eq!(primes_segment(0, 100), primes(100));
+        assert_eq!(primes_segment(5, 30), vec![5, 7, 11, 13, 17, 19, 23, 29]);
+        assert!(primes_segment(0, 1).is_empty());
         assert_eq!(primes_segment(10000000000000, 10000000000100), vec![10000000000037, 10000000000051, 10000000000099]);
         //Would already be too slow, better to use a more optimized approach, such as the Miller-Rabin primality test
         //assert_eq!(primes_segment(1000000000000000000, 1000000000000001000), vec![]);
     }
 
+    #[test]
+    fn should_generate_safe_prime() {
+        let mut tested_candidates = 0;
+        let p = random_safe_prime_with_progress(128, &mut |_| tested_candidates += 1);
+        assert!(tested_candidates > 0);
+        assert_eq!(p.bits(), 128);
+        assert!(miller_rabin_primality_test(&p));
+        assert!(miller_rabin_primality_test(&((p - 1) / 2)));
+    }
+
+    #[test]
+    fn should_generate_strong_prime() {
+        let p = random_strong_prime(256);
+        assert_eq!(p.bits(), 256);
+        assert!(miller_rabin_primality_test(&p));
+    }
+
+    #[test]
+    fn sieved_progression_should_skip_multiples_of_sieve_primes() {
+        let sieve_primes = vec![3, 5, 7];
+        let members: Vec<BigInt> = SievedProgression::new(&BigInt::from(11), &BigInt::from(2), &sieve_primes, is_divisible).take(5).collect();
+        assert_eq!(members, [11, 13, 17, 19, 23].map(BigInt::from).to_vec());
+    }
+
+    #[test]
+    fn sieved_progression_should_skip_candidates_for_safe_primes() {
+        let sieve_primes = vec![3, 5];
+        let members: Vec<BigInt> = SievedProgression::new(&BigInt::from(11), &BigInt::from(2), &sieve_primes, |residue, prime| {
+            residue == 0 || residue == (prime - 1) / 2
+        }).take(3).collect();
+        // 2 * 13 + 1 = 27, 2 * 17 + 1 = 35 and 2 * 19 + 1 = 39 are not prime
+        assert_eq!(members, [11, 23, 29].map(BigInt::from).to_vec());
+    }
+
     #[test]
     fn miller_rabin_primality_test_should_pass_for_known_prime() {
         let prime = 83;
@@ -148,7 +383,7 @@ mod tests {
         let from = 10000;
         let to = 11000;
         let primes = primes_segment(from, to);
-        for n in (from..to).into_iter() {
+        for n in from..to {
             if !primes.contains(&n) {
                 assert!(!miller_rabin_primality_test(&BigInt::from_usize(n).unwrap()))
             }
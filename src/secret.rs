@@ -0,0 +1,207 @@
+use num_bigint::{BigInt, BigUint, Sign};
+use std::{cmp, fmt};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::constant_time;
+
+/*
+ * Number whose limbs are overwritten with zeros when dropped, used for the private exponent, the factors of the modulo
+ * and the intermediate values of the private key operations.
+ *
+ * num-bigint does not give access to its buffer, so the limbs are kept in a buffer of their own which zeroize wipes
+ * with volatile writes. The BigInt passed to new and the copies returned by expose can not be wiped, they should only
+ * live as long as the computation which needs them.
+ *
+ * Does not implement Clone, so that every copy of a secret is explicit, and Debug never prints the value.
+ * The arithmetic works on the limbs directly in constant time and is only defined for non-negative numbers
+ */
+pub struct SecretBigInt {
+    sign: Sign,
+    // Little-endian, without leading zero limbs
+    limbs: Zeroizing<Vec<u64>>
+}
+
+impl SecretBigInt {
+
+    pub fn new(number: BigInt) -> SecretBigInt {
+        let (sign, limbs) = number.to_u64_digits();
+        SecretBigInt { sign, limbs: Zeroizing::new(limbs) }
+    }
+
+    pub fn expose(&self) -> BigInt {
+        let digits: Zeroizing<Vec<u32>> = Zeroizing::new(self.limbs.iter().flat_map(|&limb| [limb as u32, (limb >> 32) as u32]).collect());
+        BigInt::from_biguint(self.sign, BigUint::from_slice(&digits))
+    }
+
+    // Takes the little-endian limbs of a non-negative number
+    pub(crate) fn from_limbs(limbs: Vec<u64>) -> SecretBigInt {
+        let mut limbs = Zeroizing::new(limbs);
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let sign = if limbs.is_empty() { Sign::NoSign } else { Sign::Plus };
+        SecretBigInt { sign, limbs }
+    }
+
+    pub(crate) fn from_bytes_be(bytes: &[u8]) -> SecretBigInt {
+        let mut limbs = Vec::with_capacity(bytes.len().div_ceil(8));
+        for chunk in bytes.rchunks(8) {
+            let mut limb_bytes = Zeroizing::new([0u8; 8]);
+            limb_bytes[8 - chunk.len()..].copy_from_slice(chunk);
+            limbs.push(u64::from_be_bytes(*limb_bytes));
+        }
+        SecretBigInt::from_limbs(limbs)
+    }
+
+    pub(crate) fn limbs(&self) -> &[u64] {
+        &self.limbs
+    }
+
+    // Big-endian bytes of the magnitude, with the leading zero bytes of the highest limb
+    pub(crate) fn to_bytes_be(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(self.limbs.len() * 8));
+        for limb in self.limbs.iter().rev() {
+            bytes.extend_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub(crate) fn duplicate(&self) -> SecretBigInt {
+        SecretBigInt { sign: self.sign, limbs: Zeroizing::new(self.limbs.to_vec()) }
+    }
+
+    pub(crate) fn is_odd(&self) -> bool {
+        self.limbs.first().is_some_and(|&limb| limb & 1 == 1)
+    }
+
+    pub(crate) fn add(&self, other: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(constant_time::add(&self.limbs, &other.limbs))
+    }
+
+    // self - other for other <= self
+    pub(crate) fn subtract(&self, other: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(constant_time::subtract_smaller(&self.limbs, &other.limbs))
+    }
+
+    pub(crate) fn multiply(&self, other: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(constant_time::multiply(&self.limbs, &other.limbs))
+    }
+
+    // self mod modulo for modulo > 0
+    pub(crate) fn remainder(&self, modulo: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(constant_time::remainder(&self.limbs, &modulo.limbs))
+    }
+
+    // Not constant time, for the number of times 2 divides a number which is not a secret itself
+    pub(crate) fn trailing_zeros(&self) -> Option<u64> {
+        let (index, limb) = self.limbs.iter().enumerate().find(|(_, &limb)| limb != 0)?;
+        Some(index as u64 * 64 + limb.trailing_zeros() as u64)
+    }
+
+    pub(crate) fn shift_right(&self, bits: u64) -> SecretBigInt {
+        let (limb_shift, bit_shift) = ((bits / 64) as usize, bits % 64);
+        let limbs = self.limbs.iter().enumerate().skip(limb_shift).map(|(index, &limb)| {
+            let next_limb = self.limbs.get(index + 1).copied().unwrap_or(0);
+            if bit_shift == 0 { limb } else { (limb >> bit_shift) | (next_limb << (64 - bit_shift)) }
+        }).collect();
+        SecretBigInt::from_limbs(limbs)
+    }
+
+    // self / divisor for an odd divisor which divides self
+    pub(crate) fn divide_exact(&self, divisor: &SecretBigInt) -> SecretBigInt {
+        SecretBigInt::from_limbs(constant_time::divide_exact(&self.limbs, &divisor.limbs))
+    }
+
+    // The larger of the two numbers first, without branching on which one it is
+    pub(crate) fn larger_first(a: SecretBigInt, b: SecretBigInt) -> (SecretBigInt, SecretBigInt) {
+        let length = cmp::max(a.limbs.len(), b.limbs.len());
+        let (mut a_limbs, mut b_limbs) = (Zeroizing::new(a.limbs.to_vec()), Zeroizing::new(b.limbs.to_vec()));
+        a_limbs.resize(length, 0);
+        b_limbs.resize(length, 0);
+        constant_time::larger_first(&mut a_limbs, &mut b_limbs);
+        (SecretBigInt::from_limbs(a_limbs.to_vec()), SecretBigInt::from_limbs(b_limbs.to_vec()))
+    }
+}
+
+impl From<u64> for SecretBigInt {
+    fn from(number: u64) -> SecretBigInt {
+        SecretBigInt::from_limbs(vec![number])
+    }
+}
+
+impl From<BigInt> for SecretBigInt {
+    fn from(number: BigInt) -> SecretBigInt {
+        SecretBigInt::new(number)
+    }
+}
+
+impl fmt::Debug for SecretBigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBigInt([REDACTED])")
+    }
+}
+
+// Comparison time only depends on the length of the numbers
+impl PartialEq for SecretBigInt {
+    fn eq(&self, other: &SecretBigInt) -> bool {
+        self.sign == other.sign && bool::from(self.limbs.as_slice().ct_eq(other.limbs.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_expose_the_stored_number() {
+        for number in [BigInt::from(0), BigInt::from(-404790586766519i64), (BigInt::from(1) << 1000) - 1] {
+            assert_eq!(SecretBigInt::new(number.clone()).expose(), number);
+        }
+    }
+
+    #[test]
+    fn should_convert_bytes_and_limbs() {
+        let number: BigInt = BigInt::from(404790586766519u64) << 100;
+        let secret = SecretBigInt::from_bytes_be(&number.to_bytes_be().1);
+        assert_eq!(secret.expose(), number);
+        assert_eq!(BigInt::from_bytes_be(Sign::Plus, &secret.to_bytes_be()), number);
+        assert_eq!(SecretBigInt::from_limbs(vec![0, 0]), SecretBigInt::new(BigInt::from(0)));
+    }
+
+    #[test]
+    fn should_compute_like_bigint() {
+        let a: BigInt = (BigInt::from(404790586766519u64) << 130) + 12345;
+        let b: BigInt = (BigInt::from(375946200922409u64) << 70) + 1;
+        let (secret_a, secret_b) = (SecretBigInt::new(a.clone()), SecretBigInt::new(b.clone()));
+        assert_eq!(secret_a.add(&secret_b).expose(), &a + &b);
+        assert_eq!(secret_a.subtract(&secret_b).expose(), &a - &b);
+        assert_eq!(secret_a.multiply(&secret_b).expose(), &a * &b);
+        assert_eq!(secret_a.remainder(&secret_b).expose(), &a % &b);
+        assert_eq!(secret_a.remainder(&SecretBigInt::from(1 << 20)).expose(), &a % (1 << 20));
+        assert_eq!(secret_a.multiply(&secret_b).divide_exact(&secret_b).expose(), a);
+        for bits in [0, 1, 64, 100] {
+            assert_eq!(secret_a.shift_right(bits).expose(), &a >> bits);
+        }
+        assert_eq!(secret_a.shift_right(200).trailing_zeros(), None);
+        assert_eq!(SecretBigInt::new(BigInt::from(3) << 70).trailing_zeros(), Some(70));
+        for (first, second) in [(&a, &b), (&b, &a), (&a, &a)] {
+            let (larger, smaller) = SecretBigInt::larger_first(SecretBigInt::new(first.clone()), SecretBigInt::new(second.clone()));
+            assert_eq!((larger.expose(), smaller.expose()), (first.max(second).clone(), first.min(second).clone()));
+        }
+    }
+
+    #[test]
+    fn should_not_print_secret() {
+        let secret = SecretBigInt::new(BigInt::from(404790586766519u64));
+        assert_eq!(format!("{:?}", secret), "SecretBigInt([REDACTED])");
+    }
+
+    #[test]
+    fn should_compare_secrets() {
+        let secret = SecretBigInt::new(BigInt::from(404790586766519u64));
+        assert_eq!(secret, SecretBigInt::new(BigInt::from(404790586766519u64)));
+        assert_ne!(secret, SecretBigInt::new(BigInt::from(404790586766517u64)));
+        assert_ne!(secret, SecretBigInt::new(BigInt::from(-404790586766519i64)));
+    }
+}
@@ -1,14 +1,11 @@
-use crate::crypto::{Key, KeyType};
-use crate::crypto;
-use anyhow::{Result, Error};
+use crate::crypto::RsaPublicKey;
+use crate::pkcs8::RSA_IDENTIFIER;
 use yasna::{self, models::ObjectIdentifier};
 
-const RSA_IDENTIFIER: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
-
 //Subject Public Key Info from https://www.itu.int/rec/T-REC-X.509
 pub(crate) struct SubjectPublicKeyInfo {
     pub(crate) public_key_algorithm: AlgorithmIdentifier,
-    pub(crate) public_key: crypto::Key
+    pub(crate) public_key: RsaPublicKey
 }
 
 pub(crate) struct AlgorithmIdentifier {
@@ -17,7 +14,7 @@ pub(crate) struct AlgorithmIdentifier {
 
 impl SubjectPublicKeyInfo {
 
-    pub(crate) fn wrap(key: &Key) -> SubjectPublicKeyInfo {
+    pub(crate) fn wrap(key: &RsaPublicKey) -> SubjectPublicKeyInfo {
         SubjectPublicKeyInfo {
             public_key_algorithm: AlgorithmIdentifier {
                 algorithm: RSA_IDENTIFIER.to_vec()
@@ -36,7 +33,7 @@ impl SubjectPublicKeyInfo {
                 let public_key = reader.next().read_bitvec_bytes()?.0;
                 Ok(SubjectPublicKeyInfo {
                     public_key_algorithm,
-                    public_key: Key::from_bytes(&public_key, KeyType::Public).map_err(|_| {
+                    public_key: RsaPublicKey::from_bytes(&public_key).map_err(|_| {
                         yasna::ASN1Error::new(yasna::ASN1ErrorKind::Invalid)
                     })?
                 })
@@ -0,0 +1,71 @@
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::Error;
+
+/*
+ * STREAM construction over ChaCha20-Poly1305: the data is split into segments of SEGMENT_SIZE bytes, each sealed
+ * with its own authentication tag. The nonce of a segment is its index followed by a flag marking the last segment,
+ * so that reordered, dropped or appended segments and a stream truncated at a segment boundary fail authentication.
+ * Every segment is also bound to the container header through the associated data
+ */
+pub(crate) const SEGMENT_SIZE: usize = 64 * 1024;
+pub(crate) const TAG_SIZE: usize = 16;
+
+const INDEX_SIZE: usize = 11;
+
+pub(crate) struct SegmentCipher {
+    cipher: ChaCha20Poly1305,
+    associated_data: [u8; 32]
+}
+
+impl SegmentCipher {
+
+    pub(crate) fn new(content_key: &[u8; 32], header: &[u8]) -> SegmentCipher {
+        SegmentCipher {
+            cipher: ChaCha20Poly1305::new(content_key.into()),
+            associated_data: Sha256::digest(header).into()
+        }
+    }
+
+    pub(crate) fn seal(&self, index: usize, plaintext: &[u8], is_last: bool) -> Result<Vec<u8>, Error> {
+        let payload = Payload { msg: plaintext, aad: &self.associated_data };
+        self.cipher.encrypt(&segment_nonce(index, is_last), payload)
+            .map_err(|_| Error::InvalidContainer(format!("Segment {} is too long", index)))
+    }
+
+    pub(crate) fn open(&self, index: usize, ciphertext: &[u8], is_last: bool) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let payload = Payload { msg: ciphertext, aad: &self.associated_data };
+        self.cipher.decrypt(&segment_nonce(index, is_last), payload)
+            .map(Zeroizing::new)
+            .map_err(|_| Error::Decryption(format!("Segment {} failed authentication", index)))
+    }
+}
+
+// Big-endian segment index followed by the last segment flag
+fn segment_nonce(index: usize, is_last: bool) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[..INDEX_SIZE].copy_from_slice(&(index as u128).to_be_bytes()[16 - INDEX_SIZE..]);
+    nonce[INDEX_SIZE] = is_last as u8;
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tests::get_random_bytes;
+
+    #[test]
+    fn should_only_open_segment_at_same_position() {
+        let cipher = SegmentCipher::new(&[7u8; 32], b"header");
+        let plaintext = get_random_bytes(1000);
+        let sealed = cipher.seal(3, &plaintext, false).unwrap();
+        assert_eq!(sealed.len(), plaintext.len() + TAG_SIZE);
+        assert_eq!(cipher.open(3, &sealed, false).unwrap().as_slice(), plaintext.as_slice());
+        assert!(matches!(cipher.open(4, &sealed, false), Err(Error::Decryption(_))));
+        assert!(matches!(cipher.open(3, &sealed, true), Err(Error::Decryption(_))));
+        let other_header = SegmentCipher::new(&[7u8; 32], b"other header");
+        assert!(matches!(other_header.open(3, &sealed, false), Err(Error::Decryption(_))));
+    }
+}
@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use rand::Rng;
+
+/*
+ * Empty directory under the temporary directory of the system for the files of a single test,
+ * removed together with everything in it when dropped
+ */
+pub(crate) struct TemporaryDirectory(PathBuf);
+
+impl TemporaryDirectory {
+
+    pub(crate) fn create() -> TemporaryDirectory {
+        let directory = env::temp_dir().join(format!("euler-cryptor-test-{:016x}", rand::thread_rng().gen::<u64>()));
+        fs::create_dir_all(&directory).unwrap();
+        TemporaryDirectory(directory)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}